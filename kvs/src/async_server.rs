@@ -0,0 +1,78 @@
+//! kvs-server 的异步版本，协议与 `KvsServer` 相同
+//!
+//! 所有连接由少量 tokio 线程处理，引擎操作交给 `AsyncKvStore` 的阻塞线程池，
+//! 连接数不再受线程数限制。
+
+use serde_json::Deserializer;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::protocol::{Request, Response};
+use crate::{AsyncKvStore, KvsEngine, Result};
+
+///基于 tokio 的 kvs-server
+pub struct AsyncKvsServer<E: KvsEngine> {
+    store: AsyncKvStore<E>,
+}
+
+impl<E: KvsEngine + Send + 'static> AsyncKvsServer<E> {
+    pub fn new(store: AsyncKvStore<E>) -> AsyncKvsServer<E> {
+        AsyncKvsServer { store }
+    }
+
+    ///监听地址并处理请求，不会返回，除非监听失败
+    pub async fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        self.serve(TcpListener::bind(addr).await?).await
+    }
+
+    ///使用已经绑定的监听器处理请求，必须在 tokio 运行时中调用
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let store = self.store.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(store, stream).await {
+                    eprintln!("Error on serving client: {}", e);
+                }
+            });
+        }
+    }
+}
+
+async fn handle_connection<E: KvsEngine + Send + 'static>(store: AsyncKvStore<E>, mut stream: TcpStream) -> Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        //请求之间没有分隔符，缓冲区中的数据能解析出一个完整的请求时才处理
+        let mut requests = Deserializer::from_slice(&buf).into_iter::<Request>();
+        let parsed = requests.next().map(|request| request.map(|request| (request, requests.byte_offset())));
+        let request = match parsed {
+            Some(Ok((request, consumed))) => {
+                buf.drain(..consumed);
+                request
+            }
+            Some(Err(e)) if !e.is_eof() => return Err(e.into()),
+            _ => {
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    //客户端关闭了连接
+                    return Ok(());
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                continue;
+            }
+        };
+        let response = execute(&store, request).await;
+        stream.write_all(&serde_json::to_vec(&response)?).await?;
+    }
+}
+
+async fn execute<E: KvsEngine + Send + 'static>(store: &AsyncKvStore<E>, request: Request) -> Response {
+    let result = match request {
+        Request::Get { key } => store.get(key).await.map(Response::Value),
+        Request::Set { key, value } => store.set(key, value).await.map(|_| Response::Done),
+        Request::Remove { key } => store.remove(key).await.map(|_| Response::Done),
+        Request::Scan { prefix } => store.scan(prefix).await.map(Response::Pairs),
+    };
+    result.unwrap_or_else(|e| Response::Err(e.into()))
+}
@@ -0,0 +1,115 @@
+//! KvStore 的异步接口，供 async 代码直接调用
+//!
+//! 引擎的读写都是阻塞的磁盘 IO，所以每个操作都交给专用的线程池执行，异步任务只等待结果，
+//! 不会占住执行器的线程。调用方在操作开始执行之前放弃等待（future 被丢弃，例如超时）时，
+//! 该操作不会执行；已经开始执行的操作会执行完，结果被丢弃。
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use webserver::ThreadPool;
+
+use crate::engine::with_engine;
+use crate::{KvError, KvStore, KvsEngine, Result};
+
+// 默认的阻塞线程数；引擎一次只能执行一个操作，多出来的线程只用于排队
+const DEFAULT_BLOCKING_THREADS: usize = 1;
+
+///在专用线程池中执行引擎操作的异步接口，克隆出来的实例共享引擎和线程池
+pub struct AsyncKvStore<E: KvsEngine = KvStore> {
+    engine: Arc<Mutex<E>>,
+    pool: Arc<ThreadPool>,
+}
+
+impl<E: KvsEngine> Clone for AsyncKvStore<E> {
+    fn clone(&self) -> Self {
+        AsyncKvStore {
+            engine: Arc::clone(&self.engine),
+            pool: Arc::clone(&self.pool),
+        }
+    }
+}
+
+impl AsyncKvStore<KvStore> {
+    ///在线程池中打开 KvStore，重放日志不会阻塞调用方
+    pub async fn open(path: impl Into<PathBuf>) -> Result<AsyncKvStore<KvStore>> {
+        let path = path.into();
+        let pool = Arc::new(ThreadPool::new(DEFAULT_BLOCKING_THREADS));
+        let store = run_blocking(&pool, move || KvStore::open(path)).await?;
+        Ok(AsyncKvStore {
+            engine: Arc::new(Mutex::new(store)),
+            pool,
+        })
+    }
+}
+
+impl<E: KvsEngine + Send + 'static> AsyncKvStore<E> {
+    pub fn new(engine: E) -> AsyncKvStore<E> {
+        AsyncKvStore::from_shared(Arc::new(Mutex::new(engine)))
+    }
+
+    ///与同步代码（例如复制）共享引擎
+    pub fn from_shared(engine: Arc<Mutex<E>>) -> AsyncKvStore<E> {
+        AsyncKvStore {
+            engine,
+            pool: Arc::new(ThreadPool::new(DEFAULT_BLOCKING_THREADS)),
+        }
+    }
+
+    ///阻塞线程池的大小
+    pub fn threads(mut self, threads: usize) -> AsyncKvStore<E> {
+        self.pool = Arc::new(ThreadPool::new(threads.max(1)));
+        self
+    }
+
+    pub async fn get(&self, key: String) -> Result<Option<String>> {
+        self.run(move |engine| engine.get(key)).await
+    }
+
+    pub async fn set(&self, key: String, value: String) -> Result<()> {
+        self.run(move |engine| engine.set(key, value)).await
+    }
+
+    pub async fn remove(&self, key: String) -> Result<()> {
+        self.run(move |engine| engine.remove(key)).await
+    }
+
+    pub async fn scan(&self, prefix: String) -> Result<Vec<(String, String)>> {
+        self.run(move |engine| engine.scan(&prefix)).await
+    }
+
+    ///在线程池中对引擎执行 f
+    pub async fn run<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut E) -> Result<T> + Send + 'static,
+    {
+        let engine = Arc::clone(&self.engine);
+        //在持有锁时捕获 panic，引擎的锁不会因此中毒，之后的操作照常执行
+        run_blocking(&self.pool, move || with_engine(&engine, |engine| catch_panic(|| f(engine)))).await
+    }
+}
+
+///执行 f，panic 时返回 `KvError::TaskFailed`
+fn catch_panic<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(Err(KvError::TaskFailed))
+}
+
+async fn run_blocking<T, F>(pool: &ThreadPool, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    pool.execute(move || {
+        //调用方已经放弃等待
+        if sender.is_closed() {
+            return;
+        }
+        //panic 的线程池线程会退出并且不会被重新创建，所以 panic 不能离开任务
+        let _ = sender.send(catch_panic(f));
+    });
+    //发送端没有发送就被丢弃，说明任务没有执行
+    receiver.await.map_err(|_| KvError::TaskFailed)?
+}
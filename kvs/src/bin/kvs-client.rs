@@ -0,0 +1,82 @@
+use clap::{Parser, Subcommand};
+use kvs::{KvError, KvsClient, KvsEngine, Result, ShardedClient, DEFAULT_VNODES};
+use std::net::SocketAddr;
+
+#[derive(Parser)]
+#[command(author=env!("CARGO_PKG_AUTHORS"), version=env!("CARGO_PKG_VERSION"), about="A key-value store client", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+    /// Address of the kvs-server
+    #[arg(long, global = true, default_value = "127.0.0.1:4000")]
+    addr: SocketAddr,
+    /// Spread keys over these kvs-servers with consistent hashing instead of using --addr
+    #[arg(long, global = true, value_delimiter = ',')]
+    shards: Vec<SocketAddr>,
+    /// Virtual nodes per shard on the hash ring; must match every other client
+    #[arg(long, global = true, default_value_t = DEFAULT_VNODES)]
+    vnodes: usize,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    Get { key: String },
+    Set { key: String, value: String },
+    Rm { key: String },
+    /// Print every key/value pair whose key starts with the prefix
+    Scan { prefix: String },
+    /// Add a server to --shards and move the keys it now owns onto it
+    AddShard { addr: SocketAddr },
+    /// Remove a server from --shards and move its keys to the remaining shards
+    RemoveShard { addr: SocketAddr },
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.shards.is_empty() {
+        if let Commands::AddShard { .. } | Commands::RemoveShard { .. } = cli.command {
+            eprintln!("Rebalancing needs the current shards in --shards");
+            std::process::exit(1);
+        }
+        return execute(KvsClient::connect(cli.addr)?, cli.command);
+    }
+    let mut client = ShardedClient::connect_with(cli.shards, cli.vnodes)?;
+    match cli.command {
+        Commands::AddShard { addr } => {
+            let moved = client.add_shard(addr)?;
+            println!("Moved {} keys to {}", moved, addr);
+            Ok(())
+        }
+        Commands::RemoveShard { addr } => {
+            let moved = client.remove_shard(addr)?;
+            println!("Moved {} keys off {}", moved, addr);
+            Ok(())
+        }
+        command => execute(client, command),
+    }
+}
+
+fn execute(mut client: impl KvsEngine, command: Commands) -> Result<()> {
+    match command {
+        Commands::Get { key } => match client.get(key)? {
+            Some(value) => println!("{}", value),
+            None => println!("Key not found"),
+        },
+        Commands::Set { key, value } => client.set(key, value)?,
+        Commands::Rm { key } => match client.remove(key) {
+            Ok(()) => {}
+            Err(KvError::KeyNotFound) => {
+                eprintln!("Key not found");
+                std::process::exit(1);
+            }
+            Err(e) => return Err(e),
+        },
+        Commands::Scan { prefix } => {
+            for (key, value) in client.scan(&prefix)? {
+                println!("{} {}", key, value);
+            }
+        }
+        Commands::AddShard { .. } | Commands::RemoveShard { .. } => unreachable!(),
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+use clap::{Parser, ValueEnum};
+use kvs::{
+    AsyncKvStore, AsyncKvsServer, Follower, HttpServer, KvStore, KvsEngine, KvsServer, LsmStore, RaftConfig, RaftNode, ReplicationLeader, RespServer, Result, StoreOptions,
+};
+use std::env::current_dir;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(author=env!("CARGO_PKG_AUTHORS"), version=env!("CARGO_PKG_VERSION"), about="A key-value store server", long_about = None)]
+struct Cli {
+    /// Address to serve clients on
+    #[arg(long, default_value = "127.0.0.1:4000")]
+    addr: SocketAddr,
+    /// Storage engine used for the store in the current directory
+    #[arg(long, value_enum, default_value_t = Engine::Kvs)]
+    engine: Engine,
+    /// Wire protocol spoken to clients: kvs-client's JSON protocol, Redis RESP2 or an HTTP REST API
+    #[arg(long, value_enum, default_value_t = Protocol::Kvs)]
+    protocol: Protocol,
+    /// Act as a replication leader and accept followers on this address (kvs engine only)
+    #[arg(long, conflicts_with = "follow")]
+    replication_addr: Option<SocketAddr>,
+    /// Act as a read-only follower of the leader replicating on this address (kvs engine only)
+    #[arg(long)]
+    follow: Option<SocketAddr>,
+    /// Run as this member of a Raft cluster (kvs engine only)
+    #[arg(long, requires = "peer", conflicts_with_all = ["replication_addr", "follow"])]
+    raft_id: Option<u64>,
+    /// A cluster member, including this node, as ID=RAFT_ADDR,CLIENT_ADDR (repeatable)
+    #[arg(long, value_parser = parse_peer)]
+    peer: Vec<(u64, SocketAddr, SocketAddr)>,
+    /// Handle all connections on a few async threads instead of one thread per connection (kvs protocol only)
+    #[arg(long = "async")]
+    async_io: bool,
+    /// Number of async threads used with --async
+    #[arg(long, default_value_t = 2, requires = "async_io")]
+    threads: usize,
+    /// Cache up to this many bytes of recently read values in memory (kvs engine only)
+    #[arg(long)]
+    value_cache: Option<u64>,
+}
+
+fn parse_peer(s: &str) -> std::result::Result<(u64, SocketAddr, SocketAddr), String> {
+    let format = || format!("invalid peer {:?}, expected ID=RAFT_ADDR,CLIENT_ADDR", s);
+    let (id, addrs) = s.split_once('=').ok_or_else(format)?;
+    let (raft_addr, client_addr) = addrs.split_once(',').ok_or_else(format)?;
+    Ok((
+        id.parse().map_err(|_| format())?,
+        raft_addr.parse().map_err(|_| format())?,
+        client_addr.parse().map_err(|_| format())?,
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Protocol {
+    Kvs,
+    Resp,
+    Http,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Engine {
+    Kvs,
+    Lsm,
+    #[cfg(feature = "sled")]
+    Sled,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let path = current_dir()?;
+    let replicated = cli.replication_addr.is_some() || cli.follow.is_some() || cli.raft_id.is_some();
+    if replicated && cli.engine != Engine::Kvs {
+        eprintln!("Replication is only supported by the kvs engine");
+        std::process::exit(1);
+    }
+    if cli.async_io && cli.protocol != Protocol::Kvs {
+        eprintln!("--async is only supported by the kvs protocol");
+        std::process::exit(1);
+    }
+    eprintln!("kvs-server {} listening on {}", env!("CARGO_PKG_VERSION"), cli.addr);
+
+    if let Some(id) = cli.raft_id {
+        let config = cli
+            .peer
+            .iter()
+            .fold(RaftConfig::new(id), |config, &(id, raft_addr, client_addr)| {
+                config.peer(id, raft_addr, client_addr)
+            });
+        let node = RaftNode::start(path, config)?;
+        eprintln!("Raft node {} started", id);
+        return serve(&cli, Arc::new(Mutex::new(node)));
+    }
+
+    if let Some(leader) = cli.follow {
+        let follower = Follower::start(path, leader)?;
+        report_lag(&follower);
+        return serve(&cli, Arc::new(Mutex::new(follower)));
+    }
+    match cli.engine {
+        Engine::Kvs => {
+            let mut options = StoreOptions::new();
+            if let Some(bytes) = cli.value_cache {
+                options = options.value_cache(bytes);
+            }
+            let store = Arc::new(Mutex::new(KvStore::open_with(path, options)?));
+            //复制的主节点丢弃时停止，需要和服务一样一直运行
+            let _leader = match cli.replication_addr {
+                Some(replication_addr) => {
+                    let leader = ReplicationLeader::start(Arc::clone(&store), replication_addr)?;
+                    eprintln!("Accepting followers on {}", leader.local_addr());
+                    Some(leader)
+                }
+                None => None,
+            };
+            serve(&cli, store)
+        }
+        Engine::Lsm => serve(&cli, Arc::new(Mutex::new(LsmStore::open(path)?))),
+        #[cfg(feature = "sled")]
+        Engine::Sled => serve(&cli, Arc::new(Mutex::new(kvs::SledKvsEngine::open(path)?))),
+    }
+}
+
+fn serve<E: KvsEngine + Send + 'static>(cli: &Cli, engine: Arc<Mutex<E>>) -> Result<()> {
+    match cli.protocol {
+        Protocol::Kvs if cli.async_io => {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(cli.threads.max(1))
+                .enable_io()
+                .build()?;
+            runtime.block_on(AsyncKvsServer::new(AsyncKvStore::from_shared(engine)).run(cli.addr))
+        }
+        Protocol::Kvs => KvsServer::from_shared(engine).run(cli.addr),
+        Protocol::Resp => RespServer::from_shared(engine).run(cli.addr),
+        Protocol::Http => HttpServer::from_shared(engine).run(cli.addr),
+    }
+}
+
+///定期打印从节点的复制延迟
+fn report_lag(follower: &Follower) {
+    let status = follower.status_handle();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(5));
+        let status = status.lock().unwrap().clone();
+        match (status.connected, status.lag_bytes()) {
+            (false, _) => eprintln!("Replication: disconnected from leader"),
+            (true, Some(lag)) => eprintln!("Replication: applied {:?}, {} bytes behind", status.applied, lag),
+            (true, None) => eprintln!("Replication: applied {:?}, leader at {:?}", status.applied, status.leader),
+        }
+    });
+}
@@ -1,6 +1,17 @@
-use clap::{Parser, Subcommand};
-use kvs::{KvStore, Result, KvError};
+use clap::{Parser, Subcommand, ValueEnum};
+use kvs::{
+    Change, EncryptionKey, FamilyOptions, Format, KvStore, KvsEngine, LogPosition, LsmStore, Result, KvError, Shell, ShellReply,
+    StoreOptions, DEFAULT_FAMILY,
+};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use serde::Serialize;
 use std::env::current_dir;
+use std::fmt::Display;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(author=env!("CARGO_PKG_AUTHORS"), version=env!("CARGO_PKG_VERSION"), about=env!("CARGO_PKG_DESCRIPTION"), long_about = None)]
@@ -8,6 +19,80 @@ use std::env::current_dir;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    /// Encrypt log records with the key in this file (32 raw bytes or 64 hex characters)
+    #[arg(long, global = true)]
+    key_file: Option<PathBuf>,
+    /// Previous key used to read logs written before a key rotation
+    #[arg(long, global = true)]
+    old_key_file: Vec<PathBuf>,
+    /// Keep only key fingerprints in memory once the index grows past this many bytes (kvs engine only)
+    #[arg(long, global = true)]
+    index_memory_budget: Option<u64>,
+    /// Keep versions overwritten or removed within this many seconds when compacting (kvs engine only)
+    #[arg(long, global = true, value_name = "SECS")]
+    history_retention: Option<u64>,
+    /// Storage engine used for the store in the current directory
+    #[arg(long, global = true, value_enum, default_value_t = Engine::Kvs)]
+    engine: Engine,
+    /// Column family to read and write (kvs engine only)
+    #[arg(long, global = true, default_value = DEFAULT_FAMILY)]
+    cf: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Engine {
+    /// Log-structured hash table (the default)
+    Kvs,
+    /// LSM-tree, for key sets larger than memory
+    Lsm,
+    /// sled embedded database
+    #[cfg(feature = "sled")]
+    Sled,
+}
+
+impl Cli {
+    fn open_store(&self) -> Result<Box<dyn KvsEngine>> {
+        if self.cf != DEFAULT_FAMILY {
+            kvs_engine_only(self);
+        }
+        match self.engine {
+            Engine::Kvs => {}
+            Engine::Lsm => return Ok(Box::new(LsmStore::open(current_dir()?)?)),
+            #[cfg(feature = "sled")]
+            Engine::Sled => return Ok(Box::new(kvs::SledKvsEngine::open(current_dir()?)?)),
+        }
+        Ok(Box::new(self.open_family()?))
+    }
+
+    ///打开 KvStore 中 --cf 指定的列族
+    fn open_family(&self) -> Result<FamilyStore> {
+        let store = KvStore::open_with(current_dir()?, self.store_options()?)?;
+        if !store.families().iter().any(|(name, _)| *name == self.cf) {
+            eprintln!("Column family {} does not exist", self.cf);
+            std::process::exit(1);
+        }
+        Ok(FamilyStore {
+            store,
+            family: self.cf.clone(),
+        })
+    }
+
+    fn store_options(&self) -> Result<StoreOptions> {
+        let mut options = StoreOptions::new();
+        if let Some(key_file) = &self.key_file {
+            options = options.encryption_key(EncryptionKey::from_file(key_file)?);
+        }
+        for key_file in &self.old_key_file {
+            options = options.previous_key(EncryptionKey::from_file(key_file)?);
+        }
+        if let Some(budget) = self.index_memory_budget {
+            options = options.index_memory_budget(budget);
+        }
+        if let Some(secs) = self.history_retention {
+            options = options.history_retention(Duration::from_secs(secs));
+        }
+        Ok(options)
+    }
 }
 
 #[derive(Subcommand)]
@@ -21,15 +106,307 @@ enum Commands {
         value1: String,
     },
     Rm {
-        key1: String,
+        #[arg(required_unless_present = "prefix", conflicts_with = "prefix")]
+        key1: Option<String>,
+        /// Remove every key starting with this prefix with a single range tombstone (kvs engine only)
+        #[arg(long)]
+        prefix: Option<String>,
     },
+    /// Print the values of several keys, one per line (kvs engine only)
+    Mget {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// Add to the integer value of a key (starting from 0) and print the result (kvs engine only)
+    Incr {
+        key: String,
+        #[arg(default_value_t = 1, allow_negative_numbers = true)]
+        by: i64,
+    },
+    /// Print changes to keys starting with a prefix as they are written (kvs engine only)
+    Watch {
+        prefix: String,
+        /// Resume after this log position (`<gen>:<offset>`, as printed by a previous watch)
+        #[arg(long)]
+        from: Option<LogPosition>,
+        /// Print the changes already in the log and exit instead of following new writes
+        #[arg(long)]
+        once: bool,
+    },
+    /// Check every log file for malformed records and records failing their CRC32 checksum or authentication;
+    /// plain JSON logs carry no checksum and are only checked for format errors. Exits with 1 when damage is
+    /// found (kvs engine only)
+    Verify {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rewrite every readable record into a fresh log, moving the old logs to corrupt/ (kvs engine only)
+    Repair {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rewrite every log file in another record format through a compaction (kvs engine only)
+    Migrate {
+        /// Record format: json, bincode or msgpack
+        #[arg(long)]
+        format: Format,
+    },
+    /// Open the store once and read commands interactively (get, set, rm, scan, stats)
+    Shell {
+        /// Execute commands from stdin without a prompt, stopping at the first error
+        #[arg(long)]
+        script: bool,
+    },
+    /// Manage column families (kvs engine only)
+    Cf {
+        #[command(subcommand)]
+        command: FamilyCommand,
+    },
+    /// Manage secondary indexes on fields of JSON values (kvs engine only)
+    Index {
+        #[command(subcommand)]
+        command: IndexCommand,
+    },
+    /// List the versions of a key still in the log, oldest first (kvs engine only)
+    History {
+        key: String,
+        /// Print the versions as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show key count, disk usage, compaction history and operation counters (kvs engine only)
+    Stats {
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+///KvStore 的一个列族
+struct FamilyStore {
+    store: KvStore,
+    family: String,
+}
+
+impl KvsEngine for FamilyStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.store.set_cf(&self.family, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.store.get_cf(&self.family, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.store.remove_cf(&self.family, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.store.scan_cf(&self.family, prefix)
+    }
+}
+
+#[derive(Subcommand)]
+enum FamilyCommand {
+    /// Create a column family
+    Create {
+        name: String,
+        /// Values written to the family expire after this many seconds
+        #[arg(long)]
+        ttl: Option<u64>,
+        /// Compact once this many bytes of the family's records are stale
+        #[arg(long)]
+        compaction_threshold: Option<u64>,
+    },
+    /// Print every column family with its key count
+    List,
+    /// Delete a column family and all of its keys
+    Drop { name: String },
+}
+
+fn family_command(store: &mut KvStore, command: &FamilyCommand) -> Result<()> {
+    match command {
+        FamilyCommand::Create {
+            name,
+            ttl,
+            compaction_threshold,
+        } => {
+            let mut options = FamilyOptions::new();
+            if let Some(ttl) = ttl {
+                options = options.ttl(Duration::from_secs(*ttl));
+            }
+            if let Some(bytes) = compaction_threshold {
+                options = options.compaction_threshold(*bytes);
+            }
+            store.create_family(name, options)?;
+        }
+        FamilyCommand::List => {
+            for family in store.stats()?.families {
+                match family.options.ttl_ms {
+                    Some(ttl) => println!("{} {} keys, ttl {}s", family.name, family.live_keys, ttl / 1000),
+                    None => println!("{} {} keys", family.name, family.live_keys),
+                }
+            }
+        }
+        FamilyCommand::Drop { name } => store.drop_family(name)?,
+    }
+    Ok(())
+}
+
+#[derive(Subcommand)]
+enum IndexCommand {
+    /// Index the field at a dot-separated path, e.g. `address.city`
+    Create { name: String, path: String },
+    /// Print every index as `<name> <path>`
+    List,
+    Drop { name: String },
+    /// Print the keys whose indexed field equals a value
+    Find { name: String, value: String },
+}
+
+fn index_command(store: &mut KvStore, command: &IndexCommand) -> Result<()> {
+    match command {
+        IndexCommand::Create { name, path } => store.create_index(name, path)?,
+        IndexCommand::List => {
+            for def in store.indexes() {
+                println!("{} {}", def.name, def.path);
+            }
+        }
+        IndexCommand::Drop { name } => store.drop_index(name)?,
+        IndexCommand::Find { name, value } => {
+            for key in store.find_by(name, value)? {
+                println!("{}", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+///打印变更，跟随模式下轮询日志目录等待新的写入
+fn watch(cli: &Cli, prefix: &str, mut from: Option<LogPosition>, once: bool) -> Result<()> {
+    let path = current_dir()?;
+    let options = cli.store_options()?;
+    loop {
+        let events = match KvStore::read_family_changes(&path, &options, &cli.cf, prefix, from) {
+            Ok(events) => events,
+            Err(KvError::PositionCompacted { gen }) => {
+                eprintln!("Log generation {} has been compacted, restart the watch without --from", gen);
+                std::process::exit(1);
+            }
+            Err(e) => return Err(e),
+        };
+        for event in events {
+            match event.change {
+                Change::Set { key, value, .. } => println!("{} set {} {}", event.position, key, value),
+                Change::Remove { key } => println!("{} rm {}", event.position, key),
+                Change::Expire { key } => println!("{} expire {}", event.position, key),
+                Change::Merge { key, operator, operand } => {
+                    println!("{} merge {} {} {}", event.position, key, operator, operand)
+                }
+                Change::RemoveRange { start, end } => {
+                    println!("{} rm {}..{}", event.position, start, end.unwrap_or_default())
+                }
+                Change::DropFamily => println!("{} drop {}", event.position, event.family),
+                Change::CreateFamily { .. } => println!("{} create {}", event.position, event.family),
+            }
+            from = Some(event.position);
+        }
+        if once {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(500));
+    }
 }
+
+///检查、修复和统计只支持 kvs 引擎的数据目录
+fn kvs_engine_only(cli: &Cli) {
+    if cli.engine != Engine::Kvs {
+        eprintln!("This command only supports the kvs engine");
+        std::process::exit(1);
+    }
+}
+
+///作用于整个数据目录的命令不接受 --cf
+fn whole_store(cli: &Cli) {
+    kvs_engine_only(cli);
+    if cli.cf != DEFAULT_FAMILY {
+        eprintln!("This command applies to the whole store and does not take --cf");
+        std::process::exit(1);
+    }
+}
+
+fn print_report<T: Serialize + Display>(report: &T, json: bool) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    } else {
+        println!("{}", report);
+    }
+    Ok(())
+}
+
+fn run_shell<E: KvsEngine>(mut shell: Shell<E>, script: bool) -> Result<()> {
+    if script {
+        if let Err(e) = shell.run_script(io::stdin().lock(), io::stdout().lock()) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".kvs_history"));
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    if let Some(history) = &history {
+        //第一次使用时历史文件还不存在
+        let _ = editor.load_history(history);
+    }
+    loop {
+        match editor.readline("kvs> ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                match shell.execute(&line) {
+                    Ok(ShellReply::Output(text)) => {
+                        if !text.is_empty() {
+                            println!("{}", text);
+                        }
+                    }
+                    Ok(ShellReply::Exit) => break,
+                    Err(e) => eprintln!("error: {}", e),
+                }
+            }
+            //Ctrl-C 只放弃当前输入的行
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(readline_error(e)),
+        }
+    }
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Failed to save shell history: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn readline_error(error: ReadlineError) -> KvError {
+    match error {
+        ReadlineError::Io(e) => KvError::IoError(e),
+        e => KvError::IoError(io::Error::other(e.to_string())),
+    }
+}
+
 fn main() -> Result<()> {
     let cli = Cli::parse();
-    let path = current_dir()?;
     match &cli.command {
-        Some(Commands::Rm { key1 }) => {
-            let mut kv = KvStore::open(path)?;
+        Some(Commands::Rm { key1: None, prefix: Some(prefix) }) => {
+            kvs_engine_only(&cli);
+            let mut family = cli.open_family()?;
+            let removed = family.store.remove_prefix_cf(&family.family, prefix)?;
+            println!("Removed {} keys", removed);
+        }
+        Some(Commands::Rm { key1: Some(key1), .. }) => {
+            let mut kv = cli.open_store()?;
             match kv.remove(key1.to_string()){
                 Ok(()) => {},
                 Err(KvError::KeyNotFound) => {
@@ -42,7 +419,7 @@ fn main() -> Result<()> {
             };
         }
         Some(Commands::Get { key1 }) => {
-            let mut kv = KvStore::open(path)?;
+            let mut kv = cli.open_store()?;
             if let Some(value) = kv.get(key1.to_string())? {
                 println!("{}", value);
             } else {
@@ -50,10 +427,104 @@ fn main() -> Result<()> {
             }
     
         }
+        Some(Commands::Rm { .. }) => unreachable!("clap requires a key or --prefix"),
+        Some(Commands::Mget { keys }) => {
+            kvs_engine_only(&cli);
+            let mut family = cli.open_family()?;
+            for value in family.store.multi_get_cf(&family.family, keys)? {
+                println!("{}", value.as_deref().unwrap_or("Key not found"));
+            }
+        }
         Some(Commands::Set { key1, value1 }) => {
-            let mut kv = KvStore::open(path)?;
+            let mut kv = cli.open_store()?;
             kv.set(key1.to_string(), value1.to_string())?;
         }
+        Some(Commands::Incr { key, by }) => {
+            kvs_engine_only(&cli);
+            let mut family = cli.open_family()?;
+            match family.store.incr_cf(&family.family, key.to_string(), *by) {
+                Ok(()) => println!("{}", family.get(key.to_string())?.unwrap_or_default()),
+                Err(e @ KvError::MergeFailed(_)) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Some(Commands::Watch { prefix, from, once }) => {
+            kvs_engine_only(&cli);
+            watch(&cli, prefix, *from, *once)?;
+        }
+        Some(Commands::Verify { json }) => {
+            whole_store(&cli);
+            let report = KvStore::verify(current_dir()?, &cli.store_options()?)?;
+            print_report(&report, *json)?;
+            if !report.is_clean() {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Repair { json }) => {
+            whole_store(&cli);
+            let report = KvStore::repair(current_dir()?, &cli.store_options()?)?;
+            print_report(&report, *json)?;
+        }
+        Some(Commands::Migrate { format }) => {
+            whole_store(&cli);
+            let options = cli.store_options()?.record_format(*format);
+            let mut store = KvStore::open_with(current_dir()?, options)?;
+            store.compaction()?;
+            println!("Migrated store to {}", format);
+        }
+        Some(Commands::Shell { script }) => {
+            if cli.engine == Engine::Kvs {
+                let store = cli.open_family()?;
+                run_shell(Shell::new(store).stats(|store| store.store.stats()), *script)?;
+            } else {
+                run_shell(Shell::new(cli.open_store()?), *script)?;
+            }
+        }
+        Some(Commands::Cf { command }) => {
+            whole_store(&cli);
+            let mut store = KvStore::open_with(current_dir()?, cli.store_options()?)?;
+            match family_command(&mut store, command) {
+                Err(e @ (KvError::FamilyNotFound(_) | KvError::FamilyExists(_) | KvError::DropDefaultFamily)) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+                result => result?,
+            }
+        }
+        Some(Commands::Index { command }) => {
+            whole_store(&cli);
+            let mut store = KvStore::open_with(current_dir()?, cli.store_options()?)?;
+            match index_command(&mut store, command) {
+                Err(e @ (KvError::IndexNotFound(_) | KvError::IndexExists(_) | KvError::InvalidIndexPath(_))) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+                result => result?,
+            }
+        }
+        Some(Commands::History { key, json }) => {
+            kvs_engine_only(&cli);
+            let family = cli.open_family()?;
+            let versions = family.store.history_cf(&family.family, key)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&versions)?);
+                return Ok(());
+            }
+            for version in versions {
+                match version.value {
+                    Some(value) => println!("{} {} {}", version.seq, version.timestamp, value),
+                    None => println!("{} {} (removed)", version.seq, version.timestamp),
+                }
+            }
+        }
+        Some(Commands::Stats { json }) => {
+            whole_store(&cli);
+            let stats = KvStore::open_with(current_dir()?, cli.store_options()?)?.stats()?;
+            print_report(&stats, *json)?;
+        }
         None => {
             unreachable!()
         }
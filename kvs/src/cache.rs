@@ -0,0 +1,118 @@
+//! KvStore 的热点值缓存
+//!
+//! 按键缓存解码后的值，容量按字节计算，超出时淘汰最久没有访问的条目（LRU）。
+//! 缓存以键而不是日志位置为索引，compaction 移动记录不影响缓存的值；
+//! `set` 和 `remove` 写入日志后删除对应的条目。
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::keydir::in_range;
+
+// 估算每个条目在两个容器中的额外开销
+const ENTRY_OVERHEAD: u64 = 64;
+
+///值缓存的使用情况，计数从打开 KvStore 开始
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    ///缓存条目的估算大小
+    pub bytes: u64,
+    pub capacity_bytes: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: String,
+    ///最近一次访问的序号，也是 lru 中的键
+    tick: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct ValueCache {
+    entries: HashMap<String, Entry>,
+    ///访问序号到键，序号最小的最久没有访问
+    lru: BTreeMap<u64, String>,
+    next_tick: u64,
+    bytes: u64,
+    capacity: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl ValueCache {
+    pub(crate) fn new(capacity: u64) -> ValueCache {
+        ValueCache {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            next_tick: 0,
+            bytes: 0,
+            capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn get(&mut self, key: &str) -> Option<String> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        let key = self.lru.remove(&entry.tick).unwrap();
+        entry.tick = self.next_tick;
+        self.next_tick += 1;
+        let value = entry.value.clone();
+        self.lru.insert(entry.tick, key);
+        Some(value)
+    }
+
+    ///放入或替换键的值，比整个缓存还大的值不缓存
+    pub(crate) fn insert(&mut self, key: String, value: String) {
+        self.remove(&key);
+        let size = entry_size(&key, &value);
+        if size > self.capacity {
+            return;
+        }
+        while self.bytes + size > self.capacity {
+            let (_, oldest) = self.lru.pop_first().unwrap();
+            self.remove(&oldest);
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.bytes += size;
+        self.lru.insert(tick, key.clone());
+        self.entries.insert(key, Entry { value, tick });
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.tick);
+            self.bytes -= entry_size(key, &entry.value);
+        }
+    }
+
+    ///删除 [start, end) 中的所有键
+    pub(crate) fn remove_range(&mut self, start: &str, end: Option<&str>) {
+        let keys: Vec<String> = self.entries.keys().filter(|key| in_range(key, start, end)).cloned().collect();
+        for key in keys {
+            self.remove(&key);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len() as u64,
+            bytes: self.bytes,
+            capacity_bytes: self.capacity,
+        }
+    }
+}
+
+fn entry_size(key: &str, value: &str) -> u64 {
+    (key.len() + value.len()) as u64 + ENTRY_OVERHEAD
+}
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::protocol::{RemoteError, Request, Response};
+use crate::{KvError, KvsEngine, Result};
+
+// 连接到 Raft 集群的非主节点时，最多重定向（或等待选举）的次数
+const MAX_REDIRECTS: usize = 5;
+const ELECTION_WAIT: Duration = Duration::from_millis(200);
+
+///kvs-server 的客户端，实现了 KvsEngine，可以像本地引擎一样使用
+pub struct KvsClient {
+    reader: serde_json::Deserializer<serde_json::de::IoRead<BufReader<TcpStream>>>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl KvsClient {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<KvsClient> {
+        let stream = TcpStream::connect(addr)?;
+        Ok(KvsClient {
+            reader: serde_json::Deserializer::from_reader(BufReader::new(stream.try_clone()?)),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    ///非主节点返回主节点地址时重新连接到主节点；还没有选出主节点时稍后重试
+    ///
+    ///重试不是幂等的：主节点在写入提交之前失去身份时返回 `NotLeader`，但这条写入之后仍可能被新的主节点提交。
+    ///`set` 重复执行结果相同；`remove` 的第一次请求如果已经生效，重试会返回 `KeyNotFound`，
+    ///调用方不能据此认为键原本就不存在
+    fn call(&mut self, request: &Request) -> Result<Response> {
+        for _ in 0..MAX_REDIRECTS {
+            serde_json::to_writer(&mut self.writer, request)?;
+            self.writer.flush()?;
+            match Response::deserialize(&mut self.reader)? {
+                Response::Err(RemoteError::NotLeader(Some(leader))) => *self = KvsClient::connect(leader)?,
+                Response::Err(RemoteError::NotLeader(None)) => thread::sleep(ELECTION_WAIT),
+                Response::Err(e) => return Err(e.into()),
+                response => return Ok(response),
+            }
+        }
+        Err(KvError::NotLeader { leader: None })
+    }
+}
+
+impl KvsEngine for KvsClient {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        match self.call(&Request::Set { key, value })? {
+            Response::Done => Ok(()),
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        match self.call(&Request::Get { key })? {
+            Response::Value(value) => Ok(value),
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+
+    ///连接 Raft 集群时，重试的删除可能因为第一次请求已经生效而返回 `KeyNotFound`，见 `call`
+    fn remove(&mut self, key: String) -> Result<()> {
+        match self.call(&Request::Remove { key })? {
+            Response::Done => Ok(()),
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        match self.call(&Request::Scan {
+            prefix: prefix.to_owned(),
+        })? {
+            Response::Pairs(pairs) => Ok(pairs),
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+}
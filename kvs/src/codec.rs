@@ -0,0 +1,197 @@
+//! 日志记录的编码格式
+//!
+//! 每个日志文件的头部记录了其中记录的格式，不同格式的日志可以混在同一个目录中，读取时按各自的格式解码。
+//! 没有加密的 JSON 日志是连续的 JSON 流，和以前的日志一样；其它格式和加密的记录前面有 4 字节的长度和
+//! 4 字节的 CRC32 校验和。
+//! bincode 不是自描述的格式，不能省略字段，编码时通过 `CommendDef` 写出所有字段。
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{BlobPos, Commend, EncryptionKey, FamilyOptions, KvError, Result};
+
+///日志记录的编码格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    Bincode,
+    MessagePack,
+}
+
+impl Format {
+    ///写在日志头部的格式编号
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Format::Json => 0,
+            Format::Bincode => 1,
+            Format::MessagePack => 2,
+        }
+    }
+
+    pub(crate) fn from_id(id: u8) -> Option<Format> {
+        [Format::Json, Format::Bincode, Format::MessagePack]
+            .into_iter()
+            .find(|format| format.id() == id)
+    }
+
+    fn encode(self, cmd: &Commend) -> Result<Vec<u8>> {
+        match self {
+            Format::Json => Ok(serde_json::to_vec(cmd)?),
+            Format::Bincode => {
+                let mut buf = Vec::new();
+                CommendDef::serialize(cmd, &mut bincode::Serializer::new(&mut buf, bincode::DefaultOptions::new()))
+                    .map_err(|e| KvError::Corruption(format!("bincode: {}", e)))?;
+                Ok(buf)
+            }
+            //按字段名编码，省略的字段解码时取默认值
+            Format::MessagePack => {
+                rmp_serde::to_vec_named(cmd).map_err(|e| KvError::Corruption(format!("msgpack: {}", e)))
+            }
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: &[u8]) -> Result<Commend> {
+        match self {
+            Format::Json => Ok(serde_json::from_slice(bytes)?),
+            Format::Bincode => {
+                let mut deserializer = bincode::Deserializer::from_slice(bytes, bincode::DefaultOptions::new());
+                CommendDef::deserialize(&mut deserializer).map_err(|e| KvError::Corruption(format!("bincode: {}", e)))
+            }
+            Format::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(|e| KvError::Corruption(format!("msgpack: {}", e)))
+            }
+        }
+    }
+}
+
+// 格式为 CLI 中使用的名字
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Json => "json",
+            Format::Bincode => "bincode",
+            Format::MessagePack => "msgpack",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "bincode" => Ok(Format::Bincode),
+            "msgpack" | "messagepack" => Ok(Format::MessagePack),
+            _ => Err(format!("unknown format {}, expected json, bincode or msgpack", s)),
+        }
+    }
+}
+
+///一个日志文件中记录的编码方式：格式和密钥
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Codec<'a> {
+    pub(crate) format: Format,
+    pub(crate) key: Option<&'a EncryptionKey>,
+    ///日志代号，加密记录的认证绑定了它
+    pub(crate) gen: u64,
+}
+
+impl Codec<'_> {
+
+    ///记录前面是否有长度，只有没有加密的 JSON 日志是连续的 JSON 流
+    pub(crate) fn framed(&self) -> bool {
+        self.format != Format::Json || self.key.is_some()
+    }
+
+    ///编码写在 offset 处的一条记录，不包括前面的长度
+    pub(crate) fn encode(&self, cmd: &Commend, offset: u64) -> Result<Vec<u8>> {
+        let payload = self.format.encode(cmd)?;
+        match self.key {
+            Some(key) => key.seal(&payload, &self.aad(key, offset)),
+            None => Ok(payload),
+        }
+    }
+
+    ///解码 offset 处的记录体，offset 是记录（包括长度）在日志文件中的位置
+    pub(crate) fn decode(&self, body: &[u8], offset: u64) -> Result<Commend> {
+        match self.key {
+            Some(key) => self.format.decode(&key.open(body, &self.aad(key, offset))?),
+            None => self.format.decode(body),
+        }
+    }
+
+    ///解密 offset 处的记录体，没有加密时原样返回
+    pub(crate) fn open(&self, body: &[u8], offset: u64) -> Result<Vec<u8>> {
+        match self.key {
+            Some(key) => key.open(body, &self.aad(key, offset)),
+            None => Ok(body.to_vec()),
+        }
+    }
+
+    fn aad(&self, key: &EncryptionKey, offset: u64) -> Vec<u8> {
+        key.bind(b"log", self.gen, offset)
+    }
+}
+
+// 写出所有字段的 Commend，变体的顺序就是 bincode 中的编号，只能在末尾添加
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Commend")]
+enum CommendDef {
+    Set {
+        key: String,
+        value: String,
+        cf: Option<String>,
+        expires: Option<u64>,
+        seq: u64,
+        ts: u64,
+    },
+    SetBlob {
+        key: String,
+        blob: BlobPos,
+        cf: Option<String>,
+        expires: Option<u64>,
+        seq: u64,
+        ts: u64,
+    },
+    Remove {
+        key: String,
+        cf: Option<String>,
+        seq: u64,
+        ts: u64,
+    },
+    Merge {
+        key: String,
+        op: String,
+        operand: String,
+        cf: Option<String>,
+        seq: u64,
+        ts: u64,
+    },
+    RemoveRange {
+        start: String,
+        end: Option<String>,
+        cf: Option<String>,
+        seq: u64,
+        ts: u64,
+    },
+    Batch {
+        len: usize,
+    },
+    DropFamily {
+        cf: String,
+    },
+    Expire {
+        key: String,
+        cf: Option<String>,
+        seq: u64,
+        ts: u64,
+    },
+    CreateFamily {
+        cf: String,
+        options: FamilyOptions,
+    },
+}
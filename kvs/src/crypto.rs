@@ -0,0 +1,106 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::{KvError, Result};
+
+/// 密钥长度（字节）
+pub const KEY_LEN: usize = 32;
+/// 每条记录随机生成的 nonce 长度（字节）
+const NONCE_LEN: usize = 12;
+
+///日志记录的加密密钥（ChaCha20-Poly1305）
+///
+///`id` 由密钥内容派生，写在加密日志文件的头部，用于在打开时找到对应的密钥
+///
+///每条记录的认证绑定了密钥标识、所在的文件和偏移，调换到别的文件或偏移、重放的记录都无法解密；
+///把整个文件换成它的旧版本仍然不能发现
+#[derive(Clone)]
+pub struct EncryptionKey {
+    id: u64,
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptionKey {
+    ///通过 32 字节的原始密钥构造
+    pub fn new(bytes: [u8; KEY_LEN]) -> EncryptionKey {
+        let digest = Sha256::new()
+            .chain_update(b"kvs-key-id")
+            .chain_update(bytes)
+            .finalize();
+        let mut id = [0u8; 8];
+        id.copy_from_slice(&digest[..8]);
+        EncryptionKey {
+            id: u64::from_le_bytes(id),
+            cipher: ChaCha20Poly1305::new(&bytes.into()),
+        }
+    }
+
+    ///从密钥文件读取：文件内容可以是 32 字节原始密钥，也可以是 64 个十六进制字符
+    pub fn from_file(path: impl AsRef<Path>) -> Result<EncryptionKey> {
+        let content = fs::read(path)?;
+        if content.len() == KEY_LEN {
+            let mut bytes = [0u8; KEY_LEN];
+            bytes.copy_from_slice(&content);
+            return Ok(EncryptionKey::new(bytes));
+        }
+        let text = String::from_utf8(content).map_err(|_| KvError::InvalidKey)?;
+        EncryptionKey::from_hex(text.trim())
+    }
+
+    ///从 64 个十六进制字符构造
+    pub fn from_hex(text: &str) -> Result<EncryptionKey> {
+        let mut bytes = [0u8; KEY_LEN];
+        hex::decode_to_slice(text, &mut bytes).map_err(|_| KvError::InvalidKey)?;
+        Ok(EncryptionKey::new(bytes))
+    }
+
+    ///密钥标识，写入日志文件头部
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    ///记录的认证附加数据：密钥标识、记录所在的文件和偏移，domain 区分主日志和值日志
+    pub(crate) fn bind(&self, domain: &[u8], file: u64, offset: u64) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(domain.len() + 24);
+        aad.extend_from_slice(domain);
+        aad.extend_from_slice(&self.id.to_le_bytes());
+        aad.extend_from_slice(&file.to_le_bytes());
+        aad.extend_from_slice(&offset.to_le_bytes());
+        aad
+    }
+
+    ///加密一条记录，返回 nonce + 密文（含认证标签），aad 只参与认证不写入结果
+    pub(crate) fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|_| KvError::EncryptionFailed)?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    ///解密 `seal` 的输出，认证失败说明数据损坏、密钥不对或者记录不在原来的位置
+    pub(crate) fn open(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return Err(KvError::DecryptionFailed);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| KvError::DecryptionFailed)
+    }
+}
+
+// 不打印密钥内容，只打印标识
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncryptionKey({:016x})", self.id)
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{KvError, KvStore, RaftNode, Result};
+
+///存储引擎的统一接口，KvStore 和其它引擎都实现它，CLI 通过它操作具体引擎
+pub trait KvsEngine {
+    ///set a key/value pair in the store
+    fn set(&mut self, key: String, value: String) -> Result<()>;
+    ///get a key/value pair from the store
+    fn get(&mut self, key: String) -> Result<Option<String>>;
+    ///remove a key/value pair, returns `KvError::KeyNotFound` when the key does not exist
+    fn remove(&mut self, key: String) -> Result<()>;
+    ///all key/value pairs whose key starts with `prefix`, in ascending key order
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>>;
+    ///set a key/value pair that expires after `ttl`, returns `KvError::Unsupported` when the engine has no expiry
+    fn set_with_ttl(&mut self, _key: String, _value: String, _ttl: Duration) -> Result<()> {
+        Err(KvError::Unsupported("key expiry"))
+    }
+}
+
+///对共享的引擎执行 f，持有锁执行；Raft 节点的请求在锁外执行，一个请求等待提交时不会阻塞其它连接
+pub(crate) fn with_engine<E: KvsEngine + 'static, T>(engine: &Mutex<E>, f: impl FnOnce(&mut E) -> T) -> T {
+    let detached = RaftNode::detached(&*engine.lock().unwrap());
+    match detached {
+        Some(mut node) => f(&mut node),
+        None => f(&mut engine.lock().unwrap()),
+    }
+}
+
+impl KvsEngine for KvStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        KvStore::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        KvStore::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        KvStore::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        KvStore::scan(self, prefix)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        KvStore::set_with_ttl(self, key, value, ttl)
+    }
+}
+
+impl<E: KvsEngine + ?Sized> KvsEngine for Box<E> {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        (**self).set(key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        (**self).get(key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        (**self).remove(key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        (**self).scan(prefix)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        (**self).set_with_ttl(key, value, ttl)
+    }
+}
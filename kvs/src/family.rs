@@ -0,0 +1,196 @@
+//! 列族：同一个数据目录、同一个日志中互相独立的键空间
+//!
+//! 每个列族有自己的内存索引和配置（compaction 阈值、默认过期时间），可以整体删除。
+//! 日志记录中带有列族的名字，默认列族的记录不带名字，和没有列族之前的日志格式相同。
+//! 列族的定义保存在数据目录的 `families.json` 中；日志中出现了没有定义的列族时使用默认配置。
+//! 值缓存和二级索引只作用于默认列族；`KvStore::watch` 订阅默认列族，复制包括所有列族。
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::keydir::KeyDir;
+use crate::{CommandPos, Commend, FileSystem, KvError, Result, COMPACTION_THRESHOLD};
+
+///默认列族的名字，`KvStore::get` 等不带列族的方法都作用于它
+pub const DEFAULT_FAMILY: &str = "default";
+
+// 保存列族定义的文件
+const FAMILIES_FILE: &str = "families.json";
+
+///创建列族时的配置
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FamilyOptions {
+    ///该列族的陈旧记录超过这个大小时触发 compaction
+    pub compaction_threshold: u64,
+    ///写入的值在这么多毫秒之后过期，None 表示不过期
+    pub ttl_ms: Option<u64>,
+}
+
+impl Default for FamilyOptions {
+    fn default() -> FamilyOptions {
+        FamilyOptions {
+            compaction_threshold: COMPACTION_THRESHOLD,
+            ttl_ms: None,
+        }
+    }
+}
+
+impl FamilyOptions {
+    pub fn new() -> FamilyOptions {
+        FamilyOptions::default()
+    }
+
+    pub fn compaction_threshold(mut self, bytes: u64) -> FamilyOptions {
+        self.compaction_threshold = bytes;
+        self
+    }
+
+    ///之后写入的值默认的存活时间，过期的值读不到，在 compaction 时被删除
+    pub fn ttl(mut self, ttl: Duration) -> FamilyOptions {
+        self.ttl_ms = Some(ttl.as_millis() as u64);
+        self
+    }
+}
+
+///一个列族的统计信息
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FamilyStats {
+    pub name: String,
+    ///包括已经过期、还没有被 compaction 删除的键
+    pub live_keys: u64,
+    pub stale_bytes: u64,
+    pub options: FamilyOptions,
+}
+
+///跨列族的原子写入，`KvStore::write` 要么全部生效要么全部不生效
+///
+///```no_run
+///# use kvs::{KvStore, WriteBatch};
+///let mut store = KvStore::open("data")?;
+///store.write(WriteBatch::new().set("users", "u1", "alice").remove("emails", "alice@example.com"))?;
+///# Ok::<(), kvs::KvError>(())
+///```
+#[derive(Debug, Clone, Default)]
+pub struct WriteBatch {
+    pub(crate) ops: Vec<(String, String, Option<String>)>,
+}
+
+impl WriteBatch {
+    pub fn new() -> WriteBatch {
+        WriteBatch::default()
+    }
+
+    pub fn set(mut self, family: impl Into<String>, key: impl Into<String>, value: impl Into<String>) -> WriteBatch {
+        self.ops.push((family.into(), key.into(), Some(value.into())));
+        self
+    }
+
+    ///删除不存在的键时什么也不做
+    pub fn remove(mut self, family: impl Into<String>, key: impl Into<String>) -> WriteBatch {
+        self.ops.push((family.into(), key.into(), None));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+///一个列族在内存中的状态
+#[derive(Debug)]
+pub(crate) struct Family {
+    pub(crate) index: KeyDir,
+    pub(crate) options: FamilyOptions,
+    ///该列族中 compaction 可以回收的字节数
+    pub(crate) uncompaction: u64,
+    ///最后一条记录是 Merge 的键，以及合并链中在它之前的记录（之前的值和更早的操作数）
+    pub(crate) merge_chains: HashMap<String, Vec<CommandPos>>,
+    ///被覆盖或删除的记录和覆盖它的时间，只在配置了历史保留时间时记录
+    pub(crate) superseded: Vec<(CommandPos, u64)>,
+    ///该列族中最大的记录序号
+    pub(crate) last_seq: u64,
+    ///日志中是否有带过期时间的值（例如 `set_with_ttl` 写入的）
+    pub(crate) expiring: bool,
+}
+
+impl Family {
+    pub(crate) fn new(options: FamilyOptions, index_budget: Option<u64>) -> Family {
+        Family {
+            index: KeyDir::new(index_budget),
+            options,
+            uncompaction: 0,
+            merge_chains: HashMap::new(),
+            superseded: Vec::new(),
+            last_seq: 0,
+            expiring: false,
+        }
+    }
+
+    ///列族中是否可能有过期的值
+    pub(crate) fn may_expire(&self) -> bool {
+        self.options.ttl_ms.is_some() || self.expiring
+    }
+
+    ///现在写入的值的过期时间
+    pub(crate) fn expires(&self) -> Option<u64> {
+        self.options.ttl_ms.map(|ttl| now_ms() + ttl)
+    }
+}
+
+///日志记录中的列族名，默认列族不写名字
+pub(crate) fn record_family(name: &str) -> Option<String> {
+    (name != DEFAULT_FAMILY).then(|| name.to_owned())
+}
+
+///记录所属的列族
+pub(crate) fn family_of(cf: &Option<String>) -> &str {
+    cf.as_deref().unwrap_or(DEFAULT_FAMILY)
+}
+
+///值是否带有过期时间
+pub(crate) fn has_expiry(cmd: &Commend) -> bool {
+    matches!(
+        cmd,
+        Commend::Set { expires: Some(_), .. } | Commend::SetBlob { expires: Some(_), .. }
+    )
+}
+
+///写入记录中值的过期时间，其它记录返回 None
+pub(crate) fn expires_of(cmd: &Commend) -> Option<u64> {
+    match cmd {
+        Commend::Set { expires, .. } | Commend::SetBlob { expires, .. } => *expires,
+        _ => None,
+    }
+}
+
+pub(crate) fn is_expired(cmd: &Commend) -> bool {
+    matches!(
+        cmd,
+        Commend::Set { expires: Some(expires), .. } | Commend::SetBlob { expires: Some(expires), .. }
+            if *expires <= now_ms()
+    )
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+///读取目录中的列族定义，不包括默认列族
+pub(crate) fn load_families(fs: &dyn FileSystem, dir: &Path) -> Result<BTreeMap<String, FamilyOptions>> {
+    let path = dir.join(FAMILIES_FILE);
+    if !fs.list_files(dir)?.contains(&path) {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_slice(&fs.read(&path)?).map_err(|e| KvError::Corruption(format!("{}: {}", FAMILIES_FILE, e)))
+}
+
+pub(crate) fn save_families(fs: &dyn FileSystem, dir: &Path, families: &BTreeMap<String, FamilyOptions>) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", FAMILIES_FILE));
+    let mut file = fs.create(&tmp)?;
+    serde_json::to_writer(&mut file, families)?;
+    file.sync()?;
+    fs.rename(&tmp, &dir.join(FAMILIES_FILE))?;
+    Ok(())
+}
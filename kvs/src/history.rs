@@ -0,0 +1,182 @@
+//! 键的历史版本
+//!
+//! 每条写入记录都带有递增的序号和写入时间。日志在 compaction 之前保留了每个键所有的旧值，
+//! `KvStore::history` 直接扫描日志得到它们。配置了保留时间时，compaction 会保留在这段时间内
+//! 被覆盖或删除的旧版本：写在每个列族当前的值之前，重新加载时仍然以当前的值为准。
+//! compaction 可能丢掉序号最大的记录，已经用过的最大序号另外保存在 `seq.json` 中，重新打开后不会重复使用。
+
+use serde::Serialize;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::family::family_of;
+use crate::keydir::in_range;
+use crate::vlog;
+use crate::{
+    is_incomplete_record, log_path, sorted_gen_list, BufReaderWithPos, CommandStream, Commend, FileSystem, KvError,
+    LogHeader, Result, StoreOptions,
+};
+
+// 保存已经用过的最大序号的文件
+const SEQ_FILE: &str = "seq.json";
+
+///`KvStore::get_at` 读取的时间点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum At {
+    ///序号不大于它的最后一次写入之后
+    Seq(u64),
+    ///这个时间点之前的最后一次写入之后
+    Time(SystemTime),
+}
+
+///键的一个版本
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    pub seq: u64,
+    ///写入时间，UNIX 时间戳（毫秒）
+    pub timestamp: u64,
+    ///删除记录为 None
+    pub value: Option<String>,
+}
+
+impl KeyVersion {
+    fn visible_at(&self, at: At) -> bool {
+        match at {
+            At::Seq(seq) => self.seq <= seq,
+            At::Time(time) => {
+                let millis = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64);
+                self.timestamp <= millis
+            }
+        }
+    }
+}
+
+///读取已经用过的最大序号，文件不存在时为 0
+pub(crate) fn load_last_seq(fs: &dyn FileSystem, dir: &Path) -> Result<u64> {
+    let path = dir.join(SEQ_FILE);
+    if !fs.list_files(dir)?.contains(&path) {
+        return Ok(0);
+    }
+    serde_json::from_slice(&fs.read(&path)?).map_err(|e| KvError::Corruption(format!("{}: {}", SEQ_FILE, e)))
+}
+
+///在删除旧日志之前保存已经用过的最大序号
+pub(crate) fn save_last_seq(fs: &dyn FileSystem, dir: &Path, seq: u64) -> Result<()> {
+    let tmp = dir.join(format!("{}.tmp", SEQ_FILE));
+    let mut file = fs.create(&tmp)?;
+    serde_json::to_writer(&mut file, &seq)?;
+    file.sync()?;
+    fs.rename(&tmp, &dir.join(SEQ_FILE))?;
+    Ok(())
+}
+
+///版本列表中 at 时刻的值
+pub(crate) fn value_at(versions: &[KeyVersion], at: At) -> Option<String> {
+    versions
+        .iter()
+        .rev()
+        .find(|version| version.visible_at(at))
+        .and_then(|version| version.value.clone())
+}
+
+///扫描目录中所有的日志，返回列族 family 中 key 的所有版本，按序号排序
+pub(crate) fn key_history(path: &Path, options: &StoreOptions, family: &str, key: &str) -> Result<Vec<KeyVersion>> {
+    let mut versions = Vec::new();
+    for gen in sorted_gen_list(options.fs(), path)? {
+        let mut file = options.fs().open(&log_path(path, gen))?;
+        let header = LogHeader::read(gen, &mut file)?;
+        let codec = header.codec(gen, options)?;
+        let mut reader = BufReaderWithPos::new(file)?;
+        reader.seek(SeekFrom::Start(header.len()))?;
+        let mut stream = CommandStream::new(&mut reader, codec);
+        //正在读取的批次：还差几条记录，已经读到的版本；不完整的批次和加载时一样被丢弃
+        let mut batch: Option<(usize, Vec<KeyVersion>)> = None;
+        for cmd in stream.by_ref() {
+            let cmd = match cmd {
+                Ok(cmd) => cmd,
+                // 正在追加的记录还没有写完整
+                Err(ref e) if is_incomplete_record(e) => break,
+                Err(e) => return Err(e),
+            };
+            let version = match cmd {
+                Commend::Batch { len } => {
+                    batch = Some((len, Vec::with_capacity(len)));
+                    continue;
+                }
+                Commend::Set {
+                    key: k,
+                    value,
+                    cf,
+                    seq,
+                    ts,
+                    ..
+                } if k == key && family_of(&cf) == family => Some(KeyVersion {
+                    seq,
+                    timestamp: ts,
+                    value: Some(value),
+                }),
+                Commend::SetBlob {
+                    key: k,
+                    blob,
+                    cf,
+                    seq,
+                    ts,
+                    ..
+                } if k == key && family_of(&cf) == family => Some(KeyVersion {
+                    seq,
+                    timestamp: ts,
+                    value: Some(vlog::read_value(path, options, key, &blob)?),
+                }),
+                Commend::Remove { key: k, cf, seq, ts } | Commend::Expire { key: k, cf, seq, ts }
+                    if k == key && family_of(&cf) == family =>
+                {
+                    Some(KeyVersion {
+                        seq,
+                        timestamp: ts,
+                        value: None,
+                    })
+                }
+                Commend::RemoveRange { start, end, cf, seq, ts }
+                    if in_range(key, &start, end.as_deref()) && family_of(&cf) == family =>
+                {
+                    Some(KeyVersion {
+                        seq,
+                        timestamp: ts,
+                        value: None,
+                    })
+                }
+                //合并到上一个版本的值上
+                Commend::Merge { key: ref k, ref cf, seq, ts, .. } if k == key && family_of(cf) == family => {
+                    let pending = batch.as_ref().and_then(|(_, pending)| pending.last());
+                    let existing = pending.or(versions.last()).and_then(|version| version.value.clone());
+                    Some(KeyVersion {
+                        seq,
+                        timestamp: ts,
+                        value: options.merge_operators.fold(existing, cmd)?,
+                    })
+                }
+                //删除列族之前的版本不再属于同名的新列族
+                Commend::DropFamily { cf } if cf == family => {
+                    versions.clear();
+                    None
+                }
+                _ => None,
+            };
+            match &mut batch {
+                Some((remaining, pending)) => {
+                    pending.extend(version);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        versions.extend(batch.take().unwrap().1);
+                    }
+                }
+                None => versions.extend(version),
+            }
+        }
+    }
+    //compaction 中断时同一条记录可能同时在新旧日志中；没有序号的旧记录保持日志中的顺序
+    versions.sort_by_key(|version| version.seq);
+    versions.dedup_by(|a, b| a.seq != 0 && a.seq == b.seq);
+    Ok(versions)
+}
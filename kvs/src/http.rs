@@ -0,0 +1,286 @@
+//! HTTP 接口，方便脚本直接访问存储，请求由 `webserver::ThreadPool` 处理
+//!
+//! - `GET /keys/{key}`：读取，返回 `{"key": ..., "value": ...}`
+//! - `PUT /keys/{key}`：写入，请求体就是值
+//! - `DELETE /keys/{key}`：删除
+//! - `GET /keys?prefix=...`：按前缀列出键值对
+//!
+//! 键不存在返回 404，请求不合法返回 400，只读的从节点或者不是 Raft 主节点时返回 503，
+//! 存储的 IO 和序列化错误返回 500，错误的响应都是 `{"error": ...}`。
+//! 一个请求要在 `read_timeout` 之内读完，否则返回 408 并关闭连接，慢速的客户端不会一直占用工作线程。
+
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use webserver::ThreadPool;
+
+use crate::engine::with_engine;
+use crate::{KvError, KvsEngine, Result};
+
+// 默认的工作线程数
+const DEFAULT_THREADS: usize = 4;
+// 请求体的最大长度
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+// 读取一个完整请求的默认时限
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    body: Vec<u8>,
+}
+
+///HTTP 服务，所有请求共享同一个引擎
+pub struct HttpServer<E: KvsEngine> {
+    engine: Arc<Mutex<E>>,
+    threads: usize,
+    read_timeout: Duration,
+}
+
+impl<E: KvsEngine + Send + 'static> HttpServer<E> {
+    pub fn new(engine: E) -> HttpServer<E> {
+        HttpServer::from_shared(Arc::new(Mutex::new(engine)))
+    }
+
+    pub fn from_shared(engine: Arc<Mutex<E>>) -> HttpServer<E> {
+        HttpServer {
+            engine,
+            threads: DEFAULT_THREADS,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        }
+    }
+
+    ///线程池的大小
+    pub fn threads(mut self, threads: usize) -> HttpServer<E> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    ///读取一个完整请求（请求行、请求头和请求体）的时限
+    pub fn read_timeout(mut self, timeout: Duration) -> HttpServer<E> {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        self.serve(TcpListener::bind(addr)?)
+    }
+
+    pub fn serve(self, listener: TcpListener) -> Result<()> {
+        let pool = ThreadPool::new(self.threads);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let engine = Arc::clone(&self.engine);
+            let read_timeout = self.read_timeout;
+            pool.execute(move || {
+                if let Err(e) = handle_connection(&engine, stream, read_timeout) {
+                    eprintln!("Error on serving HTTP client: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+///每个连接只处理一个请求，请求要在 read_timeout 之内读完
+fn handle_connection<E: KvsEngine + 'static>(
+    engine: &Mutex<E>,
+    stream: TcpStream,
+    read_timeout: Duration,
+) -> Result<()> {
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + read_timeout,
+    });
+    let (status, body) = match read_request(&mut reader) {
+        Ok(Some(request)) => route(engine, request),
+        Ok(None) => return Ok(()),
+        Err(KvError::Protocol(message)) => (400, json!({ "error": message })),
+        Err(KvError::IoError(e)) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => {
+            (408, json!({ "error": "timed out reading the request" }))
+        }
+        Err(e) => return Err(e),
+    };
+    write_response(stream, status, &body)
+}
+
+///读取超过 deadline 时返回 `TimedOut` 错误，整个请求共用一个时限
+struct DeadlineReader {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn route<E: KvsEngine + 'static>(engine: &Mutex<E>, request: HttpRequest) -> (u16, Value) {
+    let key = match request.path.strip_prefix("/keys/") {
+        Some(key) if !key.is_empty() => match percent_decode(key) {
+            Some(key) => Some(key),
+            None => return (400, json!({ "error": "invalid percent-encoding in key" })),
+        },
+        _ => None,
+    };
+    with_engine(engine, |engine| {
+        let result = match (request.method.as_str(), key) {
+            ("GET", Some(key)) => engine.get(key.clone()).and_then(|value| match value {
+                Some(value) => Ok(json!({ "key": key, "value": value })),
+                None => Err(KvError::KeyNotFound),
+            }),
+            ("PUT", Some(key)) => match String::from_utf8(request.body) {
+                Ok(value) => engine
+                    .set(key.clone(), value.clone())
+                    .map(|_| json!({ "key": key, "value": value })),
+                Err(_) => return (400, json!({ "error": "value must be UTF-8" })),
+            },
+            ("DELETE", Some(key)) => engine.remove(key.clone()).map(|_| json!({ "key": key })),
+            ("GET", None) if request.path == "/keys" => {
+                let prefix = match query_param(request.query.as_deref(), "prefix") {
+                    Ok(prefix) => prefix.unwrap_or_default(),
+                    Err(()) => return (400, json!({ "error": "invalid percent-encoding in query" })),
+                };
+                engine.scan(&prefix).map(|pairs| {
+                    Value::Array(
+                        pairs
+                            .into_iter()
+                            .map(|(key, value)| json!({ "key": key, "value": value }))
+                            .collect(),
+                    )
+                })
+            }
+            (_, Some(_)) => return (405, json!({ "error": "method not allowed" })),
+            _ if request.path == "/keys" => return (405, json!({ "error": "method not allowed" })),
+            _ => return (404, json!({ "error": "not found" })),
+        };
+        match result {
+            Ok(body) => (200, body),
+            Err(e) => (status_for(&e), json!({ "error": e.to_string() })),
+        }
+    })
+}
+
+fn status_for(error: &KvError) -> u16 {
+    match error {
+        KvError::KeyNotFound | KvError::FamilyNotFound(_) | KvError::IndexNotFound(_) => 404,
+        KvError::Protocol(_)
+        | KvError::InvalidCommand(_)
+        | KvError::InvalidIndexPath(_)
+        | KvError::UnknownMergeOperator(_)
+        | KvError::MergeFailed(_)
+        | KvError::TypeMismatch { .. } => 400,
+        KvError::FamilyExists(_) | KvError::IndexExists(_) | KvError::DropDefaultFamily => 409,
+        KvError::ReadOnly | KvError::NotLeader { .. } | KvError::NoShards => 503,
+        _ => 500,
+    }
+}
+
+///读取请求行、请求头和请求体，连接在请求之前关闭时返回 None
+fn read_request(reader: &mut impl BufRead) -> Result<Option<HttpRequest>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(KvError::Protocol(format!("malformed request line '{}'", line.trim_end())));
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_owned(), Some(query.to_owned())),
+        None => (target.to_owned(), None),
+    };
+    let method = method.to_owned();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(KvError::Protocol("unexpected end of headers".to_owned()));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| KvError::Protocol("invalid Content-Length".to_owned()))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY_LEN {
+        return Err(KvError::Protocol("request body is too large".to_owned()));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(HttpRequest {
+        method,
+        path,
+        query,
+        body,
+    }))
+}
+
+fn write_response(mut stream: TcpStream, status: u16, body: &Value) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let body = serde_json::to_vec(body)?;
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn query_param(query: Option<&str>, name: &str) -> std::result::Result<Option<String>, ()> {
+    for pair in query.unwrap_or_default().split('&') {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if key == name {
+            return percent_decode(&value.replace('+', " ")).map(Some).ok_or(());
+        }
+    }
+    Ok(None)
+}
+
+///解码 URL 中的 `%XX`
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
@@ -0,0 +1,362 @@
+//! KvStore 的内存索引（键目录）
+//!
+//! 默认保存完整的键，按键排序。配置了内存预算时，索引的估算大小超过预算后转换成指纹模式：
+//! 内存中只保存键的 64 位哈希和命令位置，查找时从日志读出记录的键进行比较，
+//! 不同的键哈希相同时保存在同一个指纹下，逐个比较。指纹模式下索引的大小与键的长度无关，
+//! 代价是每次查找多读一次日志，按前缀扫描需要读取所有记录。
+
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::mem::size_of;
+use std::ops::Bound;
+
+use crate::{CommandPos, Result};
+
+// 估算内存时每个条目在容器中的额外开销
+const BTREE_ENTRY_OVERHEAD: u64 = 16;
+const HASH_ENTRY_OVERHEAD: u64 = 8;
+
+///索引的存储方式
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexMode {
+    ///内存中保存完整的键
+    Full,
+    ///内存中只保存键的指纹
+    Hashed,
+}
+
+///读取日志中某个位置的记录的键
+pub(crate) type KeyAt<'a> = dyn FnMut(CommandPos) -> Result<String> + 'a;
+
+#[derive(Debug)]
+enum Entries {
+    Full {
+        map: BTreeMap<String, CommandPos>,
+        key_bytes: u64,
+    },
+    Hashed {
+        primary: HashMap<u64, CommandPos>,
+        ///与 primary 中的条目指纹相同、键不同的条目
+        overflow: HashMap<u64, Vec<CommandPos>>,
+        len: usize,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct KeyDir {
+    entries: Entries,
+    budget: Option<u64>,
+}
+
+impl KeyDir {
+    pub(crate) fn new(budget: Option<u64>) -> KeyDir {
+        let mut keydir = KeyDir {
+            entries: Entries::Full {
+                map: BTreeMap::new(),
+                key_bytes: 0,
+            },
+            budget,
+        };
+        keydir.check_budget();
+        keydir
+    }
+
+    pub(crate) fn mode(&self) -> IndexMode {
+        match self.entries {
+            Entries::Full { .. } => IndexMode::Full,
+            Entries::Hashed { .. } => IndexMode::Hashed,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.entries {
+            Entries::Full { map, .. } => map.len(),
+            Entries::Hashed { len, .. } => *len,
+        }
+    }
+
+    ///索引占用内存的估算值
+    pub(crate) fn memory_bytes(&self) -> u64 {
+        let pos = size_of::<CommandPos>() as u64;
+        match &self.entries {
+            Entries::Full { map, key_bytes } => {
+                map.len() as u64 * (size_of::<String>() as u64 + pos + BTREE_ENTRY_OVERHEAD) + key_bytes
+            }
+            Entries::Hashed { len, .. } => *len as u64 * (size_of::<u64>() as u64 + pos + HASH_ENTRY_OVERHEAD),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str, key_at: &mut KeyAt) -> Result<Option<CommandPos>> {
+        match &self.entries {
+            Entries::Full { map, .. } => Ok(map.get(key).copied()),
+            Entries::Hashed { primary, overflow, .. } => {
+                let fingerprint = fingerprint(key);
+                let candidates = primary
+                    .get(&fingerprint)
+                    .into_iter()
+                    .chain(overflow.get(&fingerprint).into_iter().flatten());
+                for &cmd_pos in candidates {
+                    if key_at(cmd_pos)? == key {
+                        return Ok(Some(cmd_pos));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    ///返回键原来的位置
+    pub(crate) fn insert(&mut self, key: String, cmd_pos: CommandPos, key_at: &mut KeyAt) -> Result<Option<CommandPos>> {
+        let old = match &mut self.entries {
+            Entries::Full { map, key_bytes } => {
+                let len = key.len() as u64;
+                let old = map.insert(key, cmd_pos);
+                if old.is_none() {
+                    *key_bytes += len;
+                }
+                old
+            }
+            Entries::Hashed { primary, overflow, len } => {
+                let fingerprint = fingerprint(&key);
+                match find(primary, overflow, fingerprint, &key, key_at)? {
+                    Some(slot) => Some(std::mem::replace(slot, cmd_pos)),
+                    None => {
+                        *len += 1;
+                        if let Some(first) = primary.insert(fingerprint, cmd_pos) {
+                            overflow.entry(fingerprint).or_default().push(first);
+                        }
+                        None
+                    }
+                }
+            }
+        };
+        self.check_budget();
+        Ok(old)
+    }
+
+    ///返回被删除的键原来的位置
+    pub(crate) fn remove(&mut self, key: &str, key_at: &mut KeyAt) -> Result<Option<CommandPos>> {
+        match &mut self.entries {
+            Entries::Full { map, key_bytes } => {
+                let old = map.remove(key);
+                if old.is_some() {
+                    *key_bytes -= key.len() as u64;
+                }
+                Ok(old)
+            }
+            Entries::Hashed { primary, overflow, len } => {
+                let fingerprint = fingerprint(key);
+                let Some(&mut old) = find(primary, overflow, fingerprint, key, key_at)? else {
+                    return Ok(None);
+                };
+                *len -= 1;
+                unlink(primary, overflow, fingerprint, old);
+                Ok(Some(old))
+            }
+        }
+    }
+
+    ///键以 prefix 开头的条目，按键排序；指纹模式下不知道键，返回 None
+    pub(crate) fn prefix(&self, prefix: &str) -> Option<Vec<(String, CommandPos)>> {
+        match &self.entries {
+            Entries::Full { map, .. } => Some(
+                map.range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+                    .take_while(|(key, _)| key.starts_with(prefix))
+                    .map(|(key, cmd_pos)| (key.clone(), *cmd_pos))
+                    .collect(),
+            ),
+            Entries::Hashed { .. } => None,
+        }
+    }
+
+    ///删除 [start, end) 中的所有键，end 为 None 时没有上界；返回被删除的键和位置
+    pub(crate) fn remove_range(
+        &mut self,
+        start: &str,
+        end: Option<&str>,
+        key_at: &mut KeyAt,
+    ) -> Result<Vec<(String, CommandPos)>> {
+        let mut removed = Vec::new();
+        if let Entries::Full { map, key_bytes } = &mut self.entries {
+            if end.is_some_and(|end| end <= start) {
+                return Ok(removed);
+            }
+            let upper = end.map_or(Bound::Unbounded, Bound::Excluded);
+            let keys: Vec<String> = map
+                .range::<str, _>((Bound::Included(start), upper))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                let cmd_pos = map.remove(&key).unwrap();
+                *key_bytes -= key.len() as u64;
+                removed.push((key, cmd_pos));
+            }
+            return Ok(removed);
+        }
+        //指纹模式下要读出每个键才知道是否在范围内
+        self.retain(|cmd_pos| {
+            let key = key_at(cmd_pos)?;
+            if !in_range(&key, start, end) {
+                return Ok(true);
+            }
+            removed.push((key, cmd_pos));
+            Ok(false)
+        })?;
+        Ok(removed)
+    }
+
+    pub(crate) fn positions(&self) -> Vec<CommandPos> {
+        match &self.entries {
+            Entries::Full { map, .. } => map.values().copied().collect(),
+            Entries::Hashed { primary, overflow, .. } => {
+                primary.values().chain(overflow.values().flatten()).copied().collect()
+            }
+        }
+    }
+
+    pub(crate) fn positions_mut(&mut self) -> Box<dyn Iterator<Item = &mut CommandPos> + '_> {
+        match &mut self.entries {
+            Entries::Full { map, .. } => Box::new(map.values_mut()),
+            Entries::Hashed { primary, overflow, .. } => {
+                Box::new(primary.values_mut().chain(overflow.values_mut().flatten()))
+            }
+        }
+    }
+
+    ///只保留 f 返回 true 的条目
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(CommandPos) -> Result<bool>) -> Result<()> {
+        match &mut self.entries {
+            Entries::Full { map, key_bytes } => {
+                let mut removed = Vec::new();
+                for (key, cmd_pos) in map.iter() {
+                    if !f(*cmd_pos)? {
+                        removed.push(key.clone());
+                    }
+                }
+                for key in removed {
+                    map.remove(&key);
+                    *key_bytes -= key.len() as u64;
+                }
+            }
+            Entries::Hashed { primary, overflow, len } => {
+                let entries = primary.iter().chain(
+                    overflow
+                        .iter()
+                        .flat_map(|(fingerprint, positions)| positions.iter().map(move |cmd_pos| (fingerprint, cmd_pos))),
+                );
+                let mut removed = Vec::new();
+                for (&fingerprint, &cmd_pos) in entries {
+                    if !f(cmd_pos)? {
+                        removed.push((fingerprint, cmd_pos));
+                    }
+                }
+                for (fingerprint, cmd_pos) in removed {
+                    *len -= 1;
+                    unlink(primary, overflow, fingerprint, cmd_pos);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///超过内存预算时转换成指纹模式，只会转换一次
+    fn check_budget(&mut self) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        if self.mode() == IndexMode::Hashed || self.memory_bytes() <= budget {
+            return;
+        }
+        let Entries::Full { map, .. } = std::mem::replace(
+            &mut self.entries,
+            Entries::Hashed {
+                primary: HashMap::new(),
+                overflow: HashMap::new(),
+                len: 0,
+            },
+        ) else {
+            unreachable!()
+        };
+        let Entries::Hashed { primary, overflow, len } = &mut self.entries else {
+            unreachable!()
+        };
+        //完整索引中的键各不相同，指纹相同的直接放进 overflow
+        for (key, cmd_pos) in map {
+            let fingerprint = fingerprint(&key);
+            if let Some(first) = primary.insert(fingerprint, cmd_pos) {
+                overflow.entry(fingerprint).or_default().push(first);
+            }
+            *len += 1;
+        }
+    }
+}
+
+///key 是否在 [start, end) 中，end 为 None 时没有上界
+pub(crate) fn in_range(key: &str, start: &str, end: Option<&str>) -> bool {
+    key >= start && end.is_none_or(|end| key < end)
+}
+
+///以 prefix 开头的键的上界：比所有以 prefix 开头的键都大的最小字符串，不存在时返回 None
+pub(crate) fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        //跳过代理区，char::MAX 没有后继，去掉它再进位
+        let next = (last as u32 + 1..=char::MAX as u32).find_map(char::from_u32);
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+fn fingerprint(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+///删除指纹下位置为 cmd_pos 的条目
+fn unlink(
+    primary: &mut HashMap<u64, CommandPos>,
+    overflow: &mut HashMap<u64, Vec<CommandPos>>,
+    fingerprint: u64,
+    cmd_pos: CommandPos,
+) {
+    let others = overflow.entry(fingerprint).or_default();
+    if primary.get(&fingerprint) == Some(&cmd_pos) {
+        //从 overflow 中补上被删除的条目，保证 primary 中总有该指纹的一个条目
+        match others.pop() {
+            Some(other) => primary.insert(fingerprint, other),
+            None => primary.remove(&fingerprint),
+        };
+    } else {
+        others.retain(|other| *other != cmd_pos);
+    }
+    if others.is_empty() {
+        overflow.remove(&fingerprint);
+    }
+}
+
+///查找键对应的条目
+fn find<'a>(
+    primary: &'a mut HashMap<u64, CommandPos>,
+    overflow: &'a mut HashMap<u64, Vec<CommandPos>>,
+    fingerprint: u64,
+    key: &str,
+    key_at: &mut KeyAt,
+) -> Result<Option<&'a mut CommandPos>> {
+    let candidates = primary
+        .get_mut(&fingerprint)
+        .into_iter()
+        .chain(overflow.get_mut(&fingerprint).into_iter().flatten());
+    for slot in candidates {
+        if key_at(*slot)? == key {
+            return Ok(Some(slot));
+        }
+    }
+    Ok(None)
+}
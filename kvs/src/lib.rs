@@ -1,16 +1,94 @@
+// failure 的 derive 宏会在匿名常量里生成 impl
+#![allow(non_local_definitions)]
 
 use failure::Fail;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
 use std::ffi::OsStr;
-use std::fs::{File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::ops::Range;
 use std::path::Path;
 
 use std::string::String;
-use std::{collections::HashMap, fs, io, path::PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashSet};
+use std::{collections::HashMap, io, path::PathBuf};
+
+use cache::ValueCache;
+use codec::Codec;
+use family::{
+    expires_of, family_of, has_expiry, is_expired, load_families, now_ms, record_family, save_families, Family,
+};
+use keydir::{prefix_end, KeyAt, KeyDir};
+use secondary::SecondaryIndexes;
+use stats::PersistedStats;
+use vlog::ValueLog;
+use watch::{WatchSender, Watcher};
+
+pub use async_server::AsyncKvsServer;
+pub use async_store::AsyncKvStore;
+pub use cache::CacheStats;
+pub use client::KvsClient;
+pub use codec::Format;
+pub use crypto::EncryptionKey;
+pub use engine::KvsEngine;
+pub use family::{FamilyOptions, FamilyStats, WriteBatch, DEFAULT_FAMILY};
+pub use history::{At, KeyVersion};
+pub use http::HttpServer;
+pub use keydir::IndexMode;
+pub use lsm::{LsmOptions, LsmStore};
+pub use merge::{MergeOperator, ADD_OPERATOR};
+pub use memory::MemoryStore;
+pub use options::StoreOptions;
+pub use raft::{RaftConfig, RaftNode, RaftPeer, RaftRole, RaftStatus};
+pub use repair::{Damage, RepairReport, VerifyReport};
+pub use replication::{Follower, ReplicationLeader, ReplicationStatus};
+pub use resp::RespServer;
+pub use secondary::IndexDef;
+pub use server::KvsServer;
+pub use shard::{HashRing, ShardedClient, DEFAULT_VNODES};
+pub use shell::{Shell, ShellReply};
+pub use stats::{CompactionInfo, GenerationStats, OpCounters, StoreStats};
+pub use typed::TypedTree;
+pub use vfs::{DiskFs, FaultyFs, FileSystem, FsReader, FsWriter, MemoryFs};
+pub use vlog::{BlobPos, GcReport, ValueReader};
+pub use watch::{Change, LogPosition, WatchEvent};
+#[cfg(feature = "sled")]
+pub use sled_engine::SledKvsEngine;
+
+mod async_server;
+mod async_store;
+mod cache;
+mod client;
+mod codec;
+mod crypto;
+mod engine;
+mod family;
+mod history;
+mod http;
+mod keydir;
+mod lsm;
+mod memory;
+mod merge;
+mod options;
+pub mod protocol;
+mod raft;
+mod repair;
+mod replication;
+mod resp;
+mod secondary;
+mod server;
+mod shard;
+mod shell;
+mod stats;
+mod typed;
+#[cfg(feature = "sled")]
+mod sled_engine;
+mod vfs;
+mod vlog;
+mod watch;
 
 // 自定义错误
 #[derive(Debug, Fail)]
@@ -27,6 +105,89 @@ pub enum KvError {
     UnexpectedCommandType,
     #[fail(display = "Key not found")]
     KeyNotFound,
+    #[fail(display = "invalid encryption key, expected 32 bytes or 64 hex characters")]
+    InvalidKey,
+    /// 日志文件使用的密钥没有提供
+    #[fail(display = "{}.log is encrypted with key {:016x}, which was not supplied", gen, key_id)]
+    WrongKey { gen: u64, key_id: u64 },
+    /// 记录认证失败：数据被篡改或密钥不对
+    #[fail(display = "failed to decrypt log record")]
+    DecryptionFailed,
+    /// 记录加密失败
+    #[fail(display = "failed to encrypt log record")]
+    EncryptionFailed,
+    #[fail(display = "{}.log has an invalid header", gen)]
+    InvalidLogHeader { gen: u64 },
+    /// 编码后的记录超过了长度前缀能表示的 4 GiB
+    #[fail(display = "record of {} bytes is too large for the log", _0)]
+    RecordTooLarge(u64),
+    /// 磁盘上的数据文件损坏
+    #[fail(display = "corrupted data: {}", _0)]
+    Corruption(String),
+    /// 从节点不接受写入
+    #[fail(display = "this replica is read-only")]
+    ReadOnly,
+    /// kvs-server 返回的错误
+    #[fail(display = "server error: {}", _0)]
+    Remote(String),
+    /// 订阅恢复的位置所在的日志已经被 compaction 删除
+    #[fail(display = "log generation {} has been compacted away", gen)]
+    PositionCompacted { gen: u64 },
+    /// Raft 集群中只有主节点处理请求，leader 是已知主节点的客户端地址
+    #[fail(display = "not the raft leader")]
+    NotLeader { leader: Option<String> },
+    /// 分片客户端没有可用的分片
+    #[fail(display = "no shards configured")]
+    NoShards,
+    /// 客户端发送的数据不符合协议
+    #[fail(display = "protocol error: {}", _0)]
+    Protocol(String),
+    /// kvs shell 中无法执行的命令
+    #[fail(display = "{}", _0)]
+    InvalidCommand(String),
+    /// 二级索引不存在
+    #[fail(display = "index {} does not exist", _0)]
+    IndexNotFound(String),
+    /// 同名的二级索引已经存在
+    #[fail(display = "index {} already exists", _0)]
+    IndexExists(String),
+    /// 二级索引的字段路径不合法
+    #[fail(display = "invalid index path {:?}", _0)]
+    InvalidIndexPath(String),
+    /// 列族不存在
+    #[fail(display = "column family {} does not exist", _0)]
+    FamilyNotFound(String),
+    /// 同名的列族已经存在
+    #[fail(display = "column family {} already exists", _0)]
+    FamilyExists(String),
+    #[fail(display = "the default column family cannot be dropped")]
+    DropDefaultFamily,
+    /// 合并运算符没有注册
+    #[fail(display = "merge operator {} is not registered", _0)]
+    UnknownMergeOperator(String),
+    /// 合并运算符无法把操作数合并到现有的值上
+    #[fail(display = "merge failed: {}", _0)]
+    MergeFailed(String),
+    /// 值日志中的值不是 UTF-8，只能用 `get_reader` 读取
+    #[fail(display = "value of {} is not valid UTF-8, read it as a stream", _0)]
+    BinaryValue(String),
+    /// 值不能反序列化为 `get_typed` 要求的类型
+    #[fail(display = "value of {} is not a valid {}: {}", key, expected, reason)]
+    TypeMismatch {
+        key: String,
+        expected: &'static str,
+        reason: String,
+    },
+    /// 存储引擎不支持的操作
+    #[fail(display = "{} is not supported by this engine", _0)]
+    Unsupported(&'static str),
+    /// 在线程池中执行的操作 panic 了
+    #[fail(display = "background task failed")]
+    TaskFailed,
+    /// sled 引擎的错误
+    #[cfg(feature = "sled")]
+    #[fail(display = "sled error occurred.")]
+    Sled(#[cause] sled::Error),
 }
 
 // 实现根据错误源响应对应的错误
@@ -42,6 +203,13 @@ impl From<io::Error> for KvError {
     }
 }
 
+#[cfg(feature = "sled")]
+impl From<sled::Error> for KvError {
+    fn from(error: sled::Error) -> Self {
+        KvError::Sled(error)
+    }
+}
+
 // 自定义Result类型，默认使用KvError作为错误类型
 pub type Result<T> = std::result::Result<T, KvError>;
 
@@ -49,144 +217,1059 @@ pub type Result<T> = std::result::Result<T, KvError>;
 const COMPACTION_THRESHOLD: u64 = 1024*1024;
 
 // 定义枚举值 Commend，存放不同种类的命令
-#[derive(Serialize, Deserialize, Debug)]
+//
+// cf 是记录所属的列族，默认列族不写；expires 是值过期的时间（UNIX 时间戳，毫秒）
+// seq 是写入的序号，ts 是写入的时间（UNIX 时间戳，毫秒），没有这两个字段的旧记录为 0
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Commend {
-    Set { key: String, value: String },
-    Remove { key: String },
+    Set {
+        key: String,
+        value: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires: Option<u64>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
+    Remove {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
+    ///值保存在值日志中的写入，blob 是值的位置
+    SetBlob {
+        key: String,
+        blob: BlobPos,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires: Option<u64>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
+    ///合并操作数，读取时用名为 op 的合并运算符合并到之前的值上
+    Merge {
+        key: String,
+        op: String,
+        operand: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
+    ///删除 [start, end) 中的所有键，end 为空时没有上界
+    RemoveRange {
+        start: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        end: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
+    ///之后的 len 条记录是一个原子批次
+    Batch { len: usize },
+    ///删除列族中所有的键
+    DropFamily { cf: String },
+    ///创建列族，列族的配置同时保存在列族文件中，这条记录让订阅者和从节点知道新列族的配置
+    CreateFamily { cf: String, options: FamilyOptions },
+    ///值已经过期，和 Remove 一样删除键；读取时第一次发现过期或 compaction 丢掉过期的值时写入
+    Expire {
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cf: Option<String>,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        seq: u64,
+        #[serde(default, skip_serializing_if = "is_zero")]
+        ts: u64,
+    },
 }
 // 枚举结构体Commend 的构造函数
 impl Commend {
     fn set(key: String, value: String) -> Commend {
-        Commend::Set { key, value }
+        Commend::Set {
+            key,
+            value,
+            cf: None,
+            expires: None,
+            seq: 0,
+            ts: 0,
+        }
     }
     fn remove(key: String) -> Commend {
-        Commend::Remove { key }
+        Commend::Remove {
+            key,
+            cf: None,
+            seq: 0,
+            ts: 0,
+        }
+    }
+
+    ///记录的序号，批次标记等没有序号的记录为 0
+    fn seq(&self) -> u64 {
+        match self {
+            Commend::Set { seq, .. }
+            | Commend::SetBlob { seq, .. }
+            | Commend::Remove { seq, .. }
+            | Commend::Merge { seq, .. }
+            | Commend::RemoveRange { seq, .. }
+            | Commend::Expire { seq, .. } => *seq,
+            _ => 0,
+        }
     }
 }
 
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
 #[derive(Debug)]
 pub struct KvStore {
     path: PathBuf,
-    writer: BufWriterWithPos<File>,
-    readers: HashMap<u64, BufReaderWithPos<File>>,
-    index: BTreeMap<String, CommandPos>,
+    writer: LogWriter,
+    value_log: ValueLog,
+    readers: HashMap<u64, LogReader>,
+    headers: HashMap<u64, LogHeader>,
+    families: BTreeMap<String, Family>,
+    cache: Option<ValueCache>,
+    secondary: SecondaryIndexes,
     current_gen: u64,
+    ///下一条写入记录的序号
+    next_seq: u64,
     uncompaction: u64,//表示通过一次compaction可以清除的陈旧命令行
+    options: StoreOptions,
+    watchers: Vec<Watcher>,
+    stats: PersistedStats,
 }
 
 impl KvStore {
     ///set a key/value pair in the store
     pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.set_cf(DEFAULT_FAMILY, key, value)
+    }
+    ///get a key/value pair from the store
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.get_cf(DEFAULT_FAMILY, key)
+    }
+    ///remove a key/value pair from the
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.remove_cf(DEFAULT_FAMILY, key)
+    }
+    ///按键的顺序返回所有以 prefix 开头的键值对
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.scan_cf(DEFAULT_FAMILY, prefix)
+    }
+
+    ///在列族 family 中写入键值对
+    pub fn set_cf(&mut self, family: &str, key: String, value: String) -> Result<()> {
         // 序列化set 命令
-        let commend = Commend::set(key, value);
-        //获取未插入数据前的pos位置
-        let pos = self.writer.pos;
+        let commend = self.set_command(family, key, value)?;
         //插入数据后，pos的位置会自动改变
-        serde_json::to_writer(&mut self.writer, &commend)?;
-        self.writer.flush()?;
-        if let Commend::Set { key, .. } = commend {
-            if let Some(old_cmd) = self.index
-                .insert(key, (self.current_gen, pos..self.writer.pos).into()){
-                    self.uncompaction += old_cmd.len;
+        let range = self.append(&commend)?;
+        self.stats.ops.sets += 1;
+        self.apply(commend, range)?;
+        self.compact_if_needed()
+    }
+
+    ///写入在 ttl 之后过期的键值对，过期时间和值一起写在日志中，重启后仍然有效
+    pub fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.set_with_ttl_cf(DEFAULT_FAMILY, key, value, ttl)
+    }
+
+    ///写入在 ttl 之后过期的键值对，代替列族默认的过期时间
+    pub fn set_with_ttl_cf(&mut self, family: &str, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.set_expiring_cf(family, key, value, Some(now_ms() + ttl.as_millis() as u64))
+    }
+
+    ///写入在 expires（UNIX 时间戳，毫秒）过期的键值对，None 表示不过期；从节点用它应用主节点的过期时间
+    pub(crate) fn set_expiring_cf(
+        &mut self,
+        family: &str,
+        key: String,
+        value: String,
+        expires: Option<u64>,
+    ) -> Result<()> {
+        self.family(family)?;
+        let commend = self.set_command_expiring(family, key, value, expires)?;
+        let range = self.append(&commend)?;
+        self.stats.ops.sets += 1;
+        self.apply(commend, range)?;
+        self.compact_if_needed()
+    }
+
+    pub fn get_cf(&mut self, family: &str, key: String) -> Result<Option<String>> {
+        self.stats.ops.gets += 1;
+        //值缓存只用于默认列族
+        let cached = family == DEFAULT_FAMILY;
+        if let Some(value) = self.cache.as_mut().filter(|_| cached).and_then(|cache| cache.get(&key)) {
+            return Ok(Some(value));
+        }
+        //1、判断有没有key
+        let Some(cmd_pos) = self.lookup(family, &key)? else {
+            return Ok(None);
+        };
+        // 2、根据CommendPos读取数据；会过期的值不放进缓存，缓存中的值不检查过期时间
+        let cmd = self.read_command(cmd_pos)?;
+        let cached = cached && !has_expiry(&cmd);
+        let value = self.value_of(family, cmd)?;
+        if let (Some(value), Some(cache)) = (&value, self.cache.as_mut().filter(|_| cached)) {
+            cache.insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    ///读取键现在的值，不使用缓存，也不计入统计
+    pub(crate) fn current_value(&mut self, family: &str, key: &str) -> Result<Option<String>> {
+        match self.lookup(family, key)? {
+            Some(cmd_pos) => {
+                let cmd = self.read_command(cmd_pos)?;
+                self.value_of(family, cmd)
+            }
+            None => Ok(None),
+        }
+    }
+
+    ///从 value 中读取键的值；大值边读边写入值日志，不会整个放进内存，可以是任意的字节
+    pub fn set_reader(&mut self, key: String, value: impl Read) -> Result<()> {
+        self.set_reader_cf(DEFAULT_FAMILY, key, value)
+    }
+
+    pub fn set_reader_cf(&mut self, family: &str, key: String, mut value: impl Read) -> Result<()> {
+        let expires = self.family(family)?.expires();
+        let threshold = self.options.value_threshold();
+        let mut head = Vec::new();
+        value.by_ref().take(threshold).read_to_end(&mut head)?;
+        //小的文本值和 set 一样写在主日志中
+        let head = match String::from_utf8(head) {
+            Ok(text) if (text.len() as u64) < threshold => return self.set_cf(family, key, text),
+            Ok(text) => text.into_bytes(),
+            Err(e) => e.into_bytes(),
+        };
+        let blob = self.value_log.write(&self.path, &self.options, &mut head.as_slice().chain(value))?;
+        let (seq, ts) = self.stamp();
+        let cmd = Commend::SetBlob {
+            key,
+            blob,
+            cf: record_family(family),
+            expires,
+            seq,
+            ts,
+        };
+        let range = self.append(&cmd)?;
+        self.stats.ops.sets += 1;
+        self.apply(cmd, range)?;
+        self.compact_if_needed()
+    }
+
+    ///以流的方式读取键的值，值日志中的值边读边从磁盘读取
+    pub fn get_reader(&mut self, key: String) -> Result<Option<ValueReader>> {
+        self.get_reader_cf(DEFAULT_FAMILY, key)
+    }
+
+    pub fn get_reader_cf(&mut self, family: &str, key: String) -> Result<Option<ValueReader>> {
+        self.stats.ops.gets += 1;
+        let Some(cmd_pos) = self.lookup(family, &key)? else {
+            return Ok(None);
+        };
+        match self.read_command(cmd_pos)? {
+            cmd if is_expired(&cmd) => {
+                self.expire(family, key)?;
+                Ok(None)
+            }
+            Commend::SetBlob { blob, .. } => vlog::open_blob(&self.path, &self.options, &blob).map(Some),
+            cmd => Ok(self.value_of(family, cmd)?.map(ValueReader::from)),
+        }
+    }
+
+    pub fn remove_cf(&mut self, family: &str, key: String) -> Result<()> {
+        //判断键值索引是否包含该键
+        if !self.contains(family, &key)? {
+            return Err(KvError::KeyNotFound);
+        }
+        //1、在日志中存入命令
+        let (seq, ts) = self.stamp();
+        let rm_cmd = Commend::Remove {
+            key,
+            cf: record_family(family),
+            seq,
+            ts,
+        };
+        let range = self.append(&rm_cmd)?;
+        self.stats.ops.removes += 1;
+        //2、删除键值索引里面的值
+        self.apply(rm_cmd, range)
+    }
+
+    ///用合并运算符 operator 把 operand 合并到键的值上；只在日志中追加操作数，读取时才计算合并的结果
+    pub fn merge(&mut self, key: String, operator: &str, operand: String) -> Result<()> {
+        self.merge_cf(DEFAULT_FAMILY, key, operator, operand)
+    }
+
+    pub fn merge_cf(&mut self, family: &str, key: String, operator: &str, operand: String) -> Result<()> {
+        self.family(family)?;
+        if !self.options.merge_operators.contains(operator) {
+            return Err(KvError::UnknownMergeOperator(operator.to_owned()));
+        }
+        let (seq, ts) = self.stamp();
+        let cmd = Commend::Merge {
+            key,
+            op: operator.to_owned(),
+            operand,
+            cf: record_family(family),
+            seq,
+            ts,
+        };
+        let range = self.append(&cmd)?;
+        self.stats.ops.merges += 1;
+        self.apply(cmd, range)?;
+        self.compact_if_needed()
+    }
+
+    ///把键的值加上 by，键不存在时从 0 开始；值不是整数时返回 `KvError::MergeFailed`，不写入
+    pub fn incr(&mut self, key: String, by: i64) -> Result<()> {
+        self.incr_cf(DEFAULT_FAMILY, key, by)
+    }
+
+    pub fn decr(&mut self, key: String, by: i64) -> Result<()> {
+        let by = by.checked_neg().ok_or_else(|| KvError::MergeFailed(format!("cannot decrement by {}", by)))?;
+        self.incr_cf(DEFAULT_FAMILY, key, by)
+    }
+
+    pub fn incr_cf(&mut self, family: &str, key: String, by: i64) -> Result<()> {
+        //写入之前先按现有的值合并一次，合并失败的操作数之后每次读取都会失败
+        let existing = self.current_value(family, &key)?;
+        let operand = by.to_string();
+        self.options.merge_operators.merge(ADD_OPERATOR, &key, existing.as_deref(), &operand)?;
+        self.merge_cf(family, key, ADD_OPERATOR, operand)
+    }
+
+    ///按键的顺序返回列族 family 中所有以 prefix 开头的键值对
+    pub fn scan_cf(&mut self, family: &str, prefix: &str) -> Result<Vec<(String, String)>> {
+        let entries = self.scan_expiring_cf(family, prefix)?;
+        Ok(entries.into_iter().map(|(key, value, _)| (key, value)).collect())
+    }
+
+    ///和 `scan_cf` 相同，同时返回值的过期时间（UNIX 时间戳，毫秒）
+    pub(crate) fn scan_expiring_cf(
+        &mut self,
+        family: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, String, Option<u64>)>> {
+        self.stats.ops.scans += 1;
+        let index = &self.family(family)?.index;
+        let Some(positions) = index.prefix(prefix) else {
+            //指纹索引中没有键，只能读取所有记录
+            let mut entries = Vec::new();
+            for cmd_pos in index.positions() {
+                let cmd = self.read_command(cmd_pos)?;
+                let key = match &cmd {
+                    Commend::Set { key, .. } | Commend::SetBlob { key, .. } | Commend::Merge { key, .. }
+                        if key.starts_with(prefix) =>
+                    {
+                        key.clone()
+                    }
+                    Commend::Set { .. } | Commend::SetBlob { .. } | Commend::Merge { .. } => continue,
+                    _ => return Err(KvError::UnexpectedCommandType),
+                };
+                let expires = expires_of(&cmd);
+                if let Some(value) = self.value_of(family, cmd)? {
+                    entries.push((key, value, expires));
                 }
-           
+            }
+            entries.sort_unstable();
+            return Ok(entries);
+        };
+        let mut entries = Vec::with_capacity(positions.len());
+        for (key, cmd_pos) in positions {
+            let cmd = self.read_command(cmd_pos)?;
+            let expires = expires_of(&cmd);
+            if let Some(value) = self.value_of(family, cmd)? {
+                entries.push((key, value, expires));
+            }
         }
-        //达到compaction阈值
-        if self.uncompaction>COMPACTION_THRESHOLD{
-                self.compaction()?;
+        Ok(entries)
+    }
+
+    ///键还留在日志中的所有版本，按序号排序，删除记录的值为 None
+    pub fn history(&self, key: &str) -> Result<Vec<KeyVersion>> {
+        self.history_cf(DEFAULT_FAMILY, key)
+    }
+
+    pub fn history_cf(&self, family: &str, key: &str) -> Result<Vec<KeyVersion>> {
+        self.family(family)?;
+        history::key_history(&self.path, &self.options, family, key)
+    }
+
+    ///读取键在 at 时的值；那时的版本已经被 compaction 删除时返回 None
+    pub fn get_at(&self, key: &str, at: At) -> Result<Option<String>> {
+        self.get_at_cf(DEFAULT_FAMILY, key, at)
+    }
+
+    pub fn get_at_cf(&self, family: &str, key: &str, at: At) -> Result<Option<String>> {
+        Ok(history::value_at(&self.history_cf(family, key)?, at))
+    }
+
+    ///删除 [start, end) 中的所有键，日志中只写一条范围删除记录，返回删除的键数
+    pub fn remove_range(&mut self, range: Range<&str>) -> Result<u64> {
+        self.remove_range_cf(DEFAULT_FAMILY, range)
+    }
+
+    pub fn remove_range_cf(&mut self, family: &str, range: Range<&str>) -> Result<u64> {
+        self.delete_range(family, range.start.to_owned(), Some(range.end.to_owned()))
+    }
+
+    ///删除所有以 prefix 开头的键，返回删除的键数
+    pub fn remove_prefix(&mut self, prefix: &str) -> Result<u64> {
+        self.remove_prefix_cf(DEFAULT_FAMILY, prefix)
+    }
+
+    pub fn remove_prefix_cf(&mut self, family: &str, prefix: &str) -> Result<u64> {
+        self.delete_range(family, prefix.to_owned(), prefix_end(prefix))
+    }
+
+    ///把 value 编码成 JSON 后写入
+    pub fn set_typed<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<()> {
+        self.set_typed_cf(DEFAULT_FAMILY, key, value)
+    }
+
+    pub fn set_typed_cf<T: Serialize + ?Sized>(&mut self, family: &str, key: String, value: &T) -> Result<()> {
+        let value = typed::encode(value)?;
+        self.set_cf(family, key, value)
+    }
+
+    ///读取 `set_typed` 写入的值，值不是 T 时返回 `KvError::TypeMismatch`
+    pub fn get_typed<T: DeserializeOwned>(&mut self, key: String) -> Result<Option<T>> {
+        self.get_typed_cf(DEFAULT_FAMILY, key)
+    }
+
+    pub fn get_typed_cf<T: DeserializeOwned>(&mut self, family: &str, key: String) -> Result<Option<T>> {
+        match self.get_cf(family, key.clone())? {
+            Some(value) => typed::decode(&key, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    ///以 prefix 开头的键的类型化视图
+    pub fn typed_tree<T: Serialize + DeserializeOwned>(&mut self, prefix: &str) -> TypedTree<'_, T> {
+        TypedTree::new(self, DEFAULT_FAMILY, prefix)
+    }
+
+    pub fn typed_tree_cf<T: Serialize + DeserializeOwned>(
+        &mut self,
+        family: &str,
+        prefix: &str,
+    ) -> TypedTree<'_, T> {
+        TypedTree::new(self, family, prefix)
+    }
+
+    ///按顺序返回多个键的值；按日志中的位置排序后读取，减少随机读
+    pub fn multi_get(&mut self, keys: &[String]) -> Result<Vec<Option<String>>> {
+        self.multi_get_cf(DEFAULT_FAMILY, keys)
+    }
+
+    pub fn multi_get_cf(&mut self, family: &str, keys: &[String]) -> Result<Vec<Option<String>>> {
+        self.family(family)?;
+        self.stats.ops.gets += keys.len() as u64;
+        let cached = family == DEFAULT_FAMILY;
+        let mut values = vec![None; keys.len()];
+        let mut positions = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(value) = self.cache.as_mut().filter(|_| cached).and_then(|cache| cache.get(key)) {
+                values[i] = Some(value);
+            } else if let Some(cmd_pos) = self.lookup(family, key)? {
+                positions.push((cmd_pos, i));
+            }
+        }
+        positions.sort_unstable_by_key(|(cmd_pos, _)| (cmd_pos.gen, cmd_pos.pos));
+        for (cmd_pos, i) in positions {
+            let cmd = self.read_command(cmd_pos)?;
+            let cached = cached && !has_expiry(&cmd);
+            values[i] = self.value_of(family, cmd)?;
+            if let (Some(value), Some(cache)) = (&values[i], self.cache.as_mut().filter(|_| cached)) {
+                cache.insert(keys[i].clone(), value.clone());
+            }
+        }
+        Ok(values)
+    }
+
+    ///写入多个键值对，所有记录一起写入日志，只刷新一次
+    pub fn multi_set(&mut self, pairs: Vec<(String, String)>) -> Result<()> {
+        self.multi_set_cf(DEFAULT_FAMILY, pairs)
+    }
+
+    pub fn multi_set_cf(&mut self, family: &str, pairs: Vec<(String, String)>) -> Result<()> {
+        let batch = pairs
+            .into_iter()
+            .fold(WriteBatch::new(), |batch, (key, value)| batch.set(family, key, value));
+        self.write(batch)
+    }
+
+    ///原子地执行批次中的所有写入：批次写成一组连续的记录，没有完整写入的批次在打开时被丢弃
+    pub fn write(&mut self, batch: WriteBatch) -> Result<()> {
+        //写入日志之前检查列族并确定要写的记录，批次中后面的操作能看到前面的操作
+        let mut exists: HashMap<(String, String), bool> = HashMap::new();
+        let mut cmds = Vec::with_capacity(batch.ops.len() + 1);
+        cmds.push(Commend::Batch { len: 0 });
+        for (family, key, value) in batch.ops {
+            self.family(&family)?;
+            let slot = (family, key);
+            //只有删除需要知道键是否存在
+            let existed = match exists.get(&slot) {
+                Some(&existed) => existed,
+                None if value.is_some() => true,
+                None => self.contains(&slot.0, &slot.1)?,
+            };
+            exists.insert(slot.clone(), value.is_some());
+            let (family, key) = slot;
+            if value.is_none() && !existed {
+                continue;
+            }
+            let cmd = match value {
+                Some(value) => self.set_command(&family, key, value)?,
+                None => {
+                    let (seq, ts) = self.stamp();
+                    Commend::Remove {
+                        key,
+                        cf: record_family(&family),
+                        seq,
+                        ts,
+                    }
+                }
+            };
+            cmds.push(cmd);
+        }
+        if cmds.len() == 1 {
+            return Ok(());
+        }
+        cmds[0] = Commend::Batch { len: cmds.len() - 1 };
+        let ranges = self.append_all(&cmds)?;
+        for (cmd, range) in cmds.into_iter().zip(ranges) {
+            match cmd {
+                Commend::Set { .. } | Commend::SetBlob { .. } => self.stats.ops.sets += 1,
+                Commend::Remove { .. } => self.stats.ops.removes += 1,
+                _ => {}
+            }
+            self.apply(cmd, range)?;
+        }
+        self.compact_if_needed()
+    }
+
+    ///创建列族，列族的定义保存在数据目录中
+    pub fn create_family(&mut self, name: &str, options: FamilyOptions) -> Result<()> {
+        if name.is_empty() || self.families.contains_key(name) {
+            return Err(KvError::FamilyExists(name.to_owned()));
+        }
+        let cmd = Commend::CreateFamily {
+            cf: name.to_owned(),
+            options,
+        };
+        let range = self.append(&cmd)?;
+        self.apply(cmd, range)?;
+        self.save_families()
+    }
+
+    ///删除列族和其中所有的键，默认列族不能删除
+    pub fn drop_family(&mut self, name: &str) -> Result<()> {
+        if name == DEFAULT_FAMILY {
+            return Err(KvError::DropDefaultFamily);
+        }
+        self.family(name)?;
+        let cmd = Commend::DropFamily { cf: name.to_owned() };
+        let range = self.append(&cmd)?;
+        self.apply(cmd, range)?;
+        self.families.remove(name);
+        self.save_families()
+    }
+
+    ///所有列族及其配置，包括默认列族
+    pub fn families(&self) -> Vec<(String, FamilyOptions)> {
+        self.families
+            .iter()
+            .map(|(name, family)| (name.clone(), family.options))
+            .collect()
+    }
+
+    ///用 entries（按键排序的键、值、过期时间）替换列族 family 的数据，只写入有变化的键
+    pub(crate) fn replace_all(&mut self, family: &str, entries: Vec<(String, String, Option<u64>)>) -> Result<()> {
+        let mut local = self.scan_expiring_cf(family, "")?.into_iter().peekable();
+        for (key, value, expires) in entries {
+            //删除 entries 中没有的键（两边都按键排序）
+            while let Some((local_key, ..)) = local.next_if(|(local_key, ..)| *local_key < key) {
+                self.remove_cf(family, local_key)?;
+            }
+            let unchanged = |(local_key, local_value, local_expires): &(String, String, Option<u64>)| {
+                *local_key == key && *local_value == value && *local_expires == expires
+            };
+            if local.next_if(unchanged).is_none() {
+                local.next_if(|(local_key, ..)| *local_key == key);
+                self.set_expiring_cf(family, key, value, expires)?;
+            }
+        }
+        for (local_key, ..) in local {
+            self.remove_cf(family, local_key)?;
         }
         Ok(())
     }
-    ///get a key/value pair from the store
-    pub fn get(&mut self, key: String) -> Result<Option<String>> {
-        //1、判断有没有key
-        if !self.index.contains_key(&key) {
-            return Ok(None);
+
+    ///在值（JSON 对象）的字段 path 上创建二级索引，已有的值会立即被索引
+    pub fn create_index(&mut self, name: &str, path: &str) -> Result<()> {
+        self.secondary.create(IndexDef::new(name, path)?)?;
+        let mut secondary = std::mem::take(&mut self.secondary);
+        let result = self.index_all(&mut secondary);
+        self.secondary = secondary;
+        if let Err(e) = result {
+            self.secondary.drop_index(name)?;
+            return Err(e);
         }
-        // 2、根据CommendPos读取数据
-        let cmd_pos = self.index.get(&key).unwrap();
-        let  reader = self.readers.get_mut(&cmd_pos.gen).unwrap();
-        reader.seek(SeekFrom::Start(cmd_pos.pos))?;
-        let command_reader = reader.take(cmd_pos.len);
-        if let Commend::Set { value, .. } = serde_json::from_reader(command_reader)? {
-            Ok(Some(value))
-        } else {
-            Err(KvError::UnexpectedCommandType)
+        self.secondary.save(self.options.fs(), &self.path)
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> Result<()> {
+        self.secondary.drop_index(name)?;
+        self.secondary.save(self.options.fs(), &self.path)
+    }
+
+    ///所有二级索引的定义
+    pub fn indexes(&self) -> Vec<IndexDef> {
+        self.secondary.defs()
+    }
+
+    ///二级索引 index 中字段值为 value 的所有键，按键排序；数字和布尔值按 JSON 文本比较
+    pub fn find_by(&self, index: &str, value: &str) -> Result<Vec<String>> {
+        self.secondary.find(index, value)
+    }
+
+    ///把所有的值写入二级索引
+    fn index_all(&mut self, secondary: &mut SecondaryIndexes) -> Result<()> {
+        for cmd_pos in self.families[DEFAULT_FAMILY].index.positions() {
+            match self.read_command(cmd_pos)? {
+                Commend::Set { key, value, .. } => secondary.set(&key, &value),
+                //二进制的值不是 JSON，不会被索引
+                Commend::SetBlob { key, blob, .. } => match vlog::read_value(&self.path, &self.options, &key, &blob) {
+                    Ok(value) => secondary.set(&key, &value),
+                    Err(KvError::BinaryValue(_)) => {}
+                    Err(e) => return Err(e),
+                },
+                _ => {}
+            }
         }
+        Ok(())
     }
-    ///remove a key/value pair from the
-    pub fn remove(&mut self, key: String) -> Result<()> {
-        //判断键值索引是否包含该键
-        if self.index.contains_key(&key) {
-            //1、在日志中存入命令
-            let rm_cmd = Commend::remove(key);
-            serde_json::to_writer(&mut self.writer, &rm_cmd)?;
-            self.writer.flush()?;
-            //2、删除键值索引里面的值
-            if let Commend::Remove { key } = rm_cmd {
-                let old_cmd = self.index.remove(&key).expect("Key not found");
-                self.uncompaction += old_cmd.len;
+
+    ///订阅以 prefix 开头的键的变更，每次写入成功后发送事件
+    pub fn watch(&mut self, prefix: &str) -> Receiver<WatchEvent> {
+        self.subscribe(Some(DEFAULT_FAMILY), prefix)
+    }
+
+    ///订阅列族 family（None 表示所有列族）中以 prefix 开头的键的变更
+    pub(crate) fn subscribe(&mut self, family: Option<&str>, prefix: &str) -> Receiver<WatchEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.push(Watcher {
+            family: family.map(str::to_owned),
+            prefix: prefix.to_owned(),
+            sender: WatchSender::Event(sender),
+        });
+        receiver
+    }
+
+    ///订阅所有列族的变更，每次写入的事件作为一组发送，原子批次不会被拆开
+    pub(crate) fn subscribe_groups(&mut self) -> Receiver<Vec<WatchEvent>> {
+        let (sender, receiver) = mpsc::channel();
+        self.watchers.push(Watcher {
+            family: None,
+            prefix: String::new(),
+            sender: WatchSender::Group(sender),
+        });
+        receiver
+    }
+
+    ///先重放 from 之后的变更，再继续接收新的变更，消费者重启后不会漏掉事件
+    pub fn watch_from(&mut self, prefix: &str, from: LogPosition) -> Result<Receiver<WatchEvent>> {
+        let events = watch::read_changes(&self.path, &self.options, Some(DEFAULT_FAMILY), prefix, Some(from))?;
+        let receiver = self.watch(prefix);
+        self.watchers.last().unwrap().notify(&events);
+        Ok(receiver)
+    }
+
+    ///关闭存储：日志和值日志落盘，保存变化了的统计信息，返回其中的错误
+    pub fn close(mut self) -> Result<()> {
+        self.writer.sync()?;
+        self.value_log.sync()?;
+        self.stats.save_if_changed(self.options.fs(), &self.path)
+    }
+
+    ///存储的统计信息；平均值大小需要读取所有的值
+    pub fn stats(&mut self) -> Result<StoreStats> {
+        let generations = self.generations()?;
+        let mut key_bytes = 0;
+        let mut value_bytes = 0;
+        let positions: Vec<_> = self.families.values().flat_map(|family| family.index.positions()).collect();
+        for cmd_pos in positions {
+            match self.read_command(cmd_pos)? {
+                Commend::Set { key, value, .. } => {
+                    key_bytes += key.len() as u64;
+                    value_bytes += value.len() as u64;
+                }
+                Commend::SetBlob { key, blob, .. } => {
+                    key_bytes += key.len() as u64;
+                    value_bytes += blob.size;
+                }
+                _ => {}
             }
-            Ok(())
-        } else {
-            Err(KvError::KeyNotFound)
         }
+        let live_keys = self.families.values().map(|family| family.index.len() as u64).sum();
+        let average = |bytes: u64| if live_keys == 0 { 0.0 } else { bytes as f64 / live_keys as f64 };
+        Ok(StoreStats {
+            live_keys,
+            total_bytes: generations.iter().map(|generation| generation.bytes).sum(),
+            stale_bytes: self.uncompaction,
+            generations,
+            value_logs: vlog::sorted_files(self.options.fs(), &self.path)?
+                .into_iter()
+                .map(|gen| {
+                    Ok(GenerationStats {
+                        gen,
+                        bytes: self.options.fs().len(&vlog::vlog_path(&self.path, gen))?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            avg_key_bytes: average(key_bytes),
+            avg_value_bytes: average(value_bytes),
+            //任何一个列族转换成指纹模式时报告指纹模式
+            index_mode: self
+                .families
+                .values()
+                .map(|family| family.index.mode())
+                .find(|&mode| mode == IndexMode::Hashed)
+                .unwrap_or(IndexMode::Full),
+            index_bytes: self.families.values().map(|family| family.index.memory_bytes()).sum(),
+            families: self
+                .families
+                .iter()
+                .map(|(name, family)| FamilyStats {
+                    name: name.clone(),
+                    live_keys: family.index.len() as u64,
+                    stale_bytes: family.uncompaction,
+                    options: family.options,
+                })
+                .collect(),
+            cache: self.cache.as_ref().map(ValueCache::stats),
+            last_compaction: self.stats.last_compaction,
+            ops: self.stats.ops,
+        })
+    }
+
+    ///所有日志文件及其大小，按代号排序
+    fn generations(&self) -> Result<Vec<GenerationStats>> {
+        let mut gens: Vec<u64> = self.readers.keys().copied().collect();
+        gens.sort_unstable();
+        gens.into_iter()
+            .map(|gen| {
+                Ok(GenerationStats {
+                    gen,
+                    bytes: self.options.fs().len(&log_path(&self.path, gen))?,
+                })
+            })
+            .collect()
+    }
+
+    ///日志当前的末尾位置，之后的写入从这里开始
+    pub fn position(&self) -> LogPosition {
+        LogPosition {
+            gen: self.current_gen,
+            offset: self.writer.pos,
+        }
+    }
+
+    ///不打开 KvStore，直接读取目录中 from 之后的变更（from 为 None 时从头读取）
+    pub fn read_changes(
+        path: impl AsRef<Path>,
+        options: &StoreOptions,
+        prefix: &str,
+        from: Option<LogPosition>,
+    ) -> Result<Vec<WatchEvent>> {
+        watch::read_changes(path.as_ref(), options, Some(DEFAULT_FAMILY), prefix, from)
+    }
+
+    ///和 `read_changes` 相同，读取列族 family 中的变更
+    pub fn read_family_changes(
+        path: impl AsRef<Path>,
+        options: &StoreOptions,
+        family: &str,
+        prefix: &str,
+        from: Option<LogPosition>,
+    ) -> Result<Vec<WatchEvent>> {
+        watch::read_changes(path.as_ref(), options, Some(family), prefix, from)
+    }
+
+    ///不打开 KvStore，检查目录中的每个日志文件，报告无法读取的记录
+    pub fn verify(path: impl AsRef<Path>, options: &StoreOptions) -> Result<VerifyReport> {
+        repair::verify(path.as_ref(), options)
+    }
+
+    ///把目录中所有能读取的记录写成一个新的日志文件，原来的日志移到 `corrupt/` 子目录
+    ///
+    ///不能在 KvStore 打开时执行
+    pub fn repair(path: impl AsRef<Path>, options: &StoreOptions) -> Result<RepairReport> {
+        repair::repair(path.as_ref(), options)
     }
+
     ///初始化KvStore
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
+        KvStore::open_with(path, StoreOptions::default())
+    }
+
+    ///使用自定义配置初始化KvStore
+    pub fn open_with(path: impl Into<PathBuf>, mut options: StoreOptions) -> Result<KvStore> {
         // 拿到路径
         let path = path.into();
         // 如果目录不存在，则级联创建目录
-        fs::create_dir_all(&path)?;
+        options.fs().create_dir_all(&path)?;
         // 创建reader 和 index
-        let mut readers: HashMap<u64, BufReaderWithPos<File>> = HashMap::new();
-        let mut index: BTreeMap<String, CommandPos> = BTreeMap::new();
-        let mut uncompaction = 0 as u64;
+        let mut readers: HashMap<u64, LogReader> = HashMap::new();
+        let mut headers: HashMap<u64, LogHeader> = HashMap::new();
+        let defined = load_families(options.fs(), &path)?;
+        let mut families: BTreeMap<String, Family> = defined
+            .iter()
+            .map(|(name, &family_options)| (name.clone(), Family::new(family_options, options.index_budget)))
+            .collect();
+        families.insert(DEFAULT_FAMILY.to_owned(), Family::new(FamilyOptions::default(), options.index_budget));
+        let mut uncompaction = 0;
         // 获取数据文件夹下的所有日志文件的代号
-        let gen_list = sorted_gen_list(&path)?;
+        let gen_list = sorted_gen_list(options.fs(), &path)?;
         for &gen in &gen_list {
-            let mut reader = BufReaderWithPos::new(File::open(log_path(&path, gen))?)?;
+            let gen_path = log_path(&path, gen);
+            let mut file = options.fs().open(&gen_path)?;
+            let header = LogHeader::read(gen, &mut file)?;
+            //日志头部记录的密钥必须在配置中提供
+            let codec = header.codec(gen, &options)?;
+            //随机读取用的读取器先放进去，指纹索引加载时要读取之前记录的键
+            readers.insert(gen, BufReaderWithPos::new(options.fs().open(&gen_path)?)?);
+            headers.insert(gen, header);
+            let mut reader = BufReaderWithPos::new(file)?;
             //从日志文件中加载数据，然后构建内存中的键值索引
-            uncompaction += load(gen, &mut reader, &mut index)?;
-            readers.insert(gen, reader);
+            let key_at = &mut key_reader(&mut readers, &headers, &options);
+            uncompaction += load(gen, header, codec, &mut reader, &mut families, &options, key_at)?;
+        }
+        let last_seq = families.values().map(|family| family.last_seq).max().unwrap_or(0);
+        let next_seq = last_seq.max(history::load_last_seq(options.fs(), &path)?) + 1;
+        //已经删除的列族只剩下空的索引
+        families.retain(|name, family| {
+            name == DEFAULT_FAMILY || defined.contains_key(name) || family.index.len() > 0
+        });
+        //没有指定格式时沿用最新的日志文件的格式
+        if let (None, Some(last)) = (options.format, gen_list.last()) {
+            options.format = Some(headers[last].format);
         }
         let current_gen = gen_list.last().unwrap_or(&0) + 1;
-        let writer = new_log_file(&path, current_gen, &mut readers)?;
-        Ok(KvStore {
+        let writer = new_log_file(&path, current_gen, &options, &mut readers, &mut headers)?;
+        let value_log = ValueLog::open(options.fs(), &path)?;
+        let stats = PersistedStats::load(options.fs(), &path);
+        let cache = options.cache_bytes.map(ValueCache::new);
+        let mut secondary = SecondaryIndexes::load(options.fs(), &path)?;
+        let mut store = KvStore {
             path,
             writer,
+            value_log,
             readers,
-            index,
+            headers,
+            families,
+            cache,
+            secondary: SecondaryIndexes::default(),
             current_gen,
+            next_seq,
             uncompaction,
-        })
+            options,
+            watchers: Vec::new(),
+            stats,
+        };
+        //二级索引只保存定义，打开时重建
+        if !secondary.is_empty() {
+            store.index_all(&mut secondary)?;
+        }
+        store.secondary = secondary;
+        Ok(store)
     }
 
     ///clear stable entry in log
-   pub  fn compaction(&mut self)->Result<()>{
+    pub fn compaction(&mut self) -> Result<()> {
+        self.compact(&HashSet::new()).map(|_| ())
+    }
+
+    ///回收值日志：垃圾超过一半的值日志文件中还在使用的值被复制到新的值日志文件，
+    ///然后通过一次 compaction 更新主日志中的位置并删除这些文件
+    pub fn gc_value_log(&mut self) -> Result<GcReport> {
+        //当前的值日志也可以被回收，之后的值写到新的文件中
+        self.value_log.close()?;
+        let mut live: HashMap<u64, u64> = HashMap::new();
+        let reachable: Vec<CommandPos> = self
+            .families
+            .values()
+            .flat_map(|family| {
+                let chains = family.merge_chains.values().flatten().copied();
+                let retained = family.superseded.iter().map(|&(cmd_pos, _)| cmd_pos);
+                family.index.positions().into_iter().chain(chains).chain(retained)
+            })
+            .collect();
+        for cmd_pos in reachable {
+            if let Commend::SetBlob { blob, .. } = self.read_command(cmd_pos)? {
+                *live.entry(blob.file).or_default() += blob.len;
+            }
+        }
+        let victims: HashMap<u64, u64> = vlog::file_sizes(self.options.fs(), &self.path)?
+            .into_iter()
+            .filter(|(file, bytes)| live.get(file).is_none_or(|&live| live * 2 < *bytes))
+            .collect();
+        let mut report = GcReport::default();
+        if victims.is_empty() {
+            return Ok(report);
+        }
+        report.relocated_bytes = self.compact(&victims.keys().copied().collect())?;
+        //compaction 之后主日志中已经没有指向这些文件的记录
+        for (&file, &bytes) in &victims {
+            self.options.fs().remove_file(&vlog::vlog_path(&self.path, file))?;
+            report.removed_files += 1;
+            report.reclaimed_bytes += bytes;
+        }
+        report.reclaimed_bytes = report.reclaimed_bytes.saturating_sub(report.relocated_bytes);
+        Ok(report)
+    }
+
+    ///compaction；relocate 中的值日志文件里还在使用的值被复制到当前的值日志，返回复制的字节数
+    fn compact(&mut self, relocate: &HashSet<u64>) -> Result<u64> {
+        let started = Instant::now();
+        let bytes_before: u64 = self.generations()?.iter().map(|generation| generation.bytes).sum();
         let compaction_gen = self.current_gen+1;
         self.current_gen +=2;
         self.writer = self.new_log_file(self.current_gen)?;
 
         let mut compaction_writer = self.new_log_file(compaction_gen)?;
+        let compaction_header = self.headers[&compaction_gen];
+        let compaction_codec = compaction_header.codec(compaction_gen, &self.options)?;
         //1、利用键值索引读取日志中的数据，复制到新的日志文件中
-        let mut new_pos = 0 as u64;
-        for cmd_pos in self.index.values_mut(){
-            //拿到对应日志文件的读取器
-            let reader = self.readers.get_mut(&cmd_pos.gen).expect("not read this log file");
-            //将读取器中的pos移到到对应命令的位置
-            if reader.pos != cmd_pos.pos{
-                reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+        let mut new_pos = compaction_writer.pos;
+        //保留时间之前被覆盖的旧版本不再复制
+        let oldest = self.options.retention_ms.map(|retention| now_ms().saturating_sub(retention));
+        let mut relocated = 0;
+        let mut expired = Vec::new();
+        for (name, family) in self.families.iter_mut() {
+            if family.may_expire() {
+                //过期的值不再复制，compaction 之后再写入过期记录
+                family.index.retain(|cmd_pos| {
+                    let cmd = read_at(&mut self.readers, &self.headers, &self.options, cmd_pos)?;
+                    match cmd {
+                        Commend::Set { key, .. } | Commend::SetBlob { key, .. } if is_expired(&cmd) => {
+                            expired.push((name.clone(), key));
+                            Ok(false)
+                        }
+                        _ => Ok(true),
+                    }
+                })?;
+            }
+            family.superseded.retain(|&(_, at)| oldest.is_some_and(|oldest| at >= oldest));
+            //合并链合并成普通的值，写在这个列族的最后；无法合并的原样复制，读取时再报告错误
+            let mut folded = Vec::new();
+            let mut unfolded = HashMap::new();
+            for (key, chain) in std::mem::take(&mut family.merge_chains) {
+                let last_pos = family
+                    .index
+                    .get(&key, &mut key_reader(&mut self.readers, &self.headers, &self.options))?
+                    .expect("merged key is not in the index");
+                let last = read_at(&mut self.readers, &self.headers, &self.options, last_pos)?;
+                let Commend::Merge { cf, seq, ts, .. } = last.clone() else {
+                    return Err(KvError::UnexpectedCommandType);
+                };
+                match resolve(&mut self.readers, &self.headers, &self.options, &self.path, &chain, last) {
+                    Ok(Some(value)) => {
+                        if oldest.is_some() {
+                            let merged = chain.into_iter().chain(Some(last_pos));
+                            family.superseded.extend(merged.map(|cmd_pos| (cmd_pos, ts)));
+                        }
+                        let expires = None;
+                        folded.push((last_pos, Commend::Set { key, value, cf, expires, seq, ts }));
+                    }
+                    Err(e @ KvError::IoError(_)) => return Err(e),
+                    _ => {
+                        unfolded.insert(key, chain);
+                    }
+                }
+            }
+            family.merge_chains = unfolded;
+            let skipped: HashSet<CommandPos> = folded.iter().map(|&(cmd_pos, _)| cmd_pos).collect();
+            //旧版本写在当前的值之前，重新加载时每个键最后读到的仍然是当前的值
+            let retained = family.superseded.iter_mut().map(|(cmd_pos, _)| cmd_pos);
+            let chains = family.merge_chains.values_mut().flatten();
+            let current = family.index.positions_mut().filter(|cmd_pos| !skipped.contains(cmd_pos));
+            for cmd_pos in retained.chain(chains).chain(current) {
+                //拿到对应日志文件的读取器
+                let reader = self.readers.get_mut(&cmd_pos.gen).expect("not read this log file");
+                let header = self.headers[&cmd_pos.gen];
+                let codec = header.codec(cmd_pos.gen, &self.options)?;
+                //回收值日志时需要解码每条记录，找出指向被回收文件的记录
+                let mut moved_blob = None;
+                if !relocate.is_empty() {
+                    let mut cmd = read_command(reader, codec, *cmd_pos)?;
+                    if let Commend::SetBlob { blob, .. } = &mut cmd {
+                        if relocate.contains(&blob.file) {
+                            relocated += blob.len;
+                            *blob = self.value_log.copy(&self.path, &self.options, *blob)?;
+                            moved_blob = Some(cmd);
+                        }
+                    }
+                }
+                let len = if let Some(cmd) = moved_blob {
+                    write_command(&mut compaction_writer, compaction_codec, &cmd)?
+                } else if header == compaction_header && header.key_id.is_none() {
+                    //将读取器中的pos移到到对应命令的位置
+                    if reader.pos != cmd_pos.pos{
+                        reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+                    }
+                    //通过命令长度从缓冲区中读取数据复制到写缓冲区中,enrty_reader是对应命令字节长度的读缓冲区
+                    let mut entry_reader = reader.take(cmd_pos.len);
+                    io::copy(&mut entry_reader,&mut compaction_writer)?
+                } else {
+                    //日志格式或密钥不同（例如密钥轮换），或者加密记录绑定了原来的位置，需要解码后重新编码
+                    let cmd = read_command(reader, codec, *cmd_pos)?;
+                    write_command(&mut compaction_writer, compaction_codec, &cmd)?
+                };
+                //更改key的位置信息为 新的日志文件中的所在位置
+                *cmd_pos = (compaction_gen,(new_pos..new_pos+len)).into();
+                //更新命令在压缩日志中的pos位置
+                new_pos += len;
+            }
+            let mut moved = Vec::with_capacity(folded.len());
+            for (_, cmd) in folded {
+                let len = write_command(&mut compaction_writer, compaction_codec, &cmd)?;
+                if let Commend::Set { key, .. } = cmd {
+                    moved.push((key, (compaction_gen, new_pos..new_pos + len).into()));
+                }
+                new_pos += len;
+            }
+            //指纹索引比较键时可能读取刚写入的记录
+            compaction_writer.flush()?;
+            for (key, cmd_pos) in moved {
+                family.index.insert(key, cmd_pos, &mut key_reader(&mut self.readers, &self.headers, &self.options))?;
             }
-            //通过命令长度从缓冲区中读取数据复制到写缓冲区中,enrty_reader是对应命令字节长度的读缓冲区
-            let mut entry_reader = reader.take(cmd_pos.len);
-            let len = io::copy(&mut entry_reader,&mut compaction_writer)?;
-            //更改key的位置信息为 新的日志文件中的所在位置
-            *cmd_pos = (compaction_gen,(new_pos..new_pos+len)).into();
-            //更新命令在压缩日志中的pos位置
-            new_pos += len;
+            family.uncompaction = 0;
         }
-        compaction_writer.flush()?;
+        //删除旧日志之前新日志和复制的值必须已经落盘，否则崩溃后两边的数据都会丢失
+        self.value_log.sync()?;
+        compaction_writer.sync()?;
+        //被丢掉的记录中可能有最大的序号
+        history::save_last_seq(self.options.fs(), &self.path, self.next_seq - 1)?;
 
         //2、clear stale command file
         //get stale gen 
@@ -194,25 +1277,314 @@ impl KvStore {
         for stale_gen in stale_gens{
             //remove KvStore stale reader 
             self.readers.remove(&stale_gen);
-            fs::remove_file(log_path(&self.path, stale_gen))?;
+            self.headers.remove(&stale_gen);
+            self.options.fs().remove_file(&log_path(&self.path, stale_gen))?;
 
         }
         self.uncompaction = 0;
+        for (family, key) in expired {
+            self.expire(&family, key)?;
+        }
+
+        let bytes_after: u64 = self.generations()?.iter().map(|generation| generation.bytes).sum();
+        self.stats.ops.compactions += 1;
+        self.stats.last_compaction = Some(CompactionInfo {
+            finished_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            duration_ms: started.elapsed().as_millis() as u64,
+            reclaimed_bytes: bytes_before.saturating_sub(bytes_after),
+        });
+        self.stats.save(self.options.fs(), &self.path)?;
+        Ok(relocated)
+    }
+
+    fn new_log_file(&mut self,gen : u64)-> Result<LogWriter>{
+        new_log_file(&self.path, gen, &self.options, &mut self.readers, &mut self.headers)
+    }
+
+    ///在日志中写入一条范围删除记录，end 为 None 时没有上界
+    pub(crate) fn delete_range(&mut self, family: &str, start: String, end: Option<String>) -> Result<u64> {
+        let before = self.family(family)?.index.len();
+        if end.as_ref().is_some_and(|end| *end <= start) {
+            return Ok(0);
+        }
+        let (seq, ts) = self.stamp();
+        let cmd = Commend::RemoveRange {
+            start,
+            end,
+            cf: record_family(family),
+            seq,
+            ts,
+        };
+        let range = self.append(&cmd)?;
+        self.stats.ops.removes += 1;
+        self.apply(cmd, range)?;
+        let removed = (before - self.families[family].index.len()) as u64;
+        self.compact_if_needed()?;
+        Ok(removed)
+    }
+
+    ///写入键值对的记录；值不小于阈值时先把值写入值日志
+    fn set_command(&mut self, family: &str, key: String, value: String) -> Result<Commend> {
+        let expires = self.family(family)?.expires();
+        self.set_command_expiring(family, key, value, expires)
+    }
+
+    ///和 `set_command` 相同，值在 expires（UNIX 时间戳，毫秒）过期
+    fn set_command_expiring(
+        &mut self,
+        family: &str,
+        key: String,
+        value: String,
+        expires: Option<u64>,
+    ) -> Result<Commend> {
+        let cf = record_family(family);
+        if value.len() as u64 >= self.options.value_threshold() {
+            let blob = self.value_log.write(&self.path, &self.options, &mut value.as_bytes())?;
+            let (seq, ts) = self.stamp();
+            return Ok(Commend::SetBlob { key, blob, cf, expires, seq, ts });
+        }
+        let (seq, ts) = self.stamp();
+        Ok(Commend::Set { key, value, cf, expires, seq, ts })
+    }
+
+    ///为一条新的写入分配序号和时间
+    fn stamp(&mut self) -> (u64, u64) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        (seq, now_ms())
+    }
+
+    ///写入键的过期记录，订阅者收到 `Change::Expire`
+    fn expire(&mut self, family: &str, key: String) -> Result<()> {
+        let (seq, ts) = self.stamp();
+        let cmd = Commend::Expire {
+            key,
+            cf: record_family(family),
+            seq,
+            ts,
+        };
+        let range = self.append(&cmd)?;
+        self.apply(cmd, range)
+    }
+
+    ///把命令追加到当前日志文件，返回命令在日志中的位置区间
+    fn append(&mut self, cmd: &Commend) -> Result<Range<u64>> {
+        Ok(self.append_all(std::slice::from_ref(cmd))?.remove(0))
+    }
+
+    ///连续追加多条命令，最后只刷新一次
+    fn append_all(&mut self, cmds: &[Commend]) -> Result<Vec<Range<u64>>> {
+        let mut ranges = Vec::with_capacity(cmds.len());
+        let codec = self.headers[&self.current_gen].codec(self.current_gen, &self.options)?;
+        for cmd in cmds {
+            //获取未插入数据前的pos位置
+            let pos = self.writer.pos;
+            write_command(&mut self.writer, codec, cmd)?;
+            ranges.push(pos..self.writer.pos);
+        }
+        self.writer.flush()?;
+        if self.watchers.is_empty() {
+            return Ok(ranges);
+        }
+        let mut events = Vec::new();
+        for (cmd, range) in cmds.iter().zip(&ranges) {
+            let Some((family, change)) = watch::change_of(&self.path, &self.options, cmd.clone())? else {
+                continue;
+            };
+            events.push(WatchEvent {
+                position: LogPosition {
+                    gen: self.current_gen,
+                    offset: range.end,
+                },
+                family,
+                change,
+            });
+        }
+        //丢弃已经关闭的订阅
+        self.watchers.retain(|watcher| watcher.notify(&events));
+        Ok(ranges)
+    }
+
+    ///把已经写入日志的命令应用到索引
+    fn apply(&mut self, cmd: Commend, range: Range<u64>) -> Result<()> {
+        if let Commend::Set { key, cf: None, .. }
+        | Commend::SetBlob { key, cf: None, .. }
+        | Commend::Remove { key, cf: None, .. }
+        | Commend::Merge { key, cf: None, .. }
+        | Commend::Expire { key, cf: None, .. } = &cmd
+        {
+            if let Some(cache) = &mut self.cache {
+                cache.remove(key);
+            }
+        }
+        match &cmd {
+            Commend::Set { key, value, cf: None, .. } => self.secondary.set(key, value),
+            Commend::SetBlob { key, blob, cf: None, .. } if !self.secondary.is_empty() => {
+                match vlog::read_value(&self.path, &self.options, key, blob) {
+                    Ok(value) => self.secondary.set(key, &value),
+                    Err(KvError::BinaryValue(_)) => self.secondary.remove(key),
+                    Err(e) => return Err(e),
+                }
+            }
+            Commend::Remove { key, cf: None, .. } | Commend::Expire { key, cf: None, .. } => self.secondary.remove(key),
+            Commend::RemoveRange { start, end, cf: None, .. } => {
+                if let Some(cache) = &mut self.cache {
+                    cache.remove_range(start, end.as_deref());
+                }
+                self.secondary.remove_range(start, end.as_deref());
+            }
+            _ => {}
+        }
+        //合并的结果在写入索引之后才能计算
+        let merged = match &cmd {
+            Commend::Merge { key, cf: None, .. } if !self.secondary.is_empty() => Some((key.clone(), cmd.clone())),
+            _ => None,
+        };
+        self.uncompaction += index_command(
+            &mut self.families,
+            &self.options,
+            cmd,
+            (self.current_gen, range).into(),
+            &mut key_reader(&mut self.readers, &self.headers, &self.options),
+        )?;
+        if let Some((key, cmd)) = merged {
+            match self.value_of(DEFAULT_FAMILY, cmd)? {
+                Some(value) => self.secondary.set(&key, &value),
+                None => self.secondary.remove(&key),
+            }
+        }
         Ok(())
     }
 
-    fn new_log_file(&mut self,gen : u64)-> Result<BufWriterWithPos<File>>{
-        new_log_file(&self.path, gen, &mut self.readers)
+    ///某个列族的陈旧记录超过它的阈值时执行 compaction
+    fn compact_if_needed(&mut self) -> Result<()> {
+        if self
+            .families
+            .values()
+            .any(|family| family.uncompaction > family.options.compaction_threshold)
+        {
+            self.compaction()?;
+        }
+        Ok(())
+    }
+
+    fn family(&self, name: &str) -> Result<&Family> {
+        self.families.get(name).ok_or_else(|| KvError::FamilyNotFound(name.to_owned()))
+    }
+
+    fn save_families(&self) -> Result<()> {
+        let defined = self
+            .families
+            .iter()
+            .filter(|(name, _)| *name != DEFAULT_FAMILY)
+            .map(|(name, family)| (name.clone(), family.options))
+            .collect();
+        save_families(self.options.fs(), &self.path, &defined)
     }
 
+    ///在列族的索引中查找键的位置
+    fn lookup(&mut self, family: &str, key: &str) -> Result<Option<CommandPos>> {
+        let index = &self
+            .families
+            .get(family)
+            .ok_or_else(|| KvError::FamilyNotFound(family.to_owned()))?
+            .index;
+        index.get(key, &mut key_reader(&mut self.readers, &self.headers, &self.options))
+    }
+
+    ///列族中是否有这个键（不包括已经过期的值）
+    fn contains(&mut self, family: &str, key: &str) -> Result<bool> {
+        let Some(cmd_pos) = self.lookup(family, key)? else {
+            return Ok(false);
+        };
+        if !self.families[family].may_expire() {
+            return Ok(true);
+        }
+        if !is_expired(&self.read_command(cmd_pos)?) {
+            return Ok(true);
+        }
+        self.expire(family, key.to_owned())?;
+        Ok(false)
+    }
 
+    ///根据命令位置读取并解码命令
+    fn read_command(&mut self, cmd_pos: CommandPos) -> Result<Commend> {
+        read_at(&mut self.readers, &self.headers, &self.options, cmd_pos)
+    }
+
+    ///列族中键的最后一条记录 cmd 对应的值，过期的值为 None 并写入过期记录
+    fn value_of(&mut self, family: &str, cmd: Commend) -> Result<Option<String>> {
+        match cmd {
+            Commend::Set { key, .. } | Commend::SetBlob { key, .. } if is_expired(&cmd) => {
+                self.expire(family, key)?;
+                Ok(None)
+            }
+            Commend::Set { value, .. } => Ok(Some(value)),
+            Commend::SetBlob { key, blob, .. } => vlog::read_value(&self.path, &self.options, &key, &blob).map(Some),
+            Commend::Merge { ref key, .. } => {
+                let chain = self.families[family].merge_chains.get(key).map_or(&[][..], Vec::as_slice);
+                resolve(&mut self.readers, &self.headers, &self.options, &self.path, chain, cmd)
+            }
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+}
+
+///关闭时尽量保存变化了的统计信息，需要知道是否成功时先调用 `close`
+impl Drop for KvStore {
+    fn drop(&mut self) {
+        let _ = self.stats.save_if_changed(self.options.fs(), &self.path);
+    }
+}
+
+///把键值对和它们的过期时间写成一个新的日志文件，并删除目录中旧的日志文件，KvStore::open 可以直接读取
+pub(crate) fn write_snapshot<'a>(
+    path: &Path,
+    options: &StoreOptions,
+    pairs: impl IntoIterator<Item = (&'a String, &'a String, Option<u64>)>,
+) -> Result<()> {
+    let fs = options.fs();
+    fs.create_dir_all(path)?;
+    let stale_gens = sorted_gen_list(fs, path)?;
+    let gen = stale_gens.last().unwrap_or(&0) + 1;
+    let records = pairs.into_iter().map(|(key, value, expires)| Commend::Set {
+        key: key.clone(),
+        value: value.clone(),
+        cf: None,
+        expires,
+        seq: 0,
+        ts: 0,
+    });
+    write_generation(path, gen, options, records)?;
+    for stale_gen in stale_gens {
+        fs.remove_file(&log_path(path, stale_gen))?;
+    }
+    Ok(())
+}
+
+///按配置的格式和密钥把命令写成代号为 gen 的日志文件并落盘
+fn write_generation(
+    path: &Path,
+    gen: u64,
+    options: &StoreOptions,
+    records: impl IntoIterator<Item = Commend>,
+) -> Result<()> {
+    let mut writer = BufWriterWithPos::new(options.fs().create(&log_path(path, gen))?)?;
+    let header = LogHeader::new(options);
+    header.write(&mut writer)?;
+    let codec = header.codec(gen, options)?;
+    for cmd in records {
+        write_command(&mut writer, codec, &cmd)?;
+    }
+    writer.sync()
 }
 
 ///返回指定文件夹下的文件名的u64，再经过排序；例如 1.log、2.log、3.log => 1，2，3
-fn sorted_gen_list(path: &Path) -> Result<Vec<u64>> {
-    let mut gen_list: Vec<u64> = fs::read_dir(path)?
-        .flat_map(|res| -> Result<_> { Ok(res?.path()) })
-        .filter(|path| path.is_file() && path.extension() == Some("log".as_ref()))
+fn sorted_gen_list(fs: &dyn FileSystem, path: &Path) -> Result<Vec<u64>> {
+    let mut gen_list: Vec<u64> = fs
+        .list_files(path)?
+        .into_iter()
+        .filter(|path| path.extension() == Some("log".as_ref()))
         .flat_map(|path| {
             path.file_name()
                 .and_then(OsStr::to_str)
@@ -229,57 +1601,405 @@ fn sorted_gen_list(path: &Path) -> Result<Vec<u64>> {
 fn log_path(dir: &Path, gen: u64) -> PathBuf {
     dir.join(format!("{}.log", gen))
 }
-///返回日志文件的写入器
+///返回日志文件的写入器；配置了密钥时先写入日志头部
 fn new_log_file(
     path: &Path,
     gen: u64,
-    readers: &mut HashMap<u64, BufReaderWithPos<File>>,
-) -> Result<BufWriterWithPos<File>> {
-    let path = log_path(&path, gen);
-    let writer = BufWriterWithPos::new(
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .append(true)
-            .open(&path)?,
-    )?;
-    readers.insert(gen, BufReaderWithPos::new(File::open(&path)?)?);
+    options: &StoreOptions,
+    readers: &mut HashMap<u64, LogReader>,
+    headers: &mut HashMap<u64, LogHeader>,
+) -> Result<LogWriter> {
+    let path = log_path(path, gen);
+    let mut writer = BufWriterWithPos::new(options.fs().append(&path)?)?;
+    let header = LogHeader::new(options);
+    header.write(&mut writer)?;
+    writer.flush()?;
+    readers.insert(gen, BufReaderWithPos::new(options.fs().open(&path)?)?);
+    headers.insert(gen, header);
     Ok(writer)
 }
 
-///  读取数据日志文件，重构键值索引，传入对应文件的读取器reader和全局的键值索引index;返回
+///返回读取日志中某个位置的记录的键的闭包，指纹索引用它比较键
+fn key_reader<'a>(
+    readers: &'a mut HashMap<u64, LogReader>,
+    headers: &'a HashMap<u64, LogHeader>,
+    options: &'a StoreOptions,
+) -> impl FnMut(CommandPos) -> Result<String> + 'a {
+    move |cmd_pos| match read_at(readers, headers, options, cmd_pos)? {
+        Commend::Set { key, .. }
+        | Commend::SetBlob { key, .. }
+        | Commend::Remove { key, .. }
+        | Commend::Merge { key, .. }
+        | Commend::Expire { key, .. } => Ok(key),
+        _ => Err(KvError::UnexpectedCommandType),
+    }
+}
+
+///读取并解码 cmd_pos 位置的命令
+fn read_at(
+    readers: &mut HashMap<u64, LogReader>,
+    headers: &HashMap<u64, LogHeader>,
+    options: &StoreOptions,
+    cmd_pos: CommandPos,
+) -> Result<Commend> {
+    let reader = readers.get_mut(&cmd_pos.gen).expect("not read this log file");
+    let codec = headers[&cmd_pos.gen].codec(cmd_pos.gen, options)?;
+    read_command(reader, codec, cmd_pos)
+}
+
+///把一条已经在日志中的命令应用到列族的索引，返回因此变成陈旧的字节数
+///
+///日志中出现了没有定义的列族时用默认配置创建它；配置了历史保留时间时记下被覆盖的记录
+fn index_command(
+    families: &mut BTreeMap<String, Family>,
+    options: &StoreOptions,
+    cmd: Commend,
+    cmd_pos: CommandPos,
+    key_at: &mut KeyAt,
+) -> Result<u64> {
+    let (name, ts) = match &cmd {
+        Commend::Set { cf, ts, .. }
+        | Commend::SetBlob { cf, ts, .. }
+        | Commend::Remove { cf, ts, .. }
+        | Commend::Merge { cf, ts, .. }
+        | Commend::RemoveRange { cf, ts, .. }
+        | Commend::Expire { cf, ts, .. } => (family_of(cf).to_owned(), *ts),
+        Commend::DropFamily { cf } => (cf.clone(), 0),
+        Commend::Batch { .. } => return Ok(cmd_pos.len),
+        //列族文件中已经有的列族以文件中的配置为准
+        Commend::CreateFamily { cf, options: family_options } => {
+            families
+                .entry(cf.clone())
+                .or_insert_with(|| Family::new(*family_options, options.index_budget));
+            return Ok(cmd_pos.len);
+        }
+    };
+    let family = families
+        .entry(name)
+        .or_insert_with(|| Family::new(FamilyOptions::default(), options.index_budget));
+    family.last_seq = family.last_seq.max(cmd.seq());
+    family.expiring |= has_expiry(&cmd);
+    //合并链中的记录在后面的 Merge 写入时已经算作陈旧，被覆盖时只计算最后一条
+    let (stale, superseded): (u64, Vec<CommandPos>) = match cmd {
+        Commend::Set { key, .. } | Commend::SetBlob { key, .. } => {
+            let chain = family.merge_chains.remove(&key).unwrap_or_default();
+            let old_cmd = family.index.insert(key, cmd_pos, key_at)?;
+            (old_cmd.map_or(0, |old_cmd| old_cmd.len), chain.into_iter().chain(old_cmd).collect())
+        }
+        // remove 所删除的key所在的“插入命令行”已经被压缩，remove本身所在的命令行也没必要存在了
+        Commend::Remove { key, .. } | Commend::Expire { key, .. } => {
+            let chain = family.merge_chains.remove(&key).unwrap_or_default();
+            let old_cmd = family.index.remove(&key, key_at)?;
+            let stale = old_cmd.map_or(0, |old_cmd| old_cmd.len) + cmd_pos.len;
+            (stale, chain.into_iter().chain(old_cmd).chain(Some(cmd_pos)).collect())
+        }
+        Commend::RemoveRange { start, end, .. } => {
+            let removed = family.index.remove_range(&start, end.as_deref(), key_at)?;
+            let mut stale = cmd_pos.len;
+            let mut superseded = Vec::with_capacity(removed.len() + 1);
+            for (key, old_cmd) in removed {
+                stale += old_cmd.len;
+                superseded.extend(family.merge_chains.remove(&key).unwrap_or_default());
+                superseded.push(old_cmd);
+            }
+            superseded.push(cmd_pos);
+            (stale, superseded)
+        }
+        //之前的记录进入合并链，compaction 时和这条记录合并成一个值
+        Commend::Merge { key, .. } => {
+            let old_cmd = family.index.insert(key.clone(), cmd_pos, key_at)?;
+            family.merge_chains.entry(key).or_default().extend(old_cmd);
+            (old_cmd.map_or(0, |old_cmd| old_cmd.len), Vec::new())
+        }
+        _ => {
+            let stale = family.index.positions().iter().map(|old_cmd| old_cmd.len).sum::<u64>() + cmd_pos.len;
+            family.index = KeyDir::new(options.index_budget);
+            family.merge_chains.clear();
+            family.superseded.clear();
+            (stale, Vec::new())
+        }
+    };
+    if options.retention_ms.is_some() {
+        family.superseded.extend(superseded.into_iter().map(|old_cmd| (old_cmd, ts)));
+    }
+    family.uncompaction += stale;
+    Ok(stale)
+}
+
+///把合并链 chain 和最后一条 Merge 记录合并成值
+fn resolve(
+    readers: &mut HashMap<u64, LogReader>,
+    headers: &HashMap<u64, LogHeader>,
+    options: &StoreOptions,
+    dir: &Path,
+    chain: &[CommandPos],
+    last: Commend,
+) -> Result<Option<String>> {
+    let mut value = None;
+    for &cmd_pos in chain {
+        let cmd = vlog::inline(dir, options, read_at(readers, headers, options, cmd_pos)?)?;
+        value = if is_expired(&cmd) { None } else { options.merge_operators.fold(value, cmd)? };
+    }
+    options.merge_operators.fold(value, last)
+}
+
+///  读取数据日志文件，重构各个列族的键值索引；返回可以被 compaction 清除的字节数
+///
+///原子批次的记录全部读到之后才应用，日志末尾不完整的批次被丢弃
 fn load(
     gen: u64,
-    reader: &mut BufReaderWithPos<File>,
-    index: &mut BTreeMap<String, CommandPos>,
+    header: LogHeader,
+    codec: Codec,
+    reader: &mut LogReader,
+    families: &mut BTreeMap<String, Family>,
+    options: &StoreOptions,
+    key_at: &mut KeyAt,
 ) -> Result<u64> {
-    // 1、设置从头读取数据
-    let mut pos = reader.seek(SeekFrom::Start(0))?;
+    // 1、设置从头部之后读取数据
+    let mut pos = reader.seek(SeekFrom::Start(header.len()))?;
+    let mut uncompaction = 0;
+    //正在读取的批次：还差几条记录，已经读到的记录
+    let mut batch: Option<(usize, Vec<(Commend, CommandPos)>)> = None;
     //2、从读取器中反序列数据量，并生成Command的迭代器
-    let mut command_stream = serde_json::Deserializer::from_reader(reader).into_iter::<Commend>();
-    let mut uncompaction = 0 as u64;
+    let mut command_stream = CommandStream::new(reader, codec);
     while let Some(cmd) = command_stream.next() {
         //当前Command在日志中的末尾位置
-        let new_pos = command_stream.byte_offset() as u64;
-        match cmd? {
-            Commend::Set { key, .. } => {
-                if let Some(old_cmd) =  index.insert(key, (gen, pos..new_pos).into()){
-                    uncompaction += old_cmd.len;
-                }}
-            Commend::Remove { key } =>{ 
-               if  let Some(old_cmd) =  index.remove(&key){
-                    uncompaction += old_cmd.len;
+        let new_pos = command_stream.pos();
+        let cmd_pos: CommandPos = (gen, pos..new_pos).into();
+        let cmd = match cmd {
+            Ok(cmd) => cmd,
+            //追加时崩溃留下的不完整的最后一条记录，之前的记录都是完整的；它所在的批次也一起丢弃
+            Err(ref e) if is_incomplete_record(e) => break,
+            Err(e) => return Err(e),
+        };
+        match (cmd, &mut batch) {
+            (Commend::Batch { len }, _) => {
+                uncompaction += cmd_pos.len;
+                batch = Some((len, Vec::with_capacity(len)));
+            }
+            (cmd, Some((remaining, pending))) => {
+                pending.push((cmd, cmd_pos));
+                *remaining -= 1;
+                if *remaining == 0 {
+                    let (_, pending) = batch.take().unwrap();
+                    for (cmd, cmd_pos) in pending {
+                        uncompaction += index_command(families, options, cmd, cmd_pos, key_at)?;
+                    }
                 }
-                // remove 所删除的key所在的“插入命令行”已经被压缩，remove本身所在的命令行也没必要存在了
-                uncompaction += new_pos- pos;
             }
-        };
+            (cmd, None) => uncompaction += index_command(families, options, cmd, cmd_pos, key_at)?,
+        }
         //更新下一个Command的开始位置
         pos = new_pos;
     }
+    if let Some((_, pending)) = batch {
+        uncompaction += pending.iter().map(|(_, cmd_pos)| cmd_pos.len).sum::<u64>();
+    }
     Ok(uncompaction)
 }
 
+// 日志文件头部：magic + 版本号 + 格式编号 + 是否加密 + 密钥标识。未加密的 JSON 日志没有头部，直接是 JSON 命令流
+const LOG_MAGIC: &[u8; 6] = b"KVSLOG";
+const LOG_VERSION: u8 = 1;
+const LOG_HEADER_LEN: u64 = 17;
+
+///日志文件头部，描述日志中记录的编码方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LogHeader {
+    key_id: Option<u64>,
+    format: Format,
+}
+
+impl LogHeader {
+    ///按配置写入新日志时使用的头部
+    fn new(options: &StoreOptions) -> LogHeader {
+        LogHeader {
+            key_id: options.encryption_key.as_ref().map(EncryptionKey::id),
+            format: options.format(),
+        }
+    }
+
+    ///头部在文件中占用的字节数
+    fn len(&self) -> u64 {
+        match (self.format, self.key_id) {
+            (Format::Json, None) => 0,
+            _ => LOG_HEADER_LEN,
+        }
+    }
+
+    fn read<R: Read + Seek>(gen: u64, file: &mut R) -> Result<LogHeader> {
+        let mut buf = Vec::with_capacity(LOG_HEADER_LEN as usize);
+        file.by_ref().take(LOG_HEADER_LEN).read_to_end(&mut buf)?;
+        file.rewind()?;
+        if !buf.starts_with(LOG_MAGIC) {
+            return Ok(LogHeader {
+                key_id: None,
+                format: Format::Json,
+            });
+        }
+        if buf.len() as u64 != LOG_HEADER_LEN || buf[6] != LOG_VERSION {
+            return Err(KvError::InvalidLogHeader { gen });
+        }
+        let format = Format::from_id(buf[7]).ok_or(KvError::InvalidLogHeader { gen })?;
+        let key_id = (buf[8] != 0).then(|| u64::from_le_bytes(buf[9..17].try_into().unwrap()));
+        Ok(LogHeader { key_id, format })
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        if self.len() > 0 {
+            writer.write_all(LOG_MAGIC)?;
+            writer.write_all(&[LOG_VERSION, self.format.id(), self.key_id.is_some() as u8])?;
+            writer.write_all(&self.key_id.unwrap_or(0).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    ///日志中记录的编码方式；日志使用的密钥没有提供时返回 `KvError::WrongKey`
+    fn codec<'a>(&self, gen: u64, options: &'a StoreOptions) -> Result<Codec<'a>> {
+        let key = match self.key_id {
+            Some(key_id) => Some(options.key(key_id).ok_or(KvError::WrongKey { gen, key_id })?),
+            None => None,
+        };
+        Ok(Codec {
+            format: self.format,
+            key,
+            gen,
+        })
+    }
+}
+
+// 有长度的记录前面的 4 字节长度和 4 字节校验和
+pub(crate) const FRAME_HEADER_LEN: u64 = 8;
+
+///编码并写入一条命令，返回写入的字节数
+///
+///未加密的 JSON 直接写 JSON；其它情况写 4 字节长度 + 4 字节 CRC32 + 编码后的记录（加密时是 nonce + 密文）
+fn write_command<W: Write + Seek>(writer: &mut BufWriterWithPos<W>, codec: Codec, cmd: &Commend) -> Result<u64> {
+    if !codec.framed() {
+        let json = serde_json::to_vec(cmd)?;
+        writer.write_all(&json)?;
+        return Ok(json.len() as u64);
+    }
+    let body = codec.encode(cmd, writer.pos)?;
+    //长度被截断的记录写进去之后就读不回来了
+    let len = u32::try_from(body.len()).map_err(|_| KvError::RecordTooLarge(body.len() as u64))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&crc32fast::hash(&body).to_le_bytes())?;
+    writer.write_all(&body)?;
+    Ok(FRAME_HEADER_LEN + body.len() as u64)
+}
+
+///读取并解码 cmd_pos 位置的命令
+fn read_command(reader: &mut LogReader, codec: Codec, cmd_pos: CommandPos) -> Result<Commend> {
+    //将读取器中的pos移到到对应命令的位置
+    reader.seek(SeekFrom::Start(cmd_pos.pos))?;
+    let mut command_reader = reader.take(cmd_pos.len);
+    if !codec.framed() {
+        return Ok(serde_json::from_reader(command_reader)?);
+    }
+    let mut buf = Vec::with_capacity(cmd_pos.len as usize);
+    command_reader.read_to_end(&mut buf)?;
+    let (header, body) = buf
+        .split_first_chunk::<{ FRAME_HEADER_LEN as usize }>()
+        .ok_or(KvError::Corruption("truncated record".to_owned()))?;
+    check_frame(header, body)?;
+    codec.decode(body, cmd_pos.pos)
+}
+
+///按顺序解码日志中的命令，同时记录读取到的位置
+enum CommandStream<'a> {
+    Json {
+        stream: serde_json::StreamDeserializer<'a, serde_json::de::IoRead<&'a mut LogReader>, Commend>,
+        start: u64,
+    },
+    Framed {
+        reader: &'a mut LogReader,
+        codec: Codec<'a>,
+    },
+}
+
+impl<'a> CommandStream<'a> {
+    fn new(reader: &'a mut LogReader, codec: Codec<'a>) -> Self {
+        if codec.framed() {
+            return CommandStream::Framed { reader, codec };
+        }
+        CommandStream::Json {
+            start: reader.pos,
+            stream: serde_json::Deserializer::from_reader(reader).into_iter(),
+        }
+    }
+
+    ///已读取的最后一条命令的末尾位置
+    fn pos(&self) -> u64 {
+        match self {
+            CommandStream::Json { stream, start } => start + stream.byte_offset() as u64,
+            CommandStream::Framed { reader, .. } => reader.pos,
+        }
+    }
+
+    fn next_framed(reader: &mut LogReader, codec: Codec) -> Option<Result<Commend>> {
+        let offset = reader.pos;
+        let mut header = [0u8; FRAME_HEADER_LEN as usize];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            //只写了一部分头部也是不完整的记录，和没有读到任何字节区分开
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && reader.pos == offset => return None,
+            Err(e) => return Some(Err(e.into())),
+        }
+        let result = read_frame(reader, frame_len(&header))
+            .map_err(KvError::from)
+            .and_then(|body| check_frame(&header, &body).map(|()| body))
+            .and_then(|body| codec.decode(&body, offset));
+        Some(result)
+    }
+}
+
+///记录头部中的长度
+pub(crate) fn frame_len(header: &[u8; FRAME_HEADER_LEN as usize]) -> u32 {
+    u32::from_le_bytes(header[..4].try_into().unwrap())
+}
+
+///检查记录体的校验和，和头部中保存的不一致时返回 `KvError::Corruption`
+pub(crate) fn check_frame(header: &[u8; FRAME_HEADER_LEN as usize], body: &[u8]) -> Result<()> {
+    if crc32fast::hash(body).to_le_bytes() != header[4..] {
+        return Err(KvError::Corruption("record checksum mismatch".to_owned()));
+    }
+    Ok(())
+}
+
+///读出长度为 len 的记录体
+///
+///长度前缀可能已经损坏，不按它预先分配内存：缓冲区只随实际读到的字节增长，
+///文件中剩下的字节不够时返回 `UnexpectedEof`，当作不完整的记录处理
+pub(crate) fn read_frame(reader: &mut impl Read, len: u32) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader.take(len as u64).read_to_end(&mut body)?;
+    if body.len() < len as usize {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"));
+    }
+    Ok(body)
+}
+
+///读到日志末尾时记录还不完整：正在追加，或者追加时崩溃
+pub(crate) fn is_incomplete_record(error: &KvError) -> bool {
+    match error {
+        KvError::SerdeErr(e) => e.is_eof(),
+        KvError::IoError(e) => e.kind() == io::ErrorKind::UnexpectedEof,
+        _ => false,
+    }
+}
+
+impl Iterator for CommandStream<'_> {
+    type Item = Result<Commend>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            CommandStream::Json { stream, .. } => stream.next().map(|cmd| Ok(cmd?)),
+            CommandStream::Framed { reader, codec } => CommandStream::next_framed(reader, *codec),
+        }
+    }
+}
+
 ///带有位置追踪功能的缓冲写入器
 #[derive(Debug)]
 struct BufWriterWithPos<W: Write + Seek> {
@@ -290,7 +2010,7 @@ struct BufWriterWithPos<W: Write + Seek> {
 impl<W: Write + Seek> BufWriterWithPos<W> {
     ///BufWriterWithPos对象的构造函数-关联函数
     fn new(mut inner: W) -> Result<Self> {
-        let pos = inner.seek(SeekFrom::Current(0))?;
+        let pos = inner.stream_position()?;
         Ok(BufWriterWithPos {
             writer: BufWriter::new(inner),
             pos,
@@ -311,6 +2031,15 @@ impl<W: Write + Seek> Write for BufWriterWithPos<W> {
     }
 }
 
+impl LogWriter {
+    ///写出缓冲区并落盘
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_mut().sync()?;
+        Ok(())
+    }
+}
+
 ///缓冲写入器实现随机访问操作
 impl<W: Write + Seek> Seek for BufWriterWithPos<W> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
@@ -328,7 +2057,7 @@ struct BufReaderWithPos<R: Read + Seek> {
 /// 读缓冲区的构造函数
 impl<R: Read + Seek> BufReaderWithPos<R> {
     fn new(mut inner: R) -> Result<Self> {
-        let pos = inner.seek(SeekFrom::Current(0))?;
+        let pos = inner.stream_position()?;
         Ok(BufReaderWithPos {
             reader: BufReader::new(inner),
             pos,
@@ -351,8 +2080,12 @@ impl<R: Read + Seek> Seek for BufReaderWithPos<R> {
     }
 }
 
+// 日志文件的读取器和写入器
+type LogReader = BufReaderWithPos<Box<dyn FsReader>>;
+type LogWriter = BufWriterWithPos<Box<dyn FsWriter>>;
+
 ///命令在日志中的位置
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct CommandPos {
     gen: u64,
     pos: u64,
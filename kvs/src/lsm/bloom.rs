@@ -0,0 +1,65 @@
+///SSTable 的布隆过滤器，用于在读取数据块之前排除不存在的键
+#[derive(Debug)]
+pub(super) struct Bloom {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+// 每个键占用的位数，约 1% 的误判率
+const BITS_PER_KEY: usize = 10;
+
+impl Bloom {
+    ///根据键的哈希值构建过滤器
+    pub(super) fn build(key_hashes: &[u64]) -> Bloom {
+        let nbits = (key_hashes.len() * BITS_PER_KEY).max(64);
+        let mut bloom = Bloom {
+            bits: vec![0; nbits.div_ceil(8)],
+            // k = ln2 * bits/key
+            hashes: ((BITS_PER_KEY as f64 * 0.69) as u32).clamp(1, 30),
+        };
+        for &hash in key_hashes {
+            for bit in bloom.bit_positions(hash) {
+                bloom.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        bloom
+    }
+
+    pub(super) fn may_contain(&self, key: &str) -> bool {
+        self.bit_positions(hash_key(key))
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    ///编码：最后一个字节是哈希函数个数
+    pub(super) fn encode(&self) -> Vec<u8> {
+        let mut buf = self.bits.clone();
+        buf.push(self.hashes as u8);
+        buf
+    }
+
+    pub(super) fn decode(buf: &[u8]) -> Option<Bloom> {
+        let (&hashes, bits) = buf.split_last()?;
+        if bits.is_empty() {
+            return None;
+        }
+        Some(Bloom {
+            bits: bits.to_vec(),
+            hashes: hashes as u32,
+        })
+    }
+
+    // 双重哈希：h1 + i*h2
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let nbits = (self.bits.len() * 8) as u64;
+        let h1 = hash;
+        let h2 = hash.rotate_right(17) | 1;
+        (0..self.hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % nbits) as usize)
+    }
+}
+
+///FNV-1a 哈希，写入磁盘的过滤器不能依赖标准库哈希的实现细节
+pub(super) fn hash_key(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
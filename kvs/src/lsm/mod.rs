@@ -0,0 +1,462 @@
+//! LSM-tree 存储引擎：memtable + 预写日志，刷盘生成有序不可变的 SSTable，分层 compaction
+//!
+//! 目录结构：
+//! - `lsm.wal`：当前 memtable 的预写日志，格式与 KvStore 的日志相同（JSON 命令流）
+//! - `<id>.sst`：SSTable，带数据块索引和布隆过滤器
+//! - `MANIFEST`：每一层包含哪些 SSTable
+
+mod bloom;
+mod sstable;
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::iter::Peekable;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+
+use self::sstable::{table_path, Entry, TableBuilder, TableMeta, TableReader};
+use crate::{is_incomplete_record, Commend, KvError, KvsEngine, Result};
+
+// 层数，最后一层的数据不再向下合并
+const MAX_LEVELS: usize = 7;
+// 相邻两层容量的倍数
+const LEVEL_MULTIPLIER: u64 = 10;
+
+const WAL_FILE: &str = "lsm.wal";
+const MANIFEST_FILE: &str = "MANIFEST";
+
+///LsmStore 的可选配置
+#[derive(Debug, Clone)]
+pub struct LsmOptions {
+    pub(crate) memtable_size: usize,
+    pub(crate) block_size: usize,
+    pub(crate) table_size: u64,
+    pub(crate) level0_limit: usize,
+    pub(crate) level_base_size: u64,
+}
+
+impl Default for LsmOptions {
+    fn default() -> LsmOptions {
+        LsmOptions {
+            memtable_size: 4 * 1024 * 1024,
+            block_size: 4 * 1024,
+            table_size: 2 * 1024 * 1024,
+            level0_limit: 4,
+            level_base_size: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl LsmOptions {
+    pub fn new() -> LsmOptions {
+        LsmOptions::default()
+    }
+
+    ///memtable 达到该字节数后刷成 SSTable
+    pub fn memtable_size(mut self, bytes: usize) -> LsmOptions {
+        self.memtable_size = bytes;
+        self
+    }
+
+    ///SSTable 数据块的大小
+    pub fn block_size(mut self, bytes: usize) -> LsmOptions {
+        self.block_size = bytes;
+        self
+    }
+
+    ///compaction 输出的单个 SSTable 的目标大小
+    pub fn table_size(mut self, bytes: u64) -> LsmOptions {
+        self.table_size = bytes;
+        self
+    }
+
+    ///第 0 层的 SSTable 数量达到该值时合并到第 1 层
+    pub fn level0_limit(mut self, tables: usize) -> LsmOptions {
+        self.level0_limit = tables.max(1);
+        self
+    }
+
+    ///第 1 层的容量，之后每层扩大 10 倍
+    pub fn level_base_size(mut self, bytes: u64) -> LsmOptions {
+        self.level_base_size = bytes;
+        self
+    }
+}
+
+///每层包含的 SSTable，第 0 层按写入顺序（旧到新），其它层按键范围排序且互不重叠
+#[derive(Serialize, Deserialize, Debug)]
+struct Manifest {
+    next_id: u64,
+    levels: Vec<Vec<TableMeta>>,
+}
+
+impl Manifest {
+    fn load(dir: &Path) -> Result<Manifest> {
+        let path = dir.join(MANIFEST_FILE);
+        if !path.exists() {
+            return Ok(Manifest {
+                next_id: 1,
+                levels: vec![Vec::new(); MAX_LEVELS],
+            });
+        }
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if manifest.levels.len() != MAX_LEVELS {
+            return Err(KvError::Corruption("MANIFEST has an unexpected number of levels".to_owned()));
+        }
+        Ok(manifest)
+    }
+
+    ///先写临时文件再重命名，保证 MANIFEST 要么是旧的要么是新的
+    fn save(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", MANIFEST_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(tmp, dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+///基于 LSM-tree 的存储引擎，键集合可以大于内存
+#[derive(Debug)]
+pub struct LsmStore {
+    path: PathBuf,
+    options: LsmOptions,
+    memtable: BTreeMap<String, Option<String>>,
+    memtable_size: usize,
+    wal: BufWriter<File>,
+    manifest: Manifest,
+    tables: HashMap<u64, TableReader>,
+    //每层下一次 compaction 从哪个键开始，轮流合并各个键范围
+    compact_pointers: Vec<String>,
+}
+
+impl LsmStore {
+    pub fn open(path: impl Into<PathBuf>) -> Result<LsmStore> {
+        LsmStore::open_with(path, LsmOptions::default())
+    }
+
+    pub fn open_with(path: impl Into<PathBuf>, options: LsmOptions) -> Result<LsmStore> {
+        let path = path.into();
+        fs::create_dir_all(&path)?;
+        let manifest = Manifest::load(&path)?;
+
+        let mut tables = HashMap::new();
+        for meta in manifest.levels.iter().flatten() {
+            tables.insert(meta.id, TableReader::open(&path, meta.id)?);
+        }
+        //删除 compaction 中途崩溃留下的、MANIFEST 中没有记录的 SSTable
+        for entry in fs::read_dir(&path)? {
+            let file = entry?.path();
+            if file.extension() != Some(OsStr::new("sst")) {
+                continue;
+            }
+            let id = file.file_stem().and_then(OsStr::to_str).and_then(|s| s.parse().ok());
+            if id.is_some_and(|id| !tables.contains_key(&id)) {
+                fs::remove_file(file)?;
+            }
+        }
+
+        //重放预写日志，恢复未刷盘的 memtable
+        let mut memtable = BTreeMap::new();
+        let mut memtable_size = 0;
+        let wal_path = path.join(WAL_FILE);
+        if wal_path.exists() {
+            let reader = BufReader::new(File::open(&wal_path)?);
+            let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Commend>();
+            while let Some(cmd) = stream.next() {
+                let cmd = match cmd.map_err(KvError::from) {
+                    Ok(cmd) => cmd,
+                    //追加时崩溃留下的不完整记录没有写成功，截掉后才能继续追加
+                    Err(e) if is_incomplete_record(&e) => {
+                        OpenOptions::new().write(true).open(&wal_path)?.set_len(stream.byte_offset() as u64)?;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                let (key, value) = match cmd {
+                    Commend::Set { key, value, .. } => (key, Some(value)),
+                    Commend::Remove { key, .. } => (key, None),
+                    _ => return Err(KvError::UnexpectedCommandType),
+                };
+                memtable_size += key.len() + value.as_ref().map_or(0, String::len);
+                memtable.insert(key, value);
+            }
+        }
+        let wal = BufWriter::new(OpenOptions::new().create(true).append(true).open(&wal_path)?);
+
+        Ok(LsmStore {
+            path,
+            options,
+            memtable,
+            memtable_size,
+            wal,
+            manifest,
+            tables,
+            compact_pointers: vec![String::new(); MAX_LEVELS],
+        })
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        serde_json::to_writer(&mut self.wal, &Commend::set(key.clone(), value.clone()))?;
+        self.wal.flush()?;
+        self.memtable_size += key.len() + value.len();
+        self.memtable.insert(key, Some(value));
+        if self.memtable_size >= self.options.memtable_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.memtable.get(&key) {
+            return Ok(value.clone());
+        }
+        //第 0 层的表可能重叠，从新到旧查找
+        for meta in self.manifest.levels[0].iter().rev() {
+            if meta.contains(&key) {
+                if let Some(value) = self.tables.get_mut(&meta.id).unwrap().get(&key)? {
+                    return Ok(value);
+                }
+            }
+        }
+        for level in &self.manifest.levels[1..] {
+            let i = level.partition_point(|meta| meta.largest < key);
+            if let Some(meta) = level.get(i).filter(|meta| meta.contains(&key)) {
+                if let Some(value) = self.tables.get_mut(&meta.id).unwrap().get(&key)? {
+                    return Ok(value);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        if self.get(key.clone())?.is_none() {
+            return Err(KvError::KeyNotFound);
+        }
+        serde_json::to_writer(&mut self.wal, &Commend::remove(key.clone()))?;
+        self.wal.flush()?;
+        self.memtable_size += key.len();
+        self.memtable.insert(key, None);
+        if self.memtable_size >= self.options.memtable_size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    ///把 memtable 刷成第 0 层的 SSTable，然后按需进行 compaction
+    pub fn flush(&mut self) -> Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let id = self.next_table_id();
+        let mut builder = TableBuilder::new(&self.path, id, self.options.block_size)?;
+        for (key, value) in &self.memtable {
+            builder.add(key, value.as_deref())?;
+        }
+        let meta = builder.finish()?;
+        self.tables.insert(id, TableReader::open(&self.path, id)?);
+        self.manifest.levels[0].push(meta);
+        self.manifest.save(&self.path)?;
+
+        //数据已经在 SSTable 中，清空预写日志
+        self.wal = BufWriter::new(File::create(self.path.join(WAL_FILE))?);
+        self.memtable.clear();
+        self.memtable_size = 0;
+
+        while let Some((level, inputs)) = self.pick_compaction() {
+            self.compact(level, inputs)?;
+        }
+        Ok(())
+    }
+
+    ///按键的顺序返回所有以 prefix 开头的键值对
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        //以 prefix 开头的键都落在 [prefix, prefix + char::MAX] 中
+        let upper = format!("{}{}", prefix, char::MAX);
+        let memtable: Vec<Result<Entry>> = self
+            .memtable
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Ok((key.clone(), value.clone())))
+            .collect();
+        //数据源从新到旧：memtable、第 0 层（从新到旧）、其它层
+        let mut sources = vec![Box::new(memtable.into_iter()) as EntryIter];
+        let tables = self.manifest.levels[0]
+            .iter()
+            .rev()
+            .chain(self.manifest.levels[1..].iter().flatten())
+            .filter(|meta| meta.overlaps(prefix, &upper));
+        for meta in tables {
+            sources.push(Box::new(self.tables[&meta.id].iter()?));
+        }
+        let mut pairs = Vec::new();
+        for entry in MergeIter::new(sources) {
+            if let (key, Some(value)) = entry? {
+                if key.starts_with(prefix) {
+                    pairs.push((key, value));
+                }
+            }
+        }
+        Ok(pairs)
+    }
+
+    fn next_table_id(&mut self) -> u64 {
+        let id = self.manifest.next_id;
+        self.manifest.next_id += 1;
+        id
+    }
+
+    ///第 i 层（i >= 1）的容量
+    fn level_max_size(&self, level: usize) -> u64 {
+        self.options.level_base_size * LEVEL_MULTIPLIER.pow(level as u32 - 1)
+    }
+
+    ///选出需要合并到下一层的表：第 0 层表太多时合并整层，其它层超出容量时轮流选一个表
+    fn pick_compaction(&self) -> Option<(usize, Vec<TableMeta>)> {
+        let levels = &self.manifest.levels;
+        if levels[0].len() >= self.options.level0_limit {
+            return Some((0, levels[0].clone()));
+        }
+        for (level, tables) in levels.iter().enumerate().take(MAX_LEVELS - 1).skip(1) {
+            let size: u64 = tables.iter().map(|meta| meta.size).sum();
+            if size > self.level_max_size(level) {
+                let pointer = &self.compact_pointers[level];
+                let meta = tables
+                    .iter()
+                    .find(|meta| meta.smallest > *pointer)
+                    .unwrap_or(&tables[0]);
+                return Some((level, vec![meta.clone()]));
+            }
+        }
+        None
+    }
+
+    ///把 level 层的 inputs 与下一层中键范围重叠的表合并，输出到下一层
+    fn compact(&mut self, level: usize, inputs: Vec<TableMeta>) -> Result<()> {
+        let smallest = inputs.iter().map(|meta| meta.smallest.as_str()).min().unwrap().to_owned();
+        let largest = inputs.iter().map(|meta| meta.largest.as_str()).max().unwrap().to_owned();
+        let overlaps: Vec<TableMeta> = self.manifest.levels[level + 1]
+            .iter()
+            .filter(|meta| meta.overlaps(&smallest, &largest))
+            .cloned()
+            .collect();
+
+        //越新的数据源排在越前面：第 0 层从新到旧，然后是下一层
+        let mut sources = Vec::new();
+        for meta in inputs.iter().rev().chain(overlaps.iter()) {
+            sources.push(Box::new(self.tables[&meta.id].iter()?) as EntryIter);
+        }
+        //下面的层都没有数据时，删除标记可以直接丢弃
+        let bottom = self.manifest.levels[level + 2..].iter().all(Vec::is_empty);
+
+        let mut outputs = Vec::new();
+        let mut builder: Option<TableBuilder> = None;
+        for entry in MergeIter::new(sources) {
+            let (key, value) = entry?;
+            if value.is_none() && bottom {
+                continue;
+            }
+            if builder.is_none() {
+                let id = self.next_table_id();
+                builder = Some(TableBuilder::new(&self.path, id, self.options.block_size)?);
+            }
+            let current = builder.as_mut().unwrap();
+            current.add(&key, value.as_deref())?;
+            if current.size() >= self.options.table_size {
+                outputs.push(builder.take().unwrap().finish()?);
+            }
+        }
+        if let Some(builder) = builder {
+            if builder.is_empty() {
+                builder.abandon()?;
+            } else {
+                outputs.push(builder.finish()?);
+            }
+        }
+
+        for meta in &outputs {
+            self.tables.insert(meta.id, TableReader::open(&self.path, meta.id)?);
+        }
+        let obsolete: Vec<u64> = inputs.iter().chain(overlaps.iter()).map(|meta| meta.id).collect();
+        self.manifest.levels[level].retain(|meta| !obsolete.contains(&meta.id));
+        let next = &mut self.manifest.levels[level + 1];
+        next.retain(|meta| !obsolete.contains(&meta.id));
+        next.extend(outputs);
+        next.sort_by(|a, b| a.smallest.cmp(&b.smallest));
+        self.manifest.save(&self.path)?;
+        self.compact_pointers[level] = largest;
+
+        for id in obsolete {
+            self.tables.remove(&id);
+            fs::remove_file(table_path(&self.path, id))?;
+        }
+        Ok(())
+    }
+}
+
+impl KvsEngine for LsmStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        LsmStore::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        LsmStore::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        LsmStore::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        LsmStore::scan(self, prefix)
+    }
+}
+
+///按键有序的记录流
+type EntryIter = Box<dyn Iterator<Item = Result<Entry>>>;
+
+///多路归并若干有序数据源，同一个键只保留最前面（最新）数据源中的记录
+struct MergeIter {
+    sources: Vec<Peekable<EntryIter>>,
+}
+
+impl MergeIter {
+    fn new(sources: Vec<EntryIter>) -> MergeIter {
+        MergeIter {
+            sources: sources.into_iter().map(Iterator::peekable).collect(),
+        }
+    }
+}
+
+impl Iterator for MergeIter {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut min: Option<(usize, String)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            match source.peek() {
+                None => {}
+                Some(Err(_)) => return source.next(),
+                Some(Ok((key, _))) if min.as_ref().is_none_or(|(_, min_key)| key < min_key) => {
+                    min = Some((i, key.clone()));
+                }
+                Some(Ok(_)) => {}
+            }
+        }
+        let (winner, min_key) = min?;
+        let entry = self.sources[winner].next();
+        //跳过旧数据源中相同的键
+        for source in &mut self.sources {
+            while matches!(source.peek(), Some(Ok((key, _))) if *key == min_key) {
+                source.next();
+            }
+        }
+        entry
+    }
+}
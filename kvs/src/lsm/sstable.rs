@@ -0,0 +1,304 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::bloom::{hash_key, Bloom};
+use crate::{read_frame, KvError, Result};
+
+// SSTable 布局：
+//   数据块 ... | 索引块(JSON) | 布隆过滤器 | footer
+// footer 为 5 个小端 u64：索引偏移、索引长度、过滤器偏移、过滤器长度、magic
+const TABLE_MAGIC: u64 = 0x4b56_535f_5353_5431;
+const FOOTER_LEN: u64 = 40;
+
+const TAG_DELETE: u8 = 0;
+const TAG_VALUE: u8 = 1;
+
+///SSTable 中的一条记录，值为 None 表示删除标记
+pub(super) type Entry = (String, Option<String>);
+
+///SSTable 文件路径
+pub(super) fn table_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.sst", id))
+}
+
+///保存在 MANIFEST 里的 SSTable 元数据
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(super) struct TableMeta {
+    pub(super) id: u64,
+    pub(super) smallest: String,
+    pub(super) largest: String,
+    pub(super) size: u64,
+}
+
+impl TableMeta {
+    pub(super) fn contains(&self, key: &str) -> bool {
+        self.smallest.as_str() <= key && key <= self.largest.as_str()
+    }
+
+    pub(super) fn overlaps(&self, smallest: &str, largest: &str) -> bool {
+        self.smallest.as_str() <= largest && smallest <= self.largest.as_str()
+    }
+}
+
+///数据块的位置，按块中最后一个键索引
+#[derive(Serialize, Deserialize, Debug)]
+struct BlockHandle {
+    last_key: String,
+    offset: u64,
+    len: u64,
+}
+
+///按键的升序写入记录，生成一个 SSTable
+pub(super) struct TableBuilder {
+    id: u64,
+    path: PathBuf,
+    writer: BufWriter<File>,
+    offset: u64,
+    block_size: usize,
+    block: Vec<u8>,
+    last_key: Option<String>,
+    smallest: Option<String>,
+    index: Vec<BlockHandle>,
+    key_hashes: Vec<u64>,
+}
+
+impl TableBuilder {
+    pub(super) fn new(dir: &Path, id: u64, block_size: usize) -> Result<TableBuilder> {
+        let path = table_path(dir, id);
+        Ok(TableBuilder {
+            id,
+            writer: BufWriter::new(File::create(&path)?),
+            path,
+            offset: 0,
+            block_size,
+            block: Vec::new(),
+            last_key: None,
+            smallest: None,
+            index: Vec::new(),
+            key_hashes: Vec::new(),
+        })
+    }
+
+    ///追加一条记录，键必须严格递增
+    pub(super) fn add(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        debug_assert!(self.last_key.as_deref().is_none_or(|last| last < key));
+        encode_entry(&mut self.block, key, value);
+        self.key_hashes.push(hash_key(key));
+        if self.smallest.is_none() {
+            self.smallest = Some(key.to_owned());
+        }
+        self.last_key = Some(key.to_owned());
+        if self.block.len() >= self.block_size {
+            self.finish_block()?;
+        }
+        Ok(())
+    }
+
+    ///当前已写入的字节数（含未落盘的数据块）
+    pub(super) fn size(&self) -> u64 {
+        self.offset + self.block.len() as u64
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.smallest.is_none()
+    }
+
+    fn finish_block(&mut self) -> Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_all(&self.block)?;
+        self.index.push(BlockHandle {
+            last_key: self.last_key.clone().expect("block without keys"),
+            offset: self.offset,
+            len: self.block.len() as u64,
+        });
+        self.offset += self.block.len() as u64;
+        self.block.clear();
+        Ok(())
+    }
+
+    ///写入索引、过滤器和 footer，并落盘
+    pub(super) fn finish(mut self) -> Result<TableMeta> {
+        self.finish_block()?;
+        let index = serde_json::to_vec(&self.index)?;
+        let bloom = Bloom::build(&self.key_hashes).encode();
+        let index_offset = self.offset;
+        let bloom_offset = index_offset + index.len() as u64;
+        self.writer.write_all(&index)?;
+        self.writer.write_all(&bloom)?;
+        for n in [
+            index_offset,
+            index.len() as u64,
+            bloom_offset,
+            bloom.len() as u64,
+            TABLE_MAGIC,
+        ] {
+            self.writer.write_all(&n.to_le_bytes())?;
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(TableMeta {
+            id: self.id,
+            smallest: self.smallest.unwrap_or_default(),
+            largest: self.last_key.unwrap_or_default(),
+            size: bloom_offset + bloom.len() as u64 + FOOTER_LEN,
+        })
+    }
+
+    ///放弃构建，删除文件
+    pub(super) fn abandon(self) -> Result<()> {
+        let path = self.path.clone();
+        drop(self);
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
+
+///打开的 SSTable，索引和布隆过滤器常驻内存
+#[derive(Debug)]
+pub(super) struct TableReader {
+    id: u64,
+    path: PathBuf,
+    file: File,
+    index: Vec<BlockHandle>,
+    bloom: Bloom,
+    data_len: u64,
+}
+
+impl TableReader {
+    pub(super) fn open(dir: &Path, id: u64) -> Result<TableReader> {
+        let corrupted = || KvError::Corruption(format!("{}.sst is damaged", id));
+        let path = table_path(dir, id);
+        let mut file = File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        if file_len < FOOTER_LEN {
+            return Err(corrupted());
+        }
+        let mut footer = [0u8; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(file_len - FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        let field = |i: usize| u64::from_le_bytes(footer[i * 8..i * 8 + 8].try_into().unwrap());
+        let (index_offset, index_len, bloom_offset, bloom_len) = (field(0), field(1), field(2), field(3));
+        //索引和过滤器必须紧挨着 footer，读取它们之前先检查长度，避免按损坏的长度分配内存
+        let sizes_match = index_offset.checked_add(index_len) == Some(bloom_offset)
+            && bloom_offset.checked_add(bloom_len).and_then(|end| end.checked_add(FOOTER_LEN)) == Some(file_len);
+        if field(4) != TABLE_MAGIC || !sizes_match {
+            return Err(corrupted());
+        }
+        let index = serde_json::from_slice(&read_at(&mut file, index_offset, index_len)?)?;
+        let bloom = Bloom::decode(&read_at(&mut file, bloom_offset, bloom_len)?).ok_or_else(corrupted)?;
+        Ok(TableReader {
+            id,
+            path,
+            file,
+            index,
+            bloom,
+            data_len: index_offset,
+        })
+    }
+
+    ///查找键；外层 None 表示表中没有该键，内层 None 表示删除标记
+    pub(super) fn get(&mut self, key: &str) -> Result<Option<Option<String>>> {
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+        // 第一个最后键 >= key 的数据块
+        let i = self.index.partition_point(|handle| handle.last_key.as_str() < key);
+        let Some(handle) = self.index.get(i) else {
+            return Ok(None);
+        };
+        if handle.offset.checked_add(handle.len).is_none_or(|end| end > self.data_len) {
+            return Err(KvError::Corruption(format!("{}.sst has a damaged index", self.id)));
+        }
+        let block = read_at(&mut self.file, handle.offset, handle.len)?;
+        let mut cursor = block.as_slice();
+        while !cursor.is_empty() {
+            let (entry_key, value) = decode_entry(&mut cursor).ok_or_else(|| {
+                KvError::Corruption(format!("{}.sst has a damaged block at {}", self.id, handle.offset))
+            })?;
+            if entry_key == key {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    ///按顺序遍历表中的所有记录，使用独立的文件句柄，不影响点查
+    pub(super) fn iter(&self) -> Result<TableIter> {
+        let file = File::open(&self.path)?;
+        Ok(TableIter {
+            id: self.id,
+            reader: BufReader::new(file).take(self.data_len),
+        })
+    }
+}
+
+///顺序读取 SSTable 数据块中的记录
+pub(super) struct TableIter {
+    id: u64,
+    reader: io::Take<BufReader<File>>,
+}
+
+impl Iterator for TableIter {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.reader.limit() == 0 {
+            return None;
+        }
+        Some(read_entry(&mut self.reader).map_err(|_| {
+            KvError::Corruption(format!("{}.sst has a damaged data block", self.id))
+        }))
+    }
+}
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+// 记录编码：键长(u32) 键 标记(u8) [值长(u32) 值]
+fn encode_entry(buf: &mut Vec<u8>, key: &str, value: Option<&str>) {
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    match value {
+        None => buf.push(TAG_DELETE),
+        Some(value) => {
+            buf.push(TAG_VALUE);
+            buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            buf.extend_from_slice(value.as_bytes());
+        }
+    }
+}
+
+fn decode_entry(buf: &mut &[u8]) -> Option<Entry> {
+    let mut reader = *buf;
+    let entry = read_entry(&mut reader).ok()?;
+    *buf = reader;
+    Some(entry)
+}
+
+fn read_entry<R: Read>(reader: &mut R) -> io::Result<Entry> {
+    let key = read_string(reader)?;
+    let mut tag = [0u8];
+    reader.read_exact(&mut tag)?;
+    let value = match tag[0] {
+        TAG_DELETE => None,
+        TAG_VALUE => Some(read_string(reader)?),
+        _ => return Err(io::ErrorKind::InvalidData.into()),
+    };
+    Ok((key, value))
+}
+
+fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    //长度可能已经损坏，按实际读到的字节分配，不够时返回 UnexpectedEof
+    let buf = read_frame(reader, u32::from_le_bytes(len))?;
+    String::from_utf8(buf).map_err(|_| io::ErrorKind::InvalidData.into())
+}
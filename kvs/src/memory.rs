@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::family::now_ms;
+use crate::{write_snapshot, KvError, KvStore, KvsEngine, Result, StoreOptions};
+
+///纯内存的存储引擎，语义与 KvStore 相同，适合测试和临时缓存
+///
+///可以把数据快照成普通的日志文件（`KvStore::open` 能直接打开），也可以从 KvStore 的目录加载
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStore {
+    map: BTreeMap<String, String>,
+    ///带过期时间的键和过期的时间（UNIX 时间戳，毫秒）
+    expires: HashMap<String, u64>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    ///从 KvStore 的数据目录加载所有键值对，用于缓存预热
+    pub fn load_from(path: impl AsRef<Path>) -> Result<MemoryStore> {
+        MemoryStore::load_from_with(path, StoreOptions::default())
+    }
+
+    ///使用自定义配置（文件系统、密钥）打开数据目录并加载
+    pub fn load_from_with(path: impl AsRef<Path>, options: StoreOptions) -> Result<MemoryStore> {
+        let mut store = KvStore::open_with(path.as_ref(), options)?;
+        Ok(MemoryStore {
+            map: store.scan("")?.into_iter().collect(),
+            expires: HashMap::new(),
+        })
+    }
+
+    ///把当前数据写成一个新的日志文件，替换目录中原有的日志
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.snapshot_to_with(path, &StoreOptions::default())
+    }
+
+    ///按配置的文件系统、格式和密钥写快照，过期时间也写入快照
+    pub fn snapshot_to_with(&self, path: impl AsRef<Path>, options: &StoreOptions) -> Result<()> {
+        let now = now_ms();
+        let pairs = self.map.iter().filter_map(|(key, value)| match self.expires.get(key) {
+            Some(&expires) if expires <= now => None,
+            expires => Some((key, value, expires.copied())),
+        });
+        write_snapshot(path.as_ref(), options, pairs)
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.expires.remove(&key);
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    ///写入在 ttl 之后过期的键值对
+    pub fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.expires.insert(key.clone(), now_ms() + ttl.as_millis() as u64);
+        self.map.insert(key, value);
+        Ok(())
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.expire(&key);
+        Ok(self.map.get(&key).cloned())
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.expire(&key);
+        self.expires.remove(&key);
+        self.map.remove(&key).map(|_| ()).ok_or(KvError::KeyNotFound)
+    }
+
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let now = now_ms();
+        let map = &mut self.map;
+        //过期的键从两个表中都删除
+        self.expires.retain(|key, &mut expires| expires > now || map.remove(key).is_none());
+        Ok(self
+            .map
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    ///删除已经过期的键
+    fn expire(&mut self, key: &str) {
+        if self.expires.get(key).is_some_and(|&expires| expires <= now_ms()) {
+            self.expires.remove(key);
+            self.map.remove(key);
+        }
+    }
+}
+
+impl KvsEngine for MemoryStore {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        MemoryStore::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        MemoryStore::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        MemoryStore::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        MemoryStore::scan(self, prefix)
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        MemoryStore::set_with_ttl(self, key, value, ttl)
+    }
+}
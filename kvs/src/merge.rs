@@ -0,0 +1,127 @@
+//! 合并运算符：写入时只记录操作数，读取时才合并到键现有的值上
+//!
+//! `KvStore::merge` 在日志中追加一条 `Merge` 记录，不读取现有的值；`get` 时把最近一次 `set`
+//! 之后的操作数按顺序合并到它的值上，compaction 把合并的结果写成普通的值。
+//! `incr`/`decr` 写入之前先用现有的值合并一次，值不是整数时直接返回错误，不会留下无法合并的操作数。
+//! 日志中只记录运算符的名字，打开 KvStore 时需要用 `StoreOptions::merge_operator` 注册日志中用到的
+//! 自定义运算符。内置的运算符：`add` 整数相加（`incr`/`decr` 使用它），`max` 保留较大的整数，
+//! `append` 把操作数（JSON）追加到 JSON 数组的末尾。
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{Commend, KvError, Result};
+
+///`incr`/`decr` 使用的运算符
+pub const ADD_OPERATOR: &str = "add";
+
+///合并运算符，existing 为 None 表示键不存在
+///
+///```
+///# use kvs::{KvError, KvStore, MemoryFs, StoreOptions};
+///let concat = |_key: &str, existing: Option<&str>, operand: &str| {
+///    Ok::<_, KvError>(format!("{}{}", existing.unwrap_or(""), operand))
+///};
+///let options = StoreOptions::new().file_system(MemoryFs::new()).merge_operator("concat", concat);
+///let mut store = KvStore::open_with("data", options)?;
+///store.merge("greeting".to_owned(), "concat", "hello, ".to_owned())?;
+///store.merge("greeting".to_owned(), "concat", "world".to_owned())?;
+///assert_eq!(store.get("greeting".to_owned())?, Some("hello, world".to_owned()));
+///# Ok::<(), KvError>(())
+///```
+pub trait MergeOperator: Send + Sync {
+    fn merge(&self, key: &str, existing: Option<&str>, operand: &str) -> Result<String>;
+}
+
+impl<F> MergeOperator for F
+where
+    F: Fn(&str, Option<&str>, &str) -> Result<String> + Send + Sync,
+{
+    fn merge(&self, key: &str, existing: Option<&str>, operand: &str) -> Result<String> {
+        self(key, existing, operand)
+    }
+}
+
+///按名字注册的合并运算符，包括内置的运算符
+#[derive(Clone)]
+pub(crate) struct MergeOperators {
+    operators: BTreeMap<String, Arc<dyn MergeOperator>>,
+}
+
+impl Default for MergeOperators {
+    fn default() -> MergeOperators {
+        let mut operators = MergeOperators {
+            operators: BTreeMap::new(),
+        };
+        operators.register(ADD_OPERATOR, add);
+        operators.register("max", max);
+        operators.register("append", append);
+        operators
+    }
+}
+
+impl fmt::Debug for MergeOperators {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.operators.keys()).finish()
+    }
+}
+
+impl MergeOperators {
+    pub(crate) fn register(&mut self, name: impl Into<String>, operator: impl MergeOperator + 'static) {
+        self.operators.insert(name.into(), Arc::new(operator));
+    }
+
+    pub(crate) fn contains(&self, name: &str) -> bool {
+        self.operators.contains_key(name)
+    }
+
+    ///用名为 name 的运算符把操作数合并到 existing 上
+    pub(crate) fn merge(&self, name: &str, key: &str, existing: Option<&str>, operand: &str) -> Result<String> {
+        let operator = self.operators.get(name).ok_or_else(|| KvError::UnknownMergeOperator(name.to_owned()))?;
+        operator.merge(key, existing, operand)
+    }
+
+    ///把一条记录应用到键之前的值上，返回之后的值
+    pub(crate) fn fold(&self, existing: Option<String>, cmd: Commend) -> Result<Option<String>> {
+        match cmd {
+            Commend::Set { value, .. } => Ok(Some(value)),
+            Commend::Remove { .. } | Commend::Expire { .. } => Ok(None),
+            Commend::Merge { key, op, operand, .. } => self.merge(&op, &key, existing.as_deref(), &operand).map(Some),
+            _ => Err(KvError::UnexpectedCommandType),
+        }
+    }
+}
+
+fn integer(key: &str, text: &str) -> Result<i64> {
+    text.parse()
+        .map_err(|_| KvError::MergeFailed(format!("value of {} is not an integer: {:?}", key, text)))
+}
+
+fn add(key: &str, existing: Option<&str>, operand: &str) -> Result<String> {
+    let sum = integer(key, existing.unwrap_or("0"))?
+        .checked_add(integer(key, operand)?)
+        .ok_or_else(|| KvError::MergeFailed(format!("value of {} overflowed", key)))?;
+    Ok(sum.to_string())
+}
+
+fn max(key: &str, existing: Option<&str>, operand: &str) -> Result<String> {
+    let operand = integer(key, operand)?;
+    match existing {
+        Some(existing) => Ok(integer(key, existing)?.max(operand).to_string()),
+        None => Ok(operand.to_string()),
+    }
+}
+
+fn append(key: &str, existing: Option<&str>, operand: &str) -> Result<String> {
+    let item: Value = serde_json::from_str(operand)
+        .map_err(|e| KvError::MergeFailed(format!("operand for {} is not JSON: {}", key, e)))?;
+    let mut list = match existing.map(serde_json::from_str::<Value>) {
+        Some(Ok(Value::Array(list))) => list,
+        Some(_) => return Err(KvError::MergeFailed(format!("value of {} is not a JSON array", key))),
+        None => Vec::new(),
+    };
+    list.push(item);
+    Ok(Value::Array(list).to_string())
+}
@@ -0,0 +1,109 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::merge::MergeOperators;
+use crate::vlog;
+use crate::{DiskFs, EncryptionKey, FileSystem, Format, MergeOperator};
+
+///打开 KvStore 时的可选配置
+///
+///```no_run
+///# use kvs::{EncryptionKey, KvStore, StoreOptions};
+///let key = EncryptionKey::from_file("store.key")?;
+///let store = KvStore::open_with("data", StoreOptions::new().encryption_key(key))?;
+///# Ok::<(), kvs::KvError>(())
+///```
+#[derive(Debug, Clone, Default)]
+pub struct StoreOptions {
+    pub(crate) encryption_key: Option<EncryptionKey>,
+    pub(crate) previous_keys: Vec<EncryptionKey>,
+    file_system: Option<Arc<dyn FileSystem>>,
+    pub(crate) index_budget: Option<u64>,
+    pub(crate) cache_bytes: Option<u64>,
+    pub(crate) retention_ms: Option<u64>,
+    pub(crate) merge_operators: MergeOperators,
+    value_threshold: Option<u64>,
+    pub(crate) format: Option<Format>,
+}
+
+impl StoreOptions {
+    pub fn new() -> StoreOptions {
+        StoreOptions::default()
+    }
+
+    ///新写入的日志记录使用该密钥加密；未加密的旧日志在下一次 compaction 时被重写为加密格式
+    pub fn encryption_key(mut self, key: EncryptionKey) -> StoreOptions {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    ///轮换密钥时提供旧密钥，只用于读取旧日志，compaction 之后旧日志会用新密钥重写
+    pub fn previous_key(mut self, key: EncryptionKey) -> StoreOptions {
+        self.previous_keys.push(key);
+        self
+    }
+
+    ///数据目录所在的文件系统，默认是磁盘；测试时可以换成 `MemoryFs` 或 `FaultyFs`
+    pub fn file_system(mut self, file_system: impl FileSystem + 'static) -> StoreOptions {
+        self.file_system = Some(Arc::new(file_system));
+        self
+    }
+
+    pub(crate) fn fs(&self) -> &dyn FileSystem {
+        self.file_system.as_deref().unwrap_or(&DiskFs)
+    }
+
+    ///内存索引的预算（字节），索引超过预算后只在内存中保存键的指纹，查找时从日志读取键进行比较
+    pub fn index_memory_budget(mut self, bytes: u64) -> StoreOptions {
+        self.index_budget = Some(bytes);
+        self
+    }
+
+    ///缓存最近读取的值，最多占用约 bytes 字节；默认不缓存
+    pub fn value_cache(mut self, bytes: u64) -> StoreOptions {
+        self.cache_bytes = Some(bytes);
+        self
+    }
+
+    ///compaction 时保留在这段时间内被覆盖或删除的旧版本，`KvStore::get_at` 可以读到它们；
+    ///默认不保留，只有还没有被 compaction 的旧版本可以读到
+    pub fn history_retention(mut self, retention: Duration) -> StoreOptions {
+        self.retention_ms = Some(retention.as_millis() as u64);
+        self
+    }
+
+    ///注册名为 name 的合并运算符，同名时替换内置的运算符；日志中用到的运算符每次打开时都要注册
+    pub fn merge_operator(mut self, name: impl Into<String>, operator: impl MergeOperator + 'static) -> StoreOptions {
+        self.merge_operators.register(name, operator);
+        self
+    }
+
+    ///不小于 bytes 字节的值写入单独的值日志，主日志中只记录值的位置，默认 1 MiB
+    pub fn value_log_threshold(mut self, bytes: u64) -> StoreOptions {
+        self.value_threshold = Some(bytes);
+        self
+    }
+
+    pub(crate) fn value_threshold(&self) -> u64 {
+        self.value_threshold.unwrap_or(vlog::DEFAULT_THRESHOLD)
+    }
+
+    ///新写入的日志记录使用的编码格式；不设置时沿用最新的日志文件的格式，新的数据目录使用 JSON。
+    ///旧格式的日志照常读取，在下一次 compaction 时被重写为新格式
+    pub fn record_format(mut self, format: Format) -> StoreOptions {
+        self.format = Some(format);
+        self
+    }
+
+    pub(crate) fn format(&self) -> Format {
+        self.format.unwrap_or_default()
+    }
+
+    ///根据日志头部的密钥标识查找密钥
+    pub(crate) fn key(&self, id: u64) -> Option<&EncryptionKey> {
+        self.encryption_key
+            .iter()
+            .chain(self.previous_keys.iter())
+            .find(|key| key.id() == id)
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+use crate::KvError;
+
+///客户端发给 kvs-server 的请求，连接上依次发送 JSON 编码的请求和响应
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Request {
+    Get { key: String },
+    Set { key: String, value: String },
+    Remove { key: String },
+    Scan { prefix: String },
+}
+
+///kvs-server 的响应
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    Value(Option<String>),
+    Pairs(Vec<(String, String)>),
+    Done,
+    Err(RemoteError),
+}
+
+///通过网络传递的错误，客户端把它还原成 KvError
+#[derive(Serialize, Deserialize, Debug)]
+pub enum RemoteError {
+    KeyNotFound,
+    ReadOnly,
+    NotLeader(Option<String>),
+    Other(String),
+}
+
+impl From<KvError> for RemoteError {
+    fn from(error: KvError) -> Self {
+        match error {
+            KvError::KeyNotFound => RemoteError::KeyNotFound,
+            KvError::ReadOnly => RemoteError::ReadOnly,
+            KvError::NotLeader { leader } => RemoteError::NotLeader(leader),
+            error => RemoteError::Other(error.to_string()),
+        }
+    }
+}
+
+impl From<RemoteError> for KvError {
+    fn from(error: RemoteError) -> Self {
+        match error {
+            RemoteError::KeyNotFound => KvError::KeyNotFound,
+            RemoteError::ReadOnly => KvError::ReadOnly,
+            RemoteError::NotLeader(leader) => KvError::NotLeader { leader },
+            RemoteError::Other(message) => KvError::Remote(message),
+        }
+    }
+}
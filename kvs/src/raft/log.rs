@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::replication::FamilySnapshot;
+use crate::{Commend, KvError, Result};
+
+const STATE_FILE: &str = "state.json";
+const LOG_FILE: &str = "log.json";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+///Raft 日志条目，command 为 None 的是新主节点提交的空条目
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(super) struct Entry {
+    pub(super) index: u64,
+    pub(super) term: u64,
+    pub(super) command: Option<Commend>,
+}
+
+///需要持久化的投票状态
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub(super) struct HardState {
+    pub(super) term: u64,
+    pub(super) voted_for: Option<u64>,
+}
+
+///状态机快照：KvStore 所有列族的配置和数据，以及它包含的最后一个日志条目
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(super) struct Snapshot {
+    pub(super) index: u64,
+    pub(super) term: u64,
+    pub(super) families: Vec<FamilySnapshot>,
+}
+
+///持久化的 Raft 日志，快照之前的条目已经被删除
+///
+///所有文件都在数据目录的 `raft/` 子目录下，KvStore 只读取数据目录下的文件，不受影响
+pub(super) struct RaftLog {
+    dir: PathBuf,
+    writer: BufWriter<File>,
+    snapshot_index: u64,
+    snapshot_term: u64,
+    //entries[i] 的 index 为 snapshot_index + 1 + i
+    entries: Vec<Entry>,
+}
+
+impl RaftLog {
+    pub(super) fn open(dir: &Path) -> Result<RaftLog> {
+        fs::create_dir_all(dir)?;
+        let (snapshot_index, snapshot_term) = match read_json::<Snapshot>(&dir.join(SNAPSHOT_FILE))? {
+            Some(snapshot) => (snapshot.index, snapshot.term),
+            None => (0, 0),
+        };
+        let mut entries = Vec::new();
+        let log_path = dir.join(LOG_FILE);
+        if log_path.exists() {
+            let reader = BufReader::new(File::open(&log_path)?);
+            let mut stream = serde_json::Deserializer::from_reader(reader).into_iter::<Entry>();
+            while let Some(entry) = stream.next() {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    //追加时崩溃留下的不完整条目还没有确认给主节点，截掉后才能继续追加
+                    Err(e) if e.is_eof() => {
+                        OpenOptions::new().write(true).open(&log_path)?.set_len(stream.byte_offset() as u64)?;
+                        break;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                //快照之后还没来得及重写日志时，跳过快照已经包含的条目
+                if entry.index <= snapshot_index {
+                    continue;
+                }
+                if entry.index != snapshot_index + 1 + entries.len() as u64 {
+                    return Err(KvError::Corruption(format!("raft log is not contiguous at {}", entry.index)));
+                }
+                entries.push(entry);
+            }
+        }
+        let writer = BufWriter::new(OpenOptions::new().create(true).append(true).open(&log_path)?);
+        Ok(RaftLog {
+            dir: dir.to_owned(),
+            writer,
+            snapshot_index,
+            snapshot_term,
+            entries,
+        })
+    }
+
+    pub(super) fn load_state(&self) -> Result<HardState> {
+        Ok(read_json(&self.dir.join(STATE_FILE))?.unwrap_or_default())
+    }
+
+    pub(super) fn save_state(&self, state: HardState) -> Result<()> {
+        write_json(&self.dir.join(STATE_FILE), &state)
+    }
+
+    pub(super) fn load_snapshot(&self) -> Result<Snapshot> {
+        Ok(read_json(&self.dir.join(SNAPSHOT_FILE))?.unwrap_or_default())
+    }
+
+    pub(super) fn snapshot_index(&self) -> u64 {
+        self.snapshot_index
+    }
+
+    pub(super) fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub(super) fn last_term(&self) -> u64 {
+        self.entries.last().map_or(self.snapshot_term, |entry| entry.term)
+    }
+
+    ///index 处条目的任期，已经被快照删除（快照点除外）或不存在时返回 None
+    pub(super) fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    pub(super) fn entry(&self, index: u64) -> Option<&Entry> {
+        let offset = index.checked_sub(self.snapshot_index + 1)?;
+        self.entries.get(offset as usize)
+    }
+
+    ///从 index 开始最多 max 个条目
+    pub(super) fn entries_from(&self, index: u64, max: usize) -> Vec<Entry> {
+        let start = (index.saturating_sub(self.snapshot_index + 1) as usize).min(self.entries.len());
+        self.entries[start..].iter().take(max).cloned().collect()
+    }
+
+    ///追加条目并落盘
+    pub(super) fn append(&mut self, entries: &[Entry]) -> Result<()> {
+        for entry in entries {
+            debug_assert_eq!(entry.index, self.last_index() + 1);
+            serde_json::to_writer(&mut self.writer, entry)?;
+            self.entries.push(entry.clone());
+        }
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    ///删除 index 及之后的条目（与主节点冲突的部分）
+    pub(super) fn truncate_from(&mut self, index: u64) -> Result<()> {
+        let keep = index.saturating_sub(self.snapshot_index + 1) as usize;
+        self.entries.truncate(keep);
+        self.rewrite()
+    }
+
+    ///保存快照，并删除快照包含的条目
+    pub(super) fn install_snapshot(&mut self, snapshot: &Snapshot) -> Result<()> {
+        write_json(&self.dir.join(SNAPSHOT_FILE), snapshot)?;
+        //快照之后的条目如果与快照一致则保留，否则全部丢弃
+        if self.term_at(snapshot.index) == Some(snapshot.term) {
+            let drop = (snapshot.index - self.snapshot_index) as usize;
+            self.entries.drain(..drop);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot_index = snapshot.index;
+        self.snapshot_term = snapshot.term;
+        self.rewrite()
+    }
+
+    ///重写日志文件，先写临时文件再重命名
+    fn rewrite(&mut self) -> Result<()> {
+        let log_path = self.dir.join(LOG_FILE);
+        let tmp = self.dir.join(format!("{}.tmp", LOG_FILE));
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for entry in &self.entries {
+            serde_json::to_writer(&mut writer, entry)?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        fs::rename(&tmp, &log_path)?;
+        self.writer = BufWriter::new(OpenOptions::new().append(true).open(&log_path)?);
+        Ok(())
+    }
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Option<T>> {
+    match File::open(path) {
+        Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    writer.get_ref().sync_data()?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
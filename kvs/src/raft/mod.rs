@@ -0,0 +1,257 @@
+//! 基于 Raft 的 kvs 集群
+//!
+//! `set`/`remove` 作为日志条目提交给多数节点后再应用到每个节点的 KvStore；
+//! 读请求通过 ReadIndex 确认主节点身份后在主节点上执行，保证线性一致；
+//! 已应用的日志定期被 KvStore 的快照替换。非主节点返回 `KvError::NotLeader`，
+//! 其中带有主节点的客户端地址，`KvsClient` 会自动重定向。
+
+mod node;
+mod log;
+mod transport;
+
+use std::any::Any;
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use self::node::Node;
+use self::log::RaftLog;
+use self::transport::Message;
+use crate::{Commend, KvError, KvStore, KvsEngine, Result};
+
+// 客户端请求等待提交的最长时间
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+///集群中一个节点的地址
+#[derive(Debug, Clone, Copy)]
+pub struct RaftPeer {
+    ///节点之间通信的地址
+    pub raft_addr: SocketAddr,
+    ///kvs-server 对客户端服务的地址，用于重定向
+    pub client_addr: SocketAddr,
+}
+
+///Raft 节点的配置，peers 包含集群中所有节点（包括自己）
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    pub(crate) id: u64,
+    pub(crate) peers: BTreeMap<u64, RaftPeer>,
+    pub(crate) election_timeout: Duration,
+    pub(crate) heartbeat_interval: Duration,
+    pub(crate) snapshot_threshold: u64,
+}
+
+impl RaftConfig {
+    pub fn new(id: u64) -> RaftConfig {
+        RaftConfig {
+            id,
+            peers: BTreeMap::new(),
+            election_timeout: Duration::from_millis(300),
+            heartbeat_interval: Duration::from_millis(50),
+            snapshot_threshold: 1000,
+        }
+    }
+
+    pub fn peer(mut self, id: u64, raft_addr: SocketAddr, client_addr: SocketAddr) -> RaftConfig {
+        self.peers.insert(id, RaftPeer { raft_addr, client_addr });
+        self
+    }
+
+    ///选举超时的下限，实际超时在 [timeout, 2*timeout) 之间随机
+    pub fn election_timeout(mut self, timeout: Duration) -> RaftConfig {
+        self.election_timeout = timeout;
+        self
+    }
+
+    pub fn heartbeat_interval(mut self, interval: Duration) -> RaftConfig {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    ///已应用的日志条目达到该数量后用 KvStore 的快照替换
+    pub fn snapshot_threshold(mut self, entries: u64) -> RaftConfig {
+        self.snapshot_threshold = entries.max(1);
+        self
+    }
+
+    ///随机化的选举超时
+    fn random_election_timeout(&self) -> Duration {
+        let jitter = RandomState::new().build_hasher().finish() % 1000;
+        self.election_timeout + self.election_timeout * jitter as u32 / 1000
+    }
+
+    ///id 对应节点的客户端地址
+    fn client_addr(&self, id: u64) -> Option<String> {
+        self.peers.get(&id).map(|peer| peer.client_addr.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaftRole {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+///节点的当前状态
+#[derive(Debug, Clone)]
+pub struct RaftStatus {
+    pub id: u64,
+    pub role: RaftRole,
+    pub term: u64,
+    pub leader: Option<u64>,
+    pub commit_index: u64,
+    pub last_applied: u64,
+}
+
+///只读请求
+enum ReadOp {
+    Get(String),
+    Scan(String),
+}
+
+enum ReadResult {
+    Value(Option<String>),
+    Pairs(Vec<(String, String)>),
+}
+
+///发给核心线程的事件
+enum Event {
+    Message(Message),
+    Propose(Commend, Sender<Result<()>>),
+    Read(ReadOp, Sender<Result<ReadResult>>),
+    Status(Sender<RaftStatus>),
+    Shutdown,
+}
+
+///Raft 节点的句柄，实现了 KvsEngine，可以交给 kvs-server
+///
+///克隆得到的句柄共享同一个节点，请求等待提交时不占用其它句柄；最后一个句柄被丢弃时节点停止
+#[derive(Clone)]
+pub struct RaftNode {
+    inner: Arc<NodeThreads>,
+}
+
+///核心线程和监听线程，随最后一个句柄一起停止
+struct NodeThreads {
+    events: Sender<Event>,
+    raft_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl RaftNode {
+    ///打开 path 中的 KvStore 和 Raft 日志（`path/raft/`），并加入集群
+    pub fn start(path: impl Into<PathBuf>, config: RaftConfig) -> Result<RaftNode> {
+        let path = path.into();
+        let raft_addr = config
+            .peers
+            .get(&config.id)
+            .ok_or_else(|| KvError::Corruption(format!("node {} is not in the peer list", config.id)))?
+            .raft_addr;
+        let listener = TcpListener::bind(raft_addr)?;
+        let store = KvStore::open(&path)?;
+        let log = RaftLog::open(&path.join("raft"))?;
+
+        let (events, receiver) = mpsc::channel();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let node = Node::new(config, store, log)?;
+        let mut threads = vec![thread::spawn(move || node.run(receiver))];
+        let (sender, flag) = (events.clone(), Arc::clone(&shutdown));
+        threads.push(thread::spawn(move || transport::listen(listener, sender, flag)));
+        Ok(RaftNode {
+            inner: Arc::new(NodeThreads {
+                events,
+                raft_addr,
+                shutdown,
+                threads,
+            }),
+        })
+    }
+
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        self.call(|reply| Event::Propose(Commend::set(key, value), reply))
+    }
+
+    pub fn remove(&self, key: String) -> Result<()> {
+        self.call(|reply| Event::Propose(Commend::remove(key), reply))
+    }
+
+    ///线性一致读，只能在主节点上执行
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        match self.call(|reply| Event::Read(ReadOp::Get(key), reply))? {
+            ReadResult::Value(value) => Ok(value),
+            ReadResult::Pairs(_) => Err(KvError::UnexpectedCommandType),
+        }
+    }
+
+    pub fn scan(&self, prefix: &str) -> Result<Vec<(String, String)>> {
+        match self.call(|reply| Event::Read(ReadOp::Scan(prefix.to_owned()), reply))? {
+            ReadResult::Pairs(pairs) => Ok(pairs),
+            ReadResult::Value(_) => Err(KvError::UnexpectedCommandType),
+        }
+    }
+
+    pub fn status(&self) -> Result<RaftStatus> {
+        let (reply, receiver) = mpsc::channel();
+        self.inner.events.send(Event::Status(reply)).map_err(|_| stopped())?;
+        receiver.recv_timeout(REQUEST_TIMEOUT).map_err(|_| stopped())
+    }
+
+    ///engine 是 RaftNode 时返回它的一个副本；等待提交或 ReadIndex 确认的请求用副本执行，不需要持有服务端的锁
+    pub(crate) fn detached<E: 'static>(engine: &E) -> Option<E> {
+        let node = (engine as &dyn Any).downcast_ref::<RaftNode>()?.clone();
+        (Box::new(node) as Box<dyn Any>).downcast().ok().map(|node| *node)
+    }
+
+    fn call<T>(&self, event: impl FnOnce(Sender<Result<T>>) -> Event) -> Result<T> {
+        let (reply, receiver) = mpsc::channel();
+        self.inner.events.send(event(reply)).map_err(|_| stopped())?;
+        receiver.recv_timeout(REQUEST_TIMEOUT).map_err(|_| {
+            KvError::IoError(io::Error::new(io::ErrorKind::TimedOut, "raft request timed out"))
+        })?
+    }
+}
+
+fn stopped() -> KvError {
+    KvError::IoError(io::Error::new(io::ErrorKind::BrokenPipe, "raft node stopped"))
+}
+
+///停止核心线程和监听线程，释放端口和数据目录
+impl Drop for NodeThreads {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        let _ = self.events.send(Event::Shutdown);
+        //唤醒阻塞在 accept 上的监听线程
+        let _ = TcpStream::connect_timeout(&self.raft_addr, Duration::from_millis(200));
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl KvsEngine for RaftNode {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        RaftNode::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        RaftNode::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        RaftNode::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        RaftNode::scan(self, prefix)
+    }
+}
@@ -0,0 +1,639 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::Instant;
+
+use super::log::{Entry, HardState, RaftLog, Snapshot};
+use super::transport::{self, Message};
+use super::{Event, RaftConfig, RaftRole, RaftStatus, ReadOp, ReadResult};
+use crate::replication::FamilySnapshot;
+use crate::{Commend, KvError, KvStore, Result};
+
+// 一条 AppendEntries 最多携带的条目数
+const MAX_BATCH: usize = 100;
+// 一条 InstallSnapshot 最多携带的键数
+const SNAPSHOT_CHUNK: usize = 1000;
+
+///等待确认主节点身份的读请求
+struct PendingRead {
+    //读请求到达时的 commit_index，应用到这里之后才能读
+    read_index: u64,
+    //多数节点确认了这个序号的心跳之后才能读
+    seq: u64,
+    op: ReadOp,
+    reply: Sender<Result<ReadResult>>,
+}
+
+///Raft 的状态机，只在核心线程中运行，所有状态的修改都在这里完成
+pub(super) struct Node {
+    config: RaftConfig,
+    store: KvStore,
+    log: RaftLog,
+    state: HardState,
+    role: RaftRole,
+    leader: Option<u64>,
+    commit_index: u64,
+    last_applied: u64,
+    election_deadline: Instant,
+    next_heartbeat: Instant,
+    votes: HashSet<u64>,
+    //以下只在主节点上使用
+    next_index: HashMap<u64, u64>,
+    match_index: HashMap<u64, u64>,
+    acked_seq: HashMap<u64, u64>,
+    seq: u64,
+    proposals: BTreeMap<u64, (u64, Sender<Result<()>>)>,
+    reads: Vec<PendingRead>,
+    peers: HashMap<u64, Sender<Message>>,
+    //跟随者正在接收的分块快照，以及已经收到的键数
+    incoming: Option<(Snapshot, u64)>,
+}
+
+impl Node {
+    pub(super) fn new(config: RaftConfig, store: KvStore, log: RaftLog) -> Result<Node> {
+        let state = log.load_state()?;
+        let peers = config
+            .peers
+            .iter()
+            .filter(|(&id, _)| id != config.id)
+            .map(|(&id, peer)| (id, transport::connect(peer.raft_addr)))
+            .collect();
+        //快照之后的条目即使已经应用过，重新应用一次结果也相同
+        let applied = log.snapshot_index();
+        let now = Instant::now();
+        Ok(Node {
+            election_deadline: now + config.random_election_timeout(),
+            next_heartbeat: now,
+            config,
+            store,
+            log,
+            state,
+            role: RaftRole::Follower,
+            leader: None,
+            commit_index: applied,
+            last_applied: applied,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            acked_seq: HashMap::new(),
+            seq: 0,
+            proposals: BTreeMap::new(),
+            reads: Vec::new(),
+            peers,
+            incoming: None,
+        })
+    }
+
+    ///事件循环，收到 Shutdown 或句柄被丢弃时退出
+    pub(super) fn run(mut self, events: Receiver<Event>) {
+        loop {
+            let deadline = match self.role {
+                RaftRole::Leader => self.next_heartbeat,
+                _ => self.election_deadline,
+            };
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let result = match events.recv_timeout(timeout) {
+                Ok(Event::Shutdown) | Err(RecvTimeoutError::Disconnected) => return,
+                Ok(event) => self.handle(event),
+                Err(RecvTimeoutError::Timeout) => self.tick(),
+            };
+            if let Err(e) = result.and_then(|_| self.advance()) {
+                eprintln!("Raft node {}: {}", self.config.id, e);
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> Result<()> {
+        match event {
+            Event::Message(message) => self.handle_message(message),
+            Event::Propose(command, reply) => {
+                if self.role != RaftRole::Leader {
+                    let _ = reply.send(Err(self.not_leader()));
+                    return Ok(());
+                }
+                let index = self.log.last_index() + 1;
+                let term = self.state.term;
+                self.log.append(&[Entry {
+                    index,
+                    term,
+                    command: Some(command),
+                }])?;
+                self.proposals.insert(index, (term, reply));
+                self.broadcast();
+                Ok(())
+            }
+            Event::Read(op, reply) => {
+                if self.role != RaftRole::Leader {
+                    let _ = reply.send(Err(self.not_leader()));
+                    return Ok(());
+                }
+                //下一轮心跳得到多数确认，说明读请求到达时自己仍然是主节点
+                self.reads.push(PendingRead {
+                    read_index: self.commit_index,
+                    seq: self.seq + 1,
+                    op,
+                    reply,
+                });
+                self.broadcast();
+                Ok(())
+            }
+            Event::Status(reply) => {
+                let _ = reply.send(RaftStatus {
+                    id: self.config.id,
+                    role: self.role,
+                    term: self.state.term,
+                    leader: self.leader,
+                    commit_index: self.commit_index,
+                    last_applied: self.last_applied,
+                });
+                Ok(())
+            }
+            Event::Shutdown => Ok(()),
+        }
+    }
+
+    ///超时：主节点发送心跳，其它节点发起选举
+    fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        match self.role {
+            RaftRole::Leader if now >= self.next_heartbeat => self.broadcast(),
+            RaftRole::Follower | RaftRole::Candidate if now >= self.election_deadline => {
+                self.start_election()?
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: Message) -> Result<()> {
+        if message.term() > self.state.term {
+            self.become_follower(message.term())?;
+        }
+        match message {
+            Message::RequestVote {
+                term,
+                from,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = (last_log_term, last_log_index) >= (self.log.last_term(), self.log.last_index());
+                let granted = term == self.state.term
+                    && self.state.voted_for.is_none_or(|id| id == from)
+                    && up_to_date;
+                if granted {
+                    self.state.voted_for = Some(from);
+                    self.log.save_state(self.state)?;
+                    self.reset_election_deadline();
+                }
+                self.send(
+                    from,
+                    Message::Vote {
+                        term: self.state.term,
+                        from: self.config.id,
+                        granted,
+                    },
+                );
+            }
+            Message::Vote { term, from, granted } => {
+                if self.role == RaftRole::Candidate && term == self.state.term && granted {
+                    self.votes.insert(from);
+                    if self.votes.len() >= self.quorum() {
+                        self.become_leader()?;
+                    }
+                }
+            }
+            Message::AppendEntries {
+                term,
+                from,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+                seq,
+            } => {
+                let (success, match_index) = if term < self.state.term {
+                    (false, 0)
+                } else {
+                    self.follow(from);
+                    self.append_entries(prev_index, prev_term, entries, commit)?
+                };
+                self.respond(from, success, match_index, seq);
+            }
+            Message::InstallSnapshot {
+                term,
+                from,
+                index,
+                snapshot_term,
+                offset,
+                families,
+                done,
+                seq,
+            } => {
+                if term < self.state.term {
+                    self.respond(from, false, 0, seq);
+                    return Ok(());
+                }
+                self.follow(from);
+                //已经提交的部分与主节点一致，不需要快照
+                if index <= self.commit_index {
+                    self.incoming = None;
+                    if done {
+                        self.respond(from, true, index, seq);
+                    }
+                    return Ok(());
+                }
+                if offset == 0 {
+                    let snapshot = Snapshot {
+                        index,
+                        term: snapshot_term,
+                        families: Vec::new(),
+                    };
+                    self.incoming = Some((snapshot, 0));
+                }
+                let received = match &mut self.incoming {
+                    Some((snapshot, received))
+                        if snapshot.index == index && snapshot.term == snapshot_term && *received == offset =>
+                    {
+                        for family in families {
+                            *received += family.entries.len() as u64;
+                            //一个列族可能被分在相邻的几块中
+                            match snapshot.families.last_mut() {
+                                Some(last) if last.name == family.name => last.entries.extend(family.entries),
+                                _ => snapshot.families.push(family),
+                            }
+                        }
+                        true
+                    }
+                    _ => false,
+                };
+                //中间丢了块时丢弃已经收到的部分，主节点收到最后一块的失败响应后从头重发
+                if !received {
+                    self.incoming = None;
+                    if done {
+                        self.respond(from, false, self.commit_index, seq);
+                    }
+                    return Ok(());
+                }
+                if !done {
+                    return Ok(());
+                }
+                let (snapshot, _) = self.incoming.take().expect("snapshot is being received");
+                //先替换数据再保存快照，崩溃后最多重新应用快照之后的条目
+                FamilySnapshot::install(&mut self.store, snapshot.families.clone())?;
+                self.log.install_snapshot(&snapshot)?;
+                self.commit_index = index;
+                self.last_applied = index;
+                self.respond(from, true, index, seq);
+            }
+            Message::AppendResponse {
+                term,
+                from,
+                success,
+                match_index,
+                seq,
+            } => {
+                if self.role != RaftRole::Leader || term != self.state.term {
+                    return Ok(());
+                }
+                let acked = self.acked_seq.entry(from).or_insert(0);
+                *acked = (*acked).max(seq);
+                if success {
+                    let matched = self.match_index.entry(from).or_insert(0);
+                    *matched = (*matched).max(match_index);
+                    let next = *matched + 1;
+                    self.next_index.insert(from, next);
+                    //落后较多时继续发送，不必等下一次心跳
+                    if next <= self.log.last_index() {
+                        self.send_append(from)?;
+                    }
+                } else {
+                    let next = self.next_index.get(&from).copied().unwrap_or(1);
+                    self.next_index
+                        .insert(from, next.saturating_sub(1).min(match_index + 1).max(1));
+                    self.send_append(from)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///跟随者追加主节点的条目，返回是否成功以及与主节点一致的最后位置
+    fn append_entries(&mut self, prev_index: u64, prev_term: u64, entries: Vec<Entry>, commit: u64) -> Result<(bool, u64)> {
+        if prev_index > self.log.last_index() {
+            return Ok((false, self.log.last_index()));
+        }
+        //快照之前的条目都已提交，一定与主节点一致
+        if prev_index > self.log.snapshot_index() && self.log.term_at(prev_index) != Some(prev_term) {
+            return Ok((false, prev_index - 1));
+        }
+        let last_new = prev_index + entries.len() as u64;
+        let mut new = Vec::new();
+        for entry in entries {
+            if entry.index <= self.log.snapshot_index() {
+                continue;
+            }
+            if new.is_empty() {
+                match self.log.term_at(entry.index) {
+                    Some(term) if term == entry.term => continue,
+                    Some(_) => self.log.truncate_from(entry.index)?,
+                    None => {}
+                }
+            }
+            new.push(entry);
+        }
+        self.log.append(&new)?;
+        if commit > self.commit_index {
+            self.commit_index = commit.min(last_new).max(self.commit_index);
+        }
+        Ok((true, last_new))
+    }
+
+    ///收到当前任期主节点的消息
+    fn follow(&mut self, leader: u64) {
+        self.leader = Some(leader);
+        if self.role != RaftRole::Follower {
+            self.role = RaftRole::Follower;
+            self.fail_pending();
+        }
+        self.reset_election_deadline();
+    }
+
+    fn respond(&mut self, to: u64, success: bool, match_index: u64, seq: u64) {
+        self.send(
+            to,
+            Message::AppendResponse {
+                term: self.state.term,
+                from: self.config.id,
+                success,
+                match_index,
+                seq,
+            },
+        );
+    }
+
+    fn start_election(&mut self) -> Result<()> {
+        self.state.term += 1;
+        self.state.voted_for = Some(self.config.id);
+        self.log.save_state(self.state)?;
+        self.role = RaftRole::Candidate;
+        self.leader = None;
+        self.votes = [self.config.id].into_iter().collect();
+        self.reset_election_deadline();
+        if self.votes.len() >= self.quorum() {
+            return self.become_leader();
+        }
+        let message = |node: &Node| Message::RequestVote {
+            term: node.state.term,
+            from: node.config.id,
+            last_log_index: node.log.last_index(),
+            last_log_term: node.log.last_term(),
+        };
+        let ids: Vec<u64> = self.peers.keys().copied().collect();
+        for id in ids {
+            let message = message(self);
+            self.send(id, message);
+        }
+        Ok(())
+    }
+
+    ///发现更高的任期，此时还不知道新的主节点
+    fn become_follower(&mut self, term: u64) -> Result<()> {
+        self.state = HardState { term, voted_for: None };
+        self.log.save_state(self.state)?;
+        self.leader = None;
+        if self.role != RaftRole::Follower {
+            self.role = RaftRole::Follower;
+            self.fail_pending();
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> Result<()> {
+        self.role = RaftRole::Leader;
+        self.leader = Some(self.config.id);
+        let next = self.log.last_index() + 1;
+        for &id in self.peers.keys() {
+            self.next_index.insert(id, next);
+            self.match_index.insert(id, 0);
+        }
+        self.acked_seq.clear();
+        //提交一个本任期的空条目，之前任期的条目随之提交，读请求也依赖它
+        self.log.append(&[Entry {
+            index: next,
+            term: self.state.term,
+            command: None,
+        }])?;
+        self.broadcast();
+        Ok(())
+    }
+
+    ///向所有节点发送 AppendEntries，同时作为心跳
+    fn broadcast(&mut self) {
+        self.seq += 1;
+        self.next_heartbeat = Instant::now() + self.config.heartbeat_interval;
+        let ids: Vec<u64> = self.peers.keys().copied().collect();
+        for id in ids {
+            if let Err(e) = self.send_append(id) {
+                eprintln!("Raft node {}: failed to replicate to {}: {}", self.config.id, id, e);
+            }
+        }
+    }
+
+    fn send_append(&mut self, id: u64) -> Result<()> {
+        let next = self.next_index.get(&id).copied().unwrap_or(1);
+        if next <= self.log.snapshot_index() {
+            return self.send_snapshot(id);
+        }
+        let prev_index = next - 1;
+        let message = Message::AppendEntries {
+            term: self.state.term,
+            from: self.config.id,
+            prev_index,
+            prev_term: self.log.term_at(prev_index).unwrap_or(0),
+            entries: self.log.entries_from(next, MAX_BATCH),
+            commit: self.commit_index,
+            seq: self.seq,
+        };
+        self.send(id, message);
+        Ok(())
+    }
+
+    ///把快照分块发给落后的节点
+    fn send_snapshot(&mut self, id: u64) -> Result<()> {
+        let snapshot = self.log.load_snapshot()?;
+        //假定快照能送达，失败时对方的响应会让 next_index 回退
+        self.next_index.insert(id, snapshot.index + 1);
+        let chunks = split_snapshot(snapshot.families, SNAPSHOT_CHUNK);
+        let last = chunks.len() - 1;
+        let mut offset = 0;
+        for (i, families) in chunks.into_iter().enumerate() {
+            let len: usize = families.iter().map(|family| family.entries.len()).sum();
+            self.send(
+                id,
+                Message::InstallSnapshot {
+                    term: self.state.term,
+                    from: self.config.id,
+                    index: snapshot.index,
+                    snapshot_term: snapshot.term,
+                    offset,
+                    families,
+                    done: i == last,
+                    seq: self.seq,
+                },
+            );
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    fn send(&self, id: u64, message: Message) {
+        if let Some(peer) = self.peers.get(&id) {
+            let _ = peer.send(message);
+        }
+    }
+
+    ///每个事件之后：推进提交位置，应用条目，执行可以执行的读请求，必要时生成快照
+    fn advance(&mut self) -> Result<()> {
+        if self.role == RaftRole::Leader {
+            self.advance_commit();
+        }
+        self.apply_committed()?;
+        if self.role == RaftRole::Leader {
+            self.serve_reads()?;
+        }
+        self.maybe_snapshot()
+    }
+
+    ///只有本任期的条目可以通过计数提交
+    fn advance_commit(&mut self) {
+        for index in (self.commit_index + 1..=self.log.last_index()).rev() {
+            if self.log.term_at(index) != Some(self.state.term) {
+                break;
+            }
+            let replicas = 1 + self.match_index.values().filter(|&&matched| matched >= index).count();
+            if replicas >= self.quorum() {
+                self.commit_index = index;
+                break;
+            }
+        }
+    }
+
+    fn apply_committed(&mut self) -> Result<()> {
+        while self.last_applied < self.commit_index {
+            let index = self.last_applied + 1;
+            let entry = match self.log.entry(index) {
+                Some(entry) => entry.clone(),
+                None => return Err(KvError::Corruption(format!("raft entry {} is missing", index))),
+            };
+            let result = match entry.command {
+                Some(Commend::Set { key, value, .. }) => self.store.set(key, value),
+                Some(Commend::Remove { key, .. }) => self.store.remove(key),
+                _ => Ok(()),
+            };
+            //删除不存在的键只影响给客户端的结果，其它错误需要重试
+            if let Err(ref e) = result {
+                if !matches!(e, KvError::KeyNotFound) {
+                    return Err(KvError::Corruption(format!("failed to apply raft entry {}: {}", index, e)));
+                }
+            }
+            self.last_applied = index;
+            if let Some((term, reply)) = self.proposals.remove(&index) {
+                let result = if term == entry.term { result } else { Err(self.not_leader()) };
+                let _ = reply.send(result);
+            }
+        }
+        Ok(())
+    }
+
+    fn serve_reads(&mut self) -> Result<()> {
+        //新主节点的空条目提交之前，commit_index 可能落后
+        if self.log.term_at(self.commit_index) != Some(self.state.term) {
+            return Ok(());
+        }
+        let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.reads)
+            .into_iter()
+            .partition(|read| read.read_index <= self.last_applied && self.confirmed(read.seq));
+        self.reads = waiting;
+        for read in ready {
+            let result = match read.op {
+                ReadOp::Get(key) => self.store.get(key).map(ReadResult::Value),
+                ReadOp::Scan(prefix) => self.store.scan(&prefix).map(ReadResult::Pairs),
+            };
+            let _ = read.reply.send(result);
+        }
+        Ok(())
+    }
+
+    ///多数节点确认了序号为 seq 的消息
+    fn confirmed(&self, seq: u64) -> bool {
+        1 + self.acked_seq.values().filter(|&&acked| acked >= seq).count() >= self.quorum()
+    }
+
+    fn maybe_snapshot(&mut self) -> Result<()> {
+        if self.last_applied - self.log.snapshot_index() < self.config.snapshot_threshold {
+            return Ok(());
+        }
+        let snapshot = Snapshot {
+            index: self.last_applied,
+            term: self.log.term_at(self.last_applied).unwrap_or(0),
+            families: FamilySnapshot::capture(&mut self.store)?,
+        };
+        self.log.install_snapshot(&snapshot)
+    }
+
+    ///失去主节点身份时，等待中的请求都失败
+    fn fail_pending(&mut self) {
+        let leader = self.not_leader_addr();
+        for (_, (_, reply)) in std::mem::take(&mut self.proposals) {
+            let _ = reply.send(Err(KvError::NotLeader { leader: leader.clone() }));
+        }
+        for read in std::mem::take(&mut self.reads) {
+            let _ = read.reply.send(Err(KvError::NotLeader { leader: leader.clone() }));
+        }
+    }
+
+    fn not_leader(&self) -> KvError {
+        KvError::NotLeader {
+            leader: self.not_leader_addr(),
+        }
+    }
+
+    fn not_leader_addr(&self) -> Option<String> {
+        self.leader
+            .filter(|&id| id != self.config.id)
+            .and_then(|id| self.config.client_addr(id))
+    }
+
+    fn quorum(&self) -> usize {
+        self.config.peers.len() / 2 + 1
+    }
+
+    fn reset_election_deadline(&mut self) {
+        self.election_deadline = Instant::now() + self.config.random_election_timeout();
+    }
+}
+
+///把快照切成每块最多 max 个键，空的列族也放进某一块，跟随者才会创建它
+fn split_snapshot(families: Vec<FamilySnapshot>, max: usize) -> Vec<Vec<FamilySnapshot>> {
+    let mut chunks = vec![Vec::new()];
+    let mut len = 0;
+    for family in families {
+        let mut entries = family.entries.into_iter().peekable();
+        loop {
+            if len == max {
+                chunks.push(Vec::new());
+                len = 0;
+            }
+            let piece: Vec<_> = entries.by_ref().take(max - len).collect();
+            len += piece.len();
+            chunks.last_mut().unwrap().push(FamilySnapshot {
+                name: family.name.clone(),
+                options: family.options,
+                entries: piece,
+            });
+            if entries.peek().is_none() {
+                break;
+            }
+        }
+    }
+    chunks
+}
@@ -0,0 +1,124 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::log::Entry;
+use super::Event;
+use crate::replication::FamilySnapshot;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+
+///节点之间的消息，都是单向发送的，响应作为另一条消息发回
+#[derive(Serialize, Deserialize, Debug)]
+pub(super) enum Message {
+    RequestVote {
+        term: u64,
+        from: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        from: u64,
+        granted: bool,
+    },
+    ///seq 用于确认主节点身份（线性一致读）
+    AppendEntries {
+        term: u64,
+        from: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+        seq: u64,
+    },
+    AppendResponse {
+        term: u64,
+        from: u64,
+        success: bool,
+        match_index: u64,
+        seq: u64,
+    },
+    InstallSnapshot {
+        term: u64,
+        from: u64,
+        index: u64,
+        snapshot_term: u64,
+        //快照分块发送，offset 为之前的块中键的个数，done 表示最后一块
+        offset: u64,
+        families: Vec<FamilySnapshot>,
+        done: bool,
+        seq: u64,
+    },
+}
+
+impl Message {
+    pub(super) fn term(&self) -> u64 {
+        match self {
+            Message::RequestVote { term, .. }
+            | Message::Vote { term, .. }
+            | Message::AppendEntries { term, .. }
+            | Message::AppendResponse { term, .. }
+            | Message::InstallSnapshot { term, .. } => *term,
+        }
+    }
+}
+
+///接收其它节点的连接，把收到的消息交给核心线程
+pub(super) fn listen(listener: TcpListener, events: Sender<Event>, shutdown: Arc<AtomicBool>) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        let Ok(stream) = stream else { continue };
+        let events = events.clone();
+        thread::spawn(move || {
+            let reader = BufReader::new(stream);
+            for message in serde_json::Deserializer::from_reader(reader).into_iter::<Message>() {
+                let Ok(message) = message else { return };
+                if events.send(Event::Message(message)).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+///启动向 addr 发送消息的线程，返回消息队列；发送失败的消息直接丢弃，由 Raft 的重试机制补偿
+pub(super) fn connect(addr: SocketAddr) -> Sender<Message> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || send_loop(addr, receiver));
+    sender
+}
+
+fn send_loop(addr: SocketAddr, messages: Receiver<Message>) {
+    let mut connection: Option<BufWriter<TcpStream>> = None;
+    //队列关闭（节点停止）时退出
+    while let Ok(message) = messages.recv() {
+        if connection.is_none() {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    connection = Some(BufWriter::new(stream));
+                }
+                Err(_) => {
+                    //对方不可达，丢弃积压的消息
+                    while messages.try_recv().is_ok() {}
+                    continue;
+                }
+            }
+        }
+        let writer = connection.as_mut().unwrap();
+        let sent = serde_json::to_writer(&mut *writer, &message)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.flush());
+        if sent.is_err() {
+            connection = None;
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! 离线检查和修复数据目录
+//!
+//! 逐个扫描日志文件，遇到无法解码的记录时向后寻找下一条能解码的记录继续扫描，
+//! 中间跳过的字节作为损坏区域报告。有长度的记录（其它格式和加密的日志）都带有 CRC32 校验和，
+//! 内容被改动也能发现，加密的记录还有认证标签；未加密的 JSON 日志是连续的 JSON 流，没有校验和，
+//! 只能发现格式错误，内容被改动但格式仍然合法的记录无法发现，报告中会列出这些日志。修复时把所有能读出的记录重放成一个新的日志文件，原来的日志移到 `corrupt/` 目录。
+//! 原子批次只有全部记录都能读出时才重放，没有读完的批次作为丢失的区域报告。
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::history;
+use crate::keydir::in_range;
+use crate::{
+    check_frame, frame_len, log_path, sorted_gen_list, write_generation, Codec, Commend, KvError, LogHeader,
+    Result, StoreOptions, FRAME_HEADER_LEN,
+};
+
+// 修复时保存原始日志的子目录
+const CORRUPT_DIR: &str = "corrupt";
+
+///日志中无法读取的一段
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    pub gen: u64,
+    pub offset: u64,
+    ///跳过的字节数
+    pub len: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for Damage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.log at offset {}: {} bytes unreadable ({})",
+            self.gen, self.offset, self.len, self.reason
+        )
+    }
+}
+
+///`KvStore::verify` 的结果
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub generations: u64,
+    ///能读出的记录数
+    pub records: u64,
+    pub damaged: Vec<Damage>,
+    ///未加密的 JSON 日志代号：其中的记录没有校验和，只检查了格式
+    pub unchecked: Vec<u64>,
+}
+
+impl VerifyReport {
+    pub fn is_clean(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for damage in &self.damaged {
+            writeln!(f, "{}", damage)?;
+        }
+        if !self.unchecked.is_empty() {
+            writeln!(
+                f,
+                "{} of {} generations are plain JSON logs; their records have no checksum and were only checked for format errors",
+                self.unchecked.len(),
+                self.generations
+            )?;
+        }
+        write!(
+            f,
+            "{} generations, {} readable records, {} damaged regions",
+            self.generations,
+            self.records,
+            self.damaged.len()
+        )
+    }
+}
+
+///`KvStore::repair` 的结果
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RepairReport {
+    ///重放的记录数
+    pub records: u64,
+    ///修复后的键数
+    pub live_keys: u64,
+    ///新写入的日志代号，目录原本没有日志时为 None
+    pub gen: Option<u64>,
+    ///跳过的区域，其中的写入已经丢失
+    pub lost: Vec<Damage>,
+}
+
+impl RepairReport {
+    pub fn lost_bytes(&self) -> u64 {
+        self.lost.iter().map(|damage| damage.len).sum()
+    }
+}
+
+impl fmt::Display for RepairReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for damage in &self.lost {
+            writeln!(f, "lost {}", damage)?;
+        }
+        write!(
+            f,
+            "recovered {} records into {} keys, lost {} bytes in {} regions",
+            self.records,
+            self.live_keys,
+            self.lost_bytes(),
+            self.lost.len()
+        )
+    }
+}
+
+pub(crate) fn verify(path: &Path, options: &StoreOptions) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    for gen in sorted_gen_list(options.fs(), path)? {
+        report.generations += 1;
+        let scan = scan_generation(path, gen, options, |_, _| report.records += 1)?;
+        if !scan.checked {
+            report.unchecked.push(gen);
+        }
+        report.damaged.extend(scan.damaged);
+    }
+    Ok(report)
+}
+
+pub(crate) fn repair(path: &Path, options: &StoreOptions) -> Result<RepairReport> {
+    let gens = sorted_gen_list(options.fs(), path)?;
+    let mut report = RepairReport::default();
+    //(列族, 键) 到最后一条写入，以及之后还没有合并的 Merge 记录
+    let mut data: BTreeMap<(Option<String>, String), Vec<Commend>> = BTreeMap::new();
+    //被丢掉的删除记录可能有最大的序号
+    let mut last_seq = history::load_last_seq(options.fs(), path)?;
+    let mut replay = |cmd: Commend| match cmd {
+        Commend::Set { ref key, ref cf, .. } | Commend::SetBlob { ref key, ref cf, .. } => {
+            data.insert((cf.clone(), key.clone()), vec![cmd]);
+        }
+        Commend::Merge { ref key, ref cf, .. } => data.entry((cf.clone(), key.clone())).or_default().push(cmd),
+        Commend::Remove { key, cf, .. } | Commend::Expire { key, cf, .. } => {
+            data.remove(&(cf, key));
+        }
+        Commend::RemoveRange { start, end, cf, .. } => {
+            data.retain(|(family, key), _| *family != cf || !in_range(key, &start, end.as_deref()))
+        }
+        Commend::DropFamily { cf } => data.retain(|(family, _), _| family.as_ref() != Some(&cf)),
+        Commend::Batch { .. } | Commend::CreateFamily { .. } => {}
+    };
+    for &gen in &gens {
+        //正在读取的批次：批次开始的位置，还差几条记录，已经读到的记录
+        let mut batch: Option<(u64, usize, Vec<Commend>)> = None;
+        //最后一条记录的末尾
+        let mut end = 0;
+        let scan = scan_generation(path, gen, options, |cmd, range| {
+            report.records += 1;
+            last_seq = last_seq.max(cmd.seq());
+            end = range.end;
+            match (cmd, &mut batch) {
+                (Commend::Batch { len }, _) => batch = Some((range.start, len, Vec::with_capacity(len))),
+                (cmd, Some((_, remaining, pending))) => {
+                    pending.push(cmd);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        batch.take().unwrap().2.into_iter().for_each(&mut replay);
+                    }
+                }
+                (cmd, None) => replay(cmd),
+            }
+        })?;
+        report.lost.extend(scan.damaged);
+        //日志在批次中间结束，已经读到的记录也一起丢弃
+        if let Some((start, remaining, pending)) = batch {
+            report.lost.push(Damage {
+                gen,
+                offset: start,
+                len: end - start,
+                reason: format!(
+                    "incomplete batch, {} of {} records missing",
+                    remaining,
+                    remaining + pending.len()
+                ),
+            });
+        }
+    }
+    let Some(&last) = gens.last() else {
+        return Ok(report);
+    };
+
+    //先写好新的日志，再把原来的日志移走；没有指定格式时沿用最新的日志文件的格式
+    let gen = last + 1;
+    let fs = options.fs();
+    let mut options = options.clone();
+    if options.format.is_none() {
+        let header = LogHeader::read(last, &mut fs.open(&log_path(path, last))?);
+        options.format = header.ok().map(|header| header.format);
+    }
+    report.live_keys = data.len() as u64;
+    write_generation(path, gen, &options, data.into_values().flatten())?;
+    history::save_last_seq(fs, path, last_seq)?;
+    let corrupt = path.join(CORRUPT_DIR);
+    fs.create_dir_all(&corrupt)?;
+    for stale in gens {
+        fs.rename(&log_path(path, stale), &corrupt.join(format!("{}.log", stale)))?;
+    }
+    report.gen = Some(gen);
+    Ok(report)
+}
+
+///扫描一个日志文件的结果
+struct GenerationScan {
+    damaged: Vec<Damage>,
+    ///记录是否有校验和，没有时只能发现格式错误
+    checked: bool,
+}
+
+///扫描一个日志文件，把能读出的命令和它在文件中的位置交给 on_record，返回损坏的区域
+fn scan_generation(
+    path: &Path,
+    gen: u64,
+    options: &StoreOptions,
+    mut on_record: impl FnMut(Commend, Range<u64>),
+) -> Result<GenerationScan> {
+    let buf = options.fs().read(&log_path(path, gen))?;
+    let mut file = options.fs().open(&log_path(path, gen))?;
+    let whole = |reason: String| {
+        Ok(GenerationScan {
+            damaged: vec![Damage {
+                gen,
+                offset: 0,
+                len: buf.len() as u64,
+                reason,
+            }],
+            //整个文件都报告为损坏，不需要再说明
+            checked: true,
+        })
+    };
+    let header = match LogHeader::read(gen, &mut file) {
+        Ok(header) => header,
+        Err(e) => return whole(e.to_string()),
+    };
+    let codec = match header.codec(gen, options) {
+        Ok(codec) => codec,
+        Err(e @ KvError::WrongKey { .. }) => return whole(e.to_string()),
+        Err(e) => return Err(e),
+    };
+
+    let mut damaged = Vec::new();
+    let mut pos = header.len() as usize;
+    while pos < buf.len() {
+        match decode(&buf, pos, codec) {
+            Ok((cmd, end)) => {
+                on_record(cmd, pos as u64..end as u64);
+                pos = end;
+            }
+            Err(reason) => {
+                //寻找下一条能完整解码的记录
+                let resume = (pos + 1..buf.len())
+                    .find(|&next| {
+                        looks_like_record(&buf, next, codec) && decode(&buf, next, codec).is_ok()
+                    })
+                    .unwrap_or(buf.len());
+                damaged.push(Damage {
+                    gen,
+                    offset: pos as u64,
+                    len: (resume - pos) as u64,
+                    reason,
+                });
+                pos = resume;
+            }
+        }
+    }
+    Ok(GenerationScan {
+        damaged,
+        checked: codec.framed(),
+    })
+}
+
+///从 pos 解码一条命令，返回命令和它的末尾位置
+fn decode(buf: &[u8], pos: usize, codec: Codec) -> std::result::Result<(Commend, usize), String> {
+    if !codec.framed() {
+        let mut stream =
+            serde_json::Deserializer::from_slice(&buf[pos..]).into_iter::<Commend>();
+        return match stream.next() {
+            Some(Ok(cmd)) => Ok((cmd, pos + stream.byte_offset())),
+            Some(Err(e)) if e.is_eof() => Err("truncated record".to_owned()),
+            Some(Err(e)) => Err(format!("malformed record: {}", e)),
+            None => Err("truncated record".to_owned()),
+        };
+    }
+    let header = frame_header(buf, pos).ok_or("truncated record header")?;
+    let start = pos + FRAME_HEADER_LEN as usize;
+    let body = buf.get(start..start + frame_len(header) as usize).ok_or("truncated record")?;
+    check_frame(header, body).map_err(|_| "checksum mismatch".to_owned())?;
+    let payload = codec.open(body, pos as u64).map_err(|_| "authentication failed".to_owned())?;
+    let cmd = codec.format.decode(&payload).map_err(|e| format!("malformed record: {}", e))?;
+    Ok((cmd, start + body.len()))
+}
+
+fn frame_header(buf: &[u8], pos: usize) -> Option<&[u8; FRAME_HEADER_LEN as usize]> {
+    buf.get(pos..pos + FRAME_HEADER_LEN as usize)?.try_into().ok()
+}
+
+///快速过滤不可能是记录开头的位置，只在少数位置上尝试完整解码，跳过损坏区域的时间和区域的长度成正比
+fn looks_like_record(buf: &[u8], pos: usize, codec: Codec) -> bool {
+    //JSON 记录都是以变体名为键的对象：`{"` 之后是大写字母开头的名字和 `":`，记录中的字段名都是小写的
+    if !codec.framed() {
+        let Some(rest) = buf[pos..].strip_prefix(b"{\"") else {
+            return false;
+        };
+        let name_len = rest.iter().take_while(|byte| byte.is_ascii_alphabetic()).count();
+        return rest.first().is_some_and(u8::is_ascii_uppercase) && rest[name_len..].starts_with(b"\":");
+    }
+    //长度在文件范围内并且校验和一致
+    frame_header(buf, pos).is_some_and(|header| {
+        let start = pos + FRAME_HEADER_LEN as usize;
+        buf.get(start..start + frame_len(header) as usize)
+            .is_some_and(|body| check_frame(header, body).is_ok())
+    })
+}
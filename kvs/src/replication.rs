@@ -0,0 +1,469 @@
+//! 主从复制：主节点把日志中追加的记录通过 TCP 发送给从节点，从节点按顺序应用
+//!
+//! 从节点连接后先发送 `Hello`，带上已经应用到的位置；主节点从该位置开始补发日志中的记录，
+//! 如果该位置所在的日志已经被 compaction 删除，则发送整个数据的检查点（快照），之后实时转发新的写入。
+//! 从节点可能重复应用同一条记录（应用之后、保存位置之前崩溃），所以合并记录换成合并之后的值发送。
+//! 所有列族都会被复制；从节点上没有的列族在第一次收到它的记录时用默认配置创建，检查点中带有列族的配置。
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::watch;
+use crate::{
+    Change, FamilyOptions, KvError, KvStore, KvsEngine, LogPosition, Result, StoreOptions, WatchEvent, DEFAULT_FAMILY,
+};
+
+// 没有写入时主节点发送心跳的间隔，从节点据此计算延迟并发现断线
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+// 从节点保存已应用位置的文件
+const POSITION_FILE: &str = "replication.pos";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Hello {
+    from: Option<LogPosition>,
+}
+
+///主节点发给从节点的消息
+#[derive(Serialize, Deserialize, Debug)]
+enum LeaderMessage {
+    ///检查点：主节点所有列族的配置和数据，以及对应的日志位置
+    Snapshot {
+        position: LogPosition,
+        families: Vec<FamilySnapshot>,
+    },
+    ///一次写入的记录，原子批次的记录在同一条消息中，从节点一起应用
+    Records(Vec<WatchEvent>),
+    Heartbeat { position: LogPosition },
+}
+
+///检查点中一个列族的配置和数据，数据为键、值和过期时间；Raft 的快照也使用它
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct FamilySnapshot {
+    pub(crate) name: String,
+    pub(crate) options: FamilyOptions,
+    pub(crate) entries: Vec<(String, String, Option<u64>)>,
+}
+
+impl FamilySnapshot {
+    ///读取所有列族的配置和数据
+    pub(crate) fn capture(store: &mut KvStore) -> Result<Vec<FamilySnapshot>> {
+        store
+            .families()
+            .into_iter()
+            .map(|(name, options)| {
+                let entries = store.scan_expiring_cf(&name, "")?;
+                Ok(FamilySnapshot { name, options, entries })
+            })
+            .collect()
+    }
+
+    ///用快照替换 store 的数据，快照中没有的列族被删除
+    pub(crate) fn install(store: &mut KvStore, families: Vec<FamilySnapshot>) -> Result<()> {
+        let stale: Vec<String> = store
+            .families()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| name != DEFAULT_FAMILY && !families.iter().any(|family| family.name == *name))
+            .collect();
+        for name in stale {
+            store.drop_family(&name)?;
+        }
+        for family in families {
+            ensure_family(store, &family.name, family.options)?;
+            store.replace_all(&family.name, family.entries)?;
+        }
+        Ok(())
+    }
+}
+
+///复制的主节点，监听从节点的连接；丢弃时停止接受连接，发送线程也随之退出
+pub struct ReplicationLeader {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ReplicationLeader {
+    ///在 addr 上接受从节点连接，store 同时可以被其它组件（例如 kvs-server）使用
+    pub fn start(store: Arc<Mutex<KvStore>>, addr: impl ToSocketAddrs) -> Result<ReplicationLeader> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&shutdown);
+        let handle = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if flag.load(Ordering::SeqCst) {
+                    return;
+                }
+                let Ok(stream) = stream else { continue };
+                let (store, flag) = (Arc::clone(&store), Arc::clone(&flag));
+                thread::spawn(move || {
+                    if let Err(e) = ship_log(&store, stream, &flag) {
+                        eprintln!("Replication to follower stopped: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(ReplicationLeader {
+            local_addr,
+            shutdown,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for ReplicationLeader {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        //唤醒阻塞在 accept 上的监听线程
+        let _ = TcpStream::connect_timeout(&self.local_addr, Duration::from_millis(200));
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+///向一个从节点发送日志
+fn ship_log(store: &Mutex<KvStore>, stream: TcpStream, shutdown: &AtomicBool) -> Result<()> {
+    let mut reader = serde_json::Deserializer::from_reader(BufReader::new(stream.try_clone()?));
+    let mut writer = BufWriter::new(stream);
+    let hello = Hello::deserialize(&mut reader)?;
+
+    //持有锁期间完成订阅和补发数据的读取，保证两者之间没有遗漏的写入
+    let (events, catch_up) = {
+        let mut store = store.lock().unwrap();
+        let events = store.subscribe_groups();
+        let catch_up = match hello.from {
+            Some(from) => match watch::read_change_groups(&store.path, &store.options, None, "", Some(from)) {
+                Ok(groups) => groups
+                    .into_iter()
+                    .map(|group| records(&mut store, group))
+                    .collect::<Result<_>>()?,
+                Err(KvError::PositionCompacted { .. }) => vec![snapshot(&mut store)?],
+                Err(e) => return Err(e),
+            },
+            None => vec![snapshot(&mut store)?],
+        };
+        (events, catch_up)
+    };
+    for message in catch_up {
+        send(&mut writer, &message)?;
+    }
+
+    //主节点停止后，在下一条记录或心跳之后退出
+    while !shutdown.load(Ordering::SeqCst) {
+        let message = match events.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(group) => records(&mut store.lock().unwrap(), group)?,
+            Err(RecvTimeoutError::Timeout) => LeaderMessage::Heartbeat {
+                position: store.lock().unwrap().position(),
+            },
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        send(&mut writer, &message)?;
+    }
+    Ok(())
+}
+
+///一次写入的记录组成的消息，合并记录换成合并之后的值
+fn records(store: &mut KvStore, group: Vec<WatchEvent>) -> Result<LeaderMessage> {
+    let group = group.into_iter().map(|event| resolve_merge(store, event)).collect::<Result<_>>()?;
+    Ok(LeaderMessage::Records(group))
+}
+
+///把合并记录换成键现在的值，重复应用也不会重复计算；之后的记录会把值更新到对应的位置
+fn resolve_merge(store: &mut KvStore, event: WatchEvent) -> Result<WatchEvent> {
+    let Change::Merge { key, .. } = &event.change else {
+        return Ok(event);
+    };
+    let change = match store.current_value(&event.family, key) {
+        Ok(Some(value)) => Change::Set {
+            key: key.clone(),
+            value,
+            expires: None,
+        },
+        Ok(None) => Change::Remove { key: key.clone() },
+        //无法合并的记录原样发送，从节点上读取同样会失败
+        Err(KvError::MergeFailed(_)) => return Ok(event),
+        Err(e) => return Err(e),
+    };
+    Ok(WatchEvent { change, ..event })
+}
+
+fn snapshot(store: &mut KvStore) -> Result<LeaderMessage> {
+    Ok(LeaderMessage::Snapshot {
+        families: FamilySnapshot::capture(store)?,
+        position: store.position(),
+    })
+}
+
+fn send(writer: &mut BufWriter<TcpStream>, message: &LeaderMessage) -> Result<()> {
+    serde_json::to_writer(&mut *writer, message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+///从节点的复制状态
+#[derive(Debug, Clone, Default)]
+pub struct ReplicationStatus {
+    ///是否连接着主节点
+    pub connected: bool,
+    ///已经应用的主节点日志位置
+    pub applied: Option<LogPosition>,
+    ///主节点最近一次报告的日志末尾位置
+    pub leader: Option<LogPosition>,
+    ///最近一次收到主节点消息的时间
+    pub last_contact: Option<Instant>,
+}
+
+impl ReplicationStatus {
+    ///落后主节点的字节数；两者不在同一个日志文件时无法比较，返回 None
+    pub fn lag_bytes(&self) -> Option<u64> {
+        match (self.applied, self.leader) {
+            (Some(applied), Some(leader)) if applied.gen == leader.gen => {
+                Some(leader.offset.saturating_sub(applied.offset))
+            }
+            _ => None,
+        }
+    }
+}
+
+///复制的从节点：在后台线程中接收并应用主节点的日志，对外只读
+pub struct Follower {
+    store: Arc<Mutex<KvStore>>,
+    status: Arc<Mutex<ReplicationStatus>>,
+    shutdown: Arc<AtomicBool>,
+    //当前与主节点的连接，关闭时用来打断阻塞的读取
+    connection: Arc<Mutex<Option<TcpStream>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Follower {
+    ///打开 path 中的数据，并从 leader 复制
+    pub fn start(path: impl Into<PathBuf>, leader: SocketAddr) -> Result<Follower> {
+        Follower::start_with(path, StoreOptions::default(), leader)
+    }
+
+    pub fn start_with(path: impl Into<PathBuf>, options: StoreOptions, leader: SocketAddr) -> Result<Follower> {
+        let path = path.into();
+        let store = Arc::new(Mutex::new(KvStore::open_with(&path, options)?));
+        let status = Arc::new(Mutex::new(ReplicationStatus {
+            applied: load_position(&path)?,
+            ..ReplicationStatus::default()
+        }));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let connection = Arc::new(Mutex::new(None));
+
+        let replica = Replica {
+            path,
+            leader,
+            store: Arc::clone(&store),
+            status: Arc::clone(&status),
+            shutdown: Arc::clone(&shutdown),
+            connection: Arc::clone(&connection),
+        };
+        let handle = thread::spawn(move || replica.run());
+        Ok(Follower {
+            store,
+            status,
+            shutdown,
+            connection,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn status(&self) -> ReplicationStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    ///共享的状态，Follower 交给 kvs-server 之后仍然可以读取
+    pub fn status_handle(&self) -> Arc<Mutex<ReplicationStatus>> {
+        Arc::clone(&self.status)
+    }
+
+    ///读取列族 family 中键的值，列族还没有复制过来时返回 `KvError::FamilyNotFound`
+    pub fn get_cf(&self, family: &str, key: String) -> Result<Option<String>> {
+        self.store.lock().unwrap().get_cf(family, key)
+    }
+}
+
+///停止复制线程，之后可以安全地重新打开同一个目录
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(stream) = self.connection.lock().unwrap().take() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+///从节点拒绝写入
+impl KvsEngine for Follower {
+    fn set(&mut self, _key: String, _value: String) -> Result<()> {
+        Err(KvError::ReadOnly)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.store.lock().unwrap().get(key)
+    }
+
+    fn remove(&mut self, _key: String) -> Result<()> {
+        Err(KvError::ReadOnly)
+    }
+
+    fn set_with_ttl(&mut self, _key: String, _value: String, _ttl: Duration) -> Result<()> {
+        Err(KvError::ReadOnly)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.store.lock().unwrap().scan(prefix)
+    }
+}
+
+///从节点的后台复制线程
+struct Replica {
+    path: PathBuf,
+    leader: SocketAddr,
+    store: Arc<Mutex<KvStore>>,
+    status: Arc<Mutex<ReplicationStatus>>,
+    shutdown: Arc<AtomicBool>,
+    connection: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Replica {
+    ///断线后自动重连，直到 Follower 被丢弃
+    fn run(self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            if let Err(e) = self.replicate() {
+                if !self.shutdown.load(Ordering::SeqCst) {
+                    eprintln!("Replication from {} interrupted: {}", self.leader, e);
+                }
+            }
+            self.status.lock().unwrap().connected = false;
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn replicate(&self) -> Result<()> {
+        let stream = TcpStream::connect_timeout(&self.leader, READ_TIMEOUT)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        *self.connection.lock().unwrap() = Some(stream.try_clone()?);
+        //连接建立前 Follower 可能已经被丢弃
+        if self.shutdown.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut reader = serde_json::Deserializer::from_reader(BufReader::new(stream.try_clone()?));
+        let mut writer = BufWriter::new(stream);
+        let from = self.status.lock().unwrap().applied;
+        serde_json::to_writer(&mut writer, &Hello { from })?;
+        writer.flush()?;
+        self.status.lock().unwrap().connected = true;
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            let message = LeaderMessage::deserialize(&mut reader)?;
+            let applied = match message {
+                LeaderMessage::Snapshot { position, families } => {
+                    self.apply_snapshot(families)?;
+                    Some(position)
+                }
+                LeaderMessage::Records(group) => {
+                    //持有锁应用整组记录，读取的一方看不到半个批次
+                    let mut store = self.store.lock().unwrap();
+                    let mut applied = None;
+                    for event in group {
+                        apply(&mut store, &event.family, event.change)?;
+                        applied = Some(event.position);
+                    }
+                    applied
+                }
+                LeaderMessage::Heartbeat { position } => {
+                    self.status.lock().unwrap().leader = Some(position);
+                    None
+                }
+            };
+            if let Some(position) = applied {
+                save_position(&self.path, position)?;
+            }
+            let mut status = self.status.lock().unwrap();
+            status.last_contact = Some(Instant::now());
+            if let Some(position) = applied {
+                status.applied = Some(position);
+                status.leader = Some(status.leader.map_or(position, |leader| leader.max(position)));
+            }
+        }
+        Ok(())
+    }
+
+    ///用检查点替换本地数据，检查点中没有的列族被删除
+    fn apply_snapshot(&self, families: Vec<FamilySnapshot>) -> Result<()> {
+        FamilySnapshot::install(&mut self.store.lock().unwrap(), families)
+    }
+}
+
+///应用一条记录，可能被重复应用
+fn apply(store: &mut KvStore, family: &str, change: Change) -> Result<()> {
+    //创建记录总是在列族的写入之前，这里只是保证写入时列族存在
+    if !matches!(change, Change::DropFamily | Change::CreateFamily { .. }) {
+        ensure_family(store, family, FamilyOptions::default())?;
+    }
+    match change {
+        //补发时可能重复应用同一条创建记录
+        Change::CreateFamily { options } => ensure_family(store, family, options),
+        Change::Set { key, value, expires } => store.set_expiring_cf(family, key, value, expires),
+        //补发时可能重复应用同一条删除记录，过期的键在本地读取时也可能已经删除
+        Change::Remove { key } | Change::Expire { key } => match store.remove_cf(family, key) {
+            Err(KvError::KeyNotFound) => Ok(()),
+            result => result,
+        },
+        Change::Merge { key, operator, operand } => store.merge_cf(family, key, &operator, operand),
+        Change::RemoveRange { start, end } => store.delete_range(family, start, end).map(|_| ()),
+        //补发时可能重复应用同一条删除记录
+        Change::DropFamily => match store.drop_family(family) {
+            Err(KvError::FamilyNotFound(_)) => Ok(()),
+            result => result,
+        },
+    }
+}
+
+///从节点上没有的列族用 options 创建
+fn ensure_family(store: &mut KvStore, family: &str, options: FamilyOptions) -> Result<()> {
+    match store.create_family(family, options) {
+        Err(KvError::FamilyExists(_)) => Ok(()),
+        result => result,
+    }
+}
+
+fn load_position(path: &Path) -> Result<Option<LogPosition>> {
+    match fs::read(path.join(POSITION_FILE)) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+///先写临时文件并落盘再重命名，崩溃后保存的位置要么是旧的要么是新的，不会不完整
+fn save_position(path: &Path, position: LogPosition) -> Result<()> {
+    let tmp = path.join(format!("{}.tmp", POSITION_FILE));
+    let mut file = File::create(&tmp)?;
+    serde_json::to_writer(&mut file, &position)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(tmp, path.join(POSITION_FILE))?;
+    Ok(())
+}
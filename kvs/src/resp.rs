@@ -0,0 +1,371 @@
+//! Redis RESP2 协议的服务端，`redis-cli` 和现有的 Redis 客户端可以直接访问 kvs
+//!
+//! 支持 `PING`、`GET`、`SET`（`NX`/`XX`/`EX`/`PX`）、`DEL`、`EXISTS`、`KEYS`、`SCAN`、`INFO`。
+//! `EX`/`PX` 通过 `KvsEngine::set_with_ttl` 写入存储引擎，kvs 引擎把过期时间写在日志中，
+//! 服务重启后键仍然会过期；不支持过期的引擎对带过期时间的 `SET` 返回错误。
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::engine::with_engine;
+use crate::{KvError, KvsEngine, Result};
+
+// SCAN 默认每次返回的键数
+const DEFAULT_SCAN_COUNT: usize = 10;
+// 单个请求参数的最大长度，防止恶意的长度前缀耗尽内存
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+///RESP2 的响应
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn ok() -> Reply {
+        Reply::Status("OK".to_owned())
+    }
+
+    fn error(message: impl Into<String>) -> Reply {
+        Reply::Error(format!("ERR {}", message.into()))
+    }
+
+    fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Reply::Status(status) => write!(writer, "+{}\r\n", status),
+            Reply::Error(message) => write!(writer, "-{}\r\n", message),
+            Reply::Integer(n) => write!(writer, ":{}\r\n", n),
+            Reply::Bulk(None) => write!(writer, "$-1\r\n"),
+            Reply::Bulk(Some(value)) => write!(writer, "${}\r\n{}\r\n", value.len(), value),
+            Reply::Array(items) => {
+                write!(writer, "*{}\r\n", items.len())?;
+                items.iter().try_for_each(|item| item.write_to(writer))
+            }
+        }
+    }
+}
+
+///RESP 服务：每个连接一个线程，所有连接共享同一个引擎
+pub struct RespServer<E: KvsEngine> {
+    engine: Arc<Mutex<E>>,
+}
+
+impl<E: KvsEngine + Send + 'static> RespServer<E> {
+    pub fn new(engine: E) -> RespServer<E> {
+        RespServer::from_shared(Arc::new(Mutex::new(engine)))
+    }
+
+    pub fn from_shared(engine: Arc<Mutex<E>>) -> RespServer<E> {
+        RespServer { engine }
+    }
+
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        self.serve(TcpListener::bind(addr)?)
+    }
+
+    pub fn serve(self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let engine = Arc::clone(&self.engine);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(&engine, stream) {
+                    eprintln!("Error on serving RESP client: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection<E: KvsEngine + 'static>(engine: &Mutex<E>, stream: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let args = match read_command(&mut reader) {
+            Ok(Some(args)) => args,
+            Ok(None) => return Ok(()),
+            //协议错误时回复错误并关闭连接，与 Redis 相同
+            Err(KvError::Protocol(message)) => {
+                Reply::error(format!("Protocol error: {}", message)).write_to(&mut writer)?;
+                writer.flush()?;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        if args.is_empty() {
+            continue;
+        }
+        let quit = args[0].eq_ignore_ascii_case("quit");
+        let reply = with_engine(engine, |engine| execute(engine, args)).unwrap_or_else(|e| Reply::error(e.to_string()));
+        reply.write_to(&mut writer)?;
+        writer.flush()?;
+        if quit {
+            return Ok(());
+        }
+    }
+}
+
+///读取一条命令：RESP 数组，或者 telnet 使用的以空格分隔的一行
+fn read_command(reader: &mut impl BufRead) -> Result<Option<Vec<String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let Some(count) = line.strip_prefix('*') else {
+        return Ok(Some(line.split_whitespace().map(str::to_owned).collect()));
+    };
+    let count = parse_len(count)?;
+    let mut args = Vec::with_capacity(count.min(1024));
+    for _ in 0..count {
+        let header = read_line(reader)?.ok_or_else(|| protocol("unexpected end of stream"))?;
+        let len = header
+            .strip_prefix('$')
+            .ok_or_else(|| protocol(format!("expected '$', got '{}'", header)))?;
+        let len = parse_len(len)?;
+        if len > MAX_BULK_LEN {
+            return Err(protocol("invalid bulk length"));
+        }
+        let mut buf = vec![0; len + 2];
+        reader.read_exact(&mut buf)?;
+        if !buf.ends_with(b"\r\n") {
+            return Err(protocol("bulk string is not terminated by CRLF"));
+        }
+        buf.truncate(len);
+        args.push(String::from_utf8(buf).map_err(|_| protocol("only UTF-8 strings are supported"))?);
+    }
+    Ok(Some(args))
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_owned()))
+}
+
+fn parse_len(s: &str) -> Result<usize> {
+    s.parse().map_err(|_| protocol(format!("invalid length '{}'", s)))
+}
+
+fn protocol(message: impl Into<String>) -> KvError {
+    KvError::Protocol(message.into())
+}
+
+fn execute<E: KvsEngine>(engine: &mut E, args: Vec<String>) -> Result<Reply> {
+    let mut args = args.into_iter();
+    let name = args.next().unwrap_or_default().to_ascii_uppercase();
+    let args: Vec<String> = args.collect();
+    let min_args = match name.as_str() {
+        "SET" => 2,
+        "GET" | "DEL" | "EXISTS" | "KEYS" | "SCAN" => 1,
+        _ => 0,
+    };
+    if args.len() < min_args {
+        return Ok(Reply::error(format!(
+            "wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        )));
+    }
+
+    let reply = match name.as_str() {
+        "PING" => match args.into_iter().next() {
+            Some(message) => Reply::Bulk(Some(message)),
+            None => Reply::Status("PONG".to_owned()),
+        },
+        "QUIT" => Reply::ok(),
+        //redis-cli 启动时会查询命令文档
+        "COMMAND" => Reply::Array(Vec::new()),
+        "GET" => Reply::Bulk(engine.get(args[0].clone())?),
+        "SET" => set(engine, args)?,
+        "DEL" => {
+            let mut removed = 0;
+            for key in args {
+                match engine.remove(key) {
+                    Ok(()) => removed += 1,
+                    Err(KvError::KeyNotFound) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Reply::Integer(removed)
+        }
+        "EXISTS" => {
+            let mut found = 0;
+            for key in args {
+                if engine.get(key)?.is_some() {
+                    found += 1;
+                }
+            }
+            Reply::Integer(found)
+        }
+        "KEYS" => {
+            let keys = live_keys(engine, &args[0])?;
+            Reply::Array(
+                keys.into_iter()
+                    .filter(|key| glob_match(args[0].as_bytes(), key.as_bytes()))
+                    .map(|key| Reply::Bulk(Some(key)))
+                    .collect(),
+            )
+        }
+        "SCAN" => scan(engine, args)?,
+        "INFO" => {
+            let keys = live_keys(engine, "*")?.len();
+            Reply::Bulk(Some(format!(
+                "# Server\r\nkvs_version:{}\r\n\r\n# Keyspace\r\ndb0:keys={}\r\n",
+                env!("CARGO_PKG_VERSION"),
+                keys
+            )))
+        }
+        _ => Reply::error(format!("unknown command '{}'", name.to_ascii_lowercase())),
+    };
+    Ok(reply)
+}
+
+///SET key value [NX|XX] [EX seconds|PX milliseconds]
+fn set<E: KvsEngine>(engine: &mut E, args: Vec<String>) -> Result<Reply> {
+    let mut args = args.into_iter();
+    let (key, value) = (args.next().unwrap(), args.next().unwrap());
+    let (mut nx, mut xx, mut ttl) = (false, false, None);
+    while let Some(option) = args.next() {
+        match option.to_ascii_uppercase().as_str() {
+            "NX" => nx = true,
+            "XX" => xx = true,
+            unit @ ("EX" | "PX") => {
+                let amount: u64 = match args.next().map(|n| n.parse()) {
+                    Some(Ok(amount)) if amount > 0 => amount,
+                    _ => return Ok(Reply::error("invalid expire time in 'set' command")),
+                };
+                ttl = Some(if unit == "EX" {
+                    Duration::from_secs(amount)
+                } else {
+                    Duration::from_millis(amount)
+                });
+            }
+            _ => return Ok(Reply::error("syntax error")),
+        }
+    }
+    if nx && xx {
+        return Ok(Reply::error("syntax error"));
+    }
+    if nx || xx {
+        let exists = engine.get(key.clone())?.is_some();
+        if (nx && exists) || (xx && !exists) {
+            return Ok(Reply::Bulk(None));
+        }
+    }
+    match ttl {
+        Some(ttl) => engine.set_with_ttl(key, value, ttl)?,
+        None => engine.set(key, value)?,
+    }
+    Ok(Reply::ok())
+}
+
+///SCAN cursor [MATCH pattern] [COUNT count]，游标是排序后键列表中的位置
+fn scan<E: KvsEngine>(engine: &mut E, args: Vec<String>) -> Result<Reply> {
+    let Ok(cursor) = args[0].parse::<usize>() else {
+        return Ok(Reply::error("invalid cursor"));
+    };
+    let (mut pattern, mut count) = ("*".to_owned(), DEFAULT_SCAN_COUNT);
+    let mut options = args.into_iter().skip(1);
+    while let Some(option) = options.next() {
+        match (option.to_ascii_uppercase().as_str(), options.next()) {
+            ("MATCH", Some(value)) => pattern = value,
+            ("COUNT", Some(value)) => match value.parse() {
+                Ok(n) if n > 0 => count = n,
+                _ => return Ok(Reply::error("value is not an integer or out of range")),
+            },
+            _ => return Ok(Reply::error("syntax error")),
+        }
+    }
+    let keys = live_keys(engine, &pattern)?;
+    let end = cursor.saturating_add(count).min(keys.len());
+    let page = keys.get(cursor..end).unwrap_or_default();
+    let next = if end >= keys.len() { 0 } else { end };
+    Ok(Reply::Array(vec![
+        Reply::Bulk(Some(next.to_string())),
+        Reply::Array(
+            page.iter()
+                .filter(|key| glob_match(pattern.as_bytes(), key.as_bytes()))
+                .map(|key| Reply::Bulk(Some(key.clone())))
+                .collect(),
+        ),
+    ]))
+}
+
+///没有过期的键，按顺序排列；只取模式中第一个通配符之前的前缀
+fn live_keys<E: KvsEngine>(engine: &mut E, pattern: &str) -> Result<Vec<String>> {
+    let prefix_len = pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len());
+    Ok(engine
+        .scan(&pattern[..prefix_len])?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect())
+}
+
+///Redis 的 glob 匹配：`*`、`?`、`[abc]`/`[a-z]`/`[^a]` 和 `\` 转义
+///
+///遇到不匹配时回到最近的 `*`，让它多匹配一个字符；只需要记住最近的 `*`，时间是 O(模式长度 × 文本长度)
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    //最近的 `*` 之后的模式位置，以及这个 `*` 匹配到的文本末尾
+    let mut star = None;
+    while t < text.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, t));
+            continue;
+        }
+        if let Some(len) = match_one(&pattern[p..], text[t]) {
+            p += len;
+            t += 1;
+            continue;
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p;
+                t = star_t + 1;
+                star = Some((star_p, t));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+///模式开头的一个元素（不是 `*`）是否匹配字符 c，匹配时返回这个元素在模式中的长度
+fn match_one(pattern: &[u8], c: u8) -> Option<usize> {
+    match pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'[', rest @ ..] => {
+            let Some(close) = rest.iter().position(|&b| b == b']') else {
+                return (c == b'[').then_some(1);
+            };
+            let (negate, class) = match &rest[..close] {
+                [b'^', class @ ..] => (true, class),
+                class => (false, class),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == b'-' {
+                    matched |= class[i] <= c && c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == c;
+                    i += 1;
+                }
+            }
+            (matched != negate).then_some(close + 2)
+        }
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [p, ..] => (*p == c).then_some(1),
+    }
+}
@@ -0,0 +1,181 @@
+//! 按 JSON 值中的字段查找键的二级索引
+//!
+//! 索引定义保存在数据目录的 `indexes.json` 中，打开 KvStore 时读取所有的值重建索引，
+//! 之后随 `set` 和 `remove` 更新。路径是用 `.` 分隔的字段名，例如 `address.city`。
+//! 字段的值是字符串、数字或布尔值时才被索引，数字和布尔值按 JSON 文本比较；
+//! 不是 JSON 对象或没有该字段的值不出现在索引中。
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use crate::keydir::in_range;
+use crate::{FileSystem, KvError, Result};
+
+// 保存索引定义的文件
+const INDEXES_FILE: &str = "indexes.json";
+
+///一个二级索引的定义
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexDef {
+    pub name: String,
+    ///被索引的字段，例如 `email` 或 `address.city`
+    pub path: String,
+}
+
+impl IndexDef {
+    pub fn new(name: impl Into<String>, path: impl Into<String>) -> Result<IndexDef> {
+        let path = path.into();
+        if path.split('.').any(str::is_empty) {
+            return Err(KvError::InvalidIndexPath(path));
+        }
+        Ok(IndexDef { name: name.into(), path })
+    }
+
+    ///取出 JSON 值中被索引的字段
+    fn extract(&self, json: &Value) -> Option<String> {
+        let field = self.path.split('.').try_fold(json, |value, name| value.get(name))?;
+        match field {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SecondaryIndex {
+    def: IndexDef,
+    ///字段值到主键
+    by_value: BTreeMap<String, BTreeSet<String>>,
+    ///主键到字段值，更新和删除时用来找到旧的条目
+    by_key: HashMap<String, String>,
+}
+
+impl SecondaryIndex {
+    fn new(def: IndexDef) -> SecondaryIndex {
+        SecondaryIndex {
+            def,
+            by_value: BTreeMap::new(),
+            by_key: HashMap::new(),
+        }
+    }
+
+    fn set(&mut self, key: &str, json: Option<&Value>) {
+        self.remove(key);
+        if let Some(field) = json.and_then(|json| self.def.extract(json)) {
+            self.by_value.entry(field.clone()).or_default().insert(key.to_owned());
+            self.by_key.insert(key.to_owned(), field);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        let Some(field) = self.by_key.remove(key) else {
+            return;
+        };
+        let keys = self.by_value.get_mut(&field).unwrap();
+        keys.remove(key);
+        if keys.is_empty() {
+            self.by_value.remove(&field);
+        }
+    }
+}
+
+///KvStore 的所有二级索引
+#[derive(Debug, Default)]
+pub(crate) struct SecondaryIndexes {
+    indexes: Vec<SecondaryIndex>,
+}
+
+impl SecondaryIndexes {
+    ///读取目录中的索引定义，得到的索引是空的，需要重建
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path) -> Result<SecondaryIndexes> {
+        let path = dir.join(INDEXES_FILE);
+        if !fs.list_files(dir)?.contains(&path) {
+            return Ok(SecondaryIndexes::default());
+        }
+        let defs: Vec<IndexDef> = serde_json::from_slice(&fs.read(&path)?)
+            .map_err(|e| KvError::Corruption(format!("{}: {}", INDEXES_FILE, e)))?;
+        Ok(SecondaryIndexes {
+            indexes: defs.into_iter().map(SecondaryIndex::new).collect(),
+        })
+    }
+
+    pub(crate) fn save(&self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", INDEXES_FILE));
+        let mut file = fs.create(&tmp)?;
+        serde_json::to_writer(&mut file, &self.defs())?;
+        file.sync()?;
+        fs.rename(&tmp, &dir.join(INDEXES_FILE))?;
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.indexes.is_empty()
+    }
+
+    pub(crate) fn defs(&self) -> Vec<IndexDef> {
+        self.indexes.iter().map(|index| index.def.clone()).collect()
+    }
+
+    ///添加一个空的索引，之后由调用方写入已有的值
+    pub(crate) fn create(&mut self, def: IndexDef) -> Result<()> {
+        if self.indexes.iter().any(|index| index.def.name == def.name) {
+            return Err(KvError::IndexExists(def.name));
+        }
+        self.indexes.push(SecondaryIndex::new(def));
+        Ok(())
+    }
+
+    pub(crate) fn drop_index(&mut self, name: &str) -> Result<()> {
+        let before = self.indexes.len();
+        self.indexes.retain(|index| index.def.name != name);
+        if self.indexes.len() == before {
+            return Err(KvError::IndexNotFound(name.to_owned()));
+        }
+        Ok(())
+    }
+
+    ///更新所有索引中键的条目
+    pub(crate) fn set(&mut self, key: &str, value: &str) {
+        if self.indexes.is_empty() {
+            return;
+        }
+        let json = serde_json::from_str::<Value>(value).ok();
+        for index in &mut self.indexes {
+            index.set(key, json.as_ref());
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        for index in &mut self.indexes {
+            index.remove(key);
+        }
+    }
+
+    ///删除所有索引中 [start, end) 中的键
+    pub(crate) fn remove_range(&mut self, start: &str, end: Option<&str>) {
+        for index in &mut self.indexes {
+            let keys: Vec<String> = index.by_key.keys().filter(|key| in_range(key, start, end)).cloned().collect();
+            for key in keys {
+                index.remove(&key);
+            }
+        }
+    }
+
+    ///字段值等于 value 的所有主键，按键排序
+    pub(crate) fn find(&self, name: &str, value: &str) -> Result<Vec<String>> {
+        let index = self
+            .indexes
+            .iter()
+            .find(|index| index.def.name == name)
+            .ok_or_else(|| KvError::IndexNotFound(name.to_owned()))?;
+        Ok(index
+            .by_value
+            .get(value)
+            .map(|keys| keys.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,70 @@
+use serde::Deserialize;
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::engine::with_engine;
+use crate::protocol::{Request, Response};
+use crate::{KvsEngine, Result};
+
+///kvs-server：每个连接一个线程，所有连接共享同一个引擎
+pub struct KvsServer<E: KvsEngine> {
+    engine: Arc<Mutex<E>>,
+}
+
+impl<E: KvsEngine + Send + 'static> KvsServer<E> {
+    pub fn new(engine: E) -> KvsServer<E> {
+        KvsServer::from_shared(Arc::new(Mutex::new(engine)))
+    }
+
+    ///与其它组件（例如复制）共享引擎
+    pub fn from_shared(engine: Arc<Mutex<E>>) -> KvsServer<E> {
+        KvsServer { engine }
+    }
+
+    ///监听地址并处理请求，不会返回，除非监听失败
+    pub fn run(self, addr: impl ToSocketAddrs) -> Result<()> {
+        self.serve(TcpListener::bind(addr)?)
+    }
+
+    ///使用已经绑定的监听器处理请求
+    pub fn serve(self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let engine = Arc::clone(&self.engine);
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(&engine, stream) {
+                    eprintln!("Error on serving client: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection<E: KvsEngine + 'static>(engine: &Mutex<E>, stream: TcpStream) -> Result<()> {
+    let mut reader = serde_json::Deserializer::from_reader(BufReader::new(stream.try_clone()?));
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let request = match Request::deserialize(&mut reader) {
+            Ok(request) => request,
+            //客户端关闭了连接
+            Err(e) if e.is_eof() => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let response = with_engine(engine, |engine| execute(engine, request));
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.flush()?;
+    }
+}
+
+fn execute<E: KvsEngine>(engine: &mut E, request: Request) -> Response {
+    let result = match request {
+        Request::Get { key } => engine.get(key).map(Response::Value),
+        Request::Set { key, value } => engine.set(key, value).map(|_| Response::Done),
+        Request::Remove { key } => engine.remove(key).map(|_| Response::Done),
+        Request::Scan { prefix } => engine.scan(&prefix).map(Response::Pairs),
+    };
+    result.unwrap_or_else(|e| Response::Err(e.into()))
+}
@@ -0,0 +1,184 @@
+//! 客户端分片：用带虚拟节点的一致性哈希环把键分配到多个 kvs-server
+//!
+//! 增加或删除分片时只有环上相邻区间的键需要移动，`ShardedClient::add_shard`/`remove_shard`
+//! 负责把这些键搬到新的归属分片。搬移时先写新分片再删旧分片，中途失败可以重新执行。
+
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::Bound;
+
+use crate::{KvError, KvsClient, KvsEngine, Result};
+
+///每个分片默认的虚拟节点数
+pub const DEFAULT_VNODES: usize = 160;
+
+///一致性哈希环，只依赖分片地址，不同进程计算的结果相同
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    vnodes: usize,
+    ring: BTreeMap<u64, SocketAddr>,
+}
+
+impl HashRing {
+    pub fn new(vnodes: usize) -> HashRing {
+        HashRing {
+            vnodes: vnodes.max(1),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, shard: SocketAddr) {
+        for i in 0..self.vnodes {
+            self.ring.insert(hash(format!("{}#{}", shard, i).as_bytes()), shard);
+        }
+    }
+
+    pub fn remove(&mut self, shard: SocketAddr) {
+        self.ring.retain(|_, addr| *addr != shard);
+    }
+
+    pub fn contains(&self, shard: SocketAddr) -> bool {
+        self.ring.values().any(|addr| *addr == shard)
+    }
+
+    ///环上所有分片，按地址排序
+    pub fn shards(&self) -> Vec<SocketAddr> {
+        let mut shards: Vec<_> = self.ring.values().copied().collect();
+        shards.sort();
+        shards.dedup();
+        shards
+    }
+
+    ///键所属的分片：哈希值之后（顺时针）的第一个虚拟节点
+    pub fn shard_for(&self, key: &str) -> Option<SocketAddr> {
+        self.ring
+            .range((Bound::Included(hash(key.as_bytes())), Bound::Unbounded))
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, addr)| *addr)
+    }
+}
+
+fn hash(data: &[u8]) -> u64 {
+    let digest = Sha256::digest(data);
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+///分片的客户端，接口与 KvStore 相同
+pub struct ShardedClient {
+    ring: HashRing,
+    clients: BTreeMap<SocketAddr, KvsClient>,
+}
+
+impl ShardedClient {
+    pub fn connect(shards: impl IntoIterator<Item = SocketAddr>) -> Result<ShardedClient> {
+        ShardedClient::connect_with(shards, DEFAULT_VNODES)
+    }
+
+    ///所有客户端必须使用相同的分片列表和虚拟节点数，否则会把键写到不同的分片
+    pub fn connect_with(shards: impl IntoIterator<Item = SocketAddr>, vnodes: usize) -> Result<ShardedClient> {
+        let mut client = ShardedClient {
+            ring: HashRing::new(vnodes),
+            clients: BTreeMap::new(),
+        };
+        for shard in shards {
+            client.clients.insert(shard, KvsClient::connect(shard)?);
+            client.ring.add(shard);
+        }
+        Ok(client)
+    }
+
+    pub fn shards(&self) -> Vec<SocketAddr> {
+        self.ring.shards()
+    }
+
+    pub fn shard_for(&self, key: &str) -> Option<SocketAddr> {
+        self.ring.shard_for(key)
+    }
+
+    pub fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.client_for(&key)?.set(key, value)
+    }
+
+    pub fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.client_for(&key)?.get(key)
+    }
+
+    pub fn remove(&mut self, key: String) -> Result<()> {
+        self.client_for(&key)?.remove(key)
+    }
+
+    ///合并所有分片的结果，按键排序
+    pub fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        for client in self.clients.values_mut() {
+            pairs.extend(client.scan(prefix)?);
+        }
+        pairs.sort();
+        Ok(pairs)
+    }
+
+    ///加入新的分片，并把现在归它所有的键从其它分片搬过来，返回搬移的键数
+    pub fn add_shard(&mut self, shard: SocketAddr) -> Result<usize> {
+        if self.ring.contains(shard) {
+            return Ok(0);
+        }
+        let mut target = KvsClient::connect(shard)?;
+        self.ring.add(shard);
+        let mut moved = 0;
+        for client in self.clients.values_mut() {
+            for (key, value) in client.scan("")? {
+                if self.ring.shard_for(&key) == Some(shard) {
+                    target.set(key.clone(), value)?;
+                    client.remove(key)?;
+                    moved += 1;
+                }
+            }
+        }
+        self.clients.insert(shard, target);
+        Ok(moved)
+    }
+
+    ///移除分片，并把它的键搬到剩余的分片，返回搬移的键数
+    pub fn remove_shard(&mut self, shard: SocketAddr) -> Result<usize> {
+        if !self.ring.contains(shard) {
+            return Ok(0);
+        }
+        if self.clients.len() == 1 {
+            return Err(KvError::NoShards);
+        }
+        self.ring.remove(shard);
+        let mut source = self.clients.remove(&shard).ok_or(KvError::NoShards)?;
+        let mut moved = 0;
+        for (key, value) in source.scan("")? {
+            self.client_for(&key)?.set(key.clone(), value)?;
+            source.remove(key)?;
+            moved += 1;
+        }
+        Ok(moved)
+    }
+
+    fn client_for(&mut self, key: &str) -> Result<&mut KvsClient> {
+        let shard = self.ring.shard_for(key).ok_or(KvError::NoShards)?;
+        self.clients.get_mut(&shard).ok_or(KvError::NoShards)
+    }
+}
+
+impl KvsEngine for ShardedClient {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        ShardedClient::set(self, key, value)
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        ShardedClient::get(self, key)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        ShardedClient::remove(self, key)
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        ShardedClient::scan(self, prefix)
+    }
+}
@@ -0,0 +1,162 @@
+//! `kvs shell` 的命令解释器，存储只打开一次，多条命令共用
+//!
+//! 支持 `get KEY`、`set KEY VALUE`、`rm KEY`、`scan [PREFIX]`、`stats`、`help` 和 `exit`。
+//! 参数用空白分隔，包含空白的键和值可以用单引号或双引号括起来，双引号内和引号外可以用 `\` 转义。
+
+use std::io::{BufRead, Write};
+
+use crate::{KvError, KvsEngine, Result, StoreStats};
+
+const HELP: &str = "\
+get KEY            print the value of KEY
+set KEY VALUE      set KEY to VALUE
+rm KEY             remove KEY
+scan [PREFIX]      list key/value pairs whose key starts with PREFIX
+stats              show store statistics (kvs engine only)
+help               show this message
+exit               leave the shell";
+
+///一条命令执行后的结果
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShellReply {
+    ///需要打印的输出，可能为空
+    Output(String),
+    ///退出解释器
+    Exit,
+}
+
+///命令解释器
+pub struct Shell<E: KvsEngine> {
+    engine: E,
+    stats: Option<fn(&mut E) -> Result<StoreStats>>,
+}
+
+impl<E: KvsEngine> Shell<E> {
+    pub fn new(engine: E) -> Shell<E> {
+        Shell { engine, stats: None }
+    }
+
+    ///提供 `stats` 命令的实现，没有提供时该命令报错
+    pub fn stats(mut self, stats: fn(&mut E) -> Result<StoreStats>) -> Shell<E> {
+        self.stats = Some(stats);
+        self
+    }
+
+    ///执行一行命令；空行和 `#` 开头的注释什么也不做
+    pub fn execute(&mut self, line: &str) -> Result<ShellReply> {
+        let words = split_words(line)?;
+        let Some((command, args)) = words.split_first() else {
+            return Ok(ShellReply::Output(String::new()));
+        };
+        let output = match (command.as_str(), args) {
+            ("get", [key]) => match self.engine.get(key.clone())? {
+                Some(value) => value,
+                None => "Key not found".to_owned(),
+            },
+            ("set", [key, value]) => {
+                self.engine.set(key.clone(), value.clone())?;
+                String::new()
+            }
+            ("rm", [key]) => {
+                self.engine.remove(key.clone())?;
+                String::new()
+            }
+            ("scan", []) | ("scan", [_]) => {
+                let prefix = args.first().map_or("", String::as_str);
+                let pairs = self.engine.scan(prefix)?;
+                let lines: Vec<_> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{} {}", quote(key), quote(value)))
+                    .collect();
+                lines.join("\n")
+            }
+            ("stats", []) => match self.stats {
+                Some(stats) => stats(&mut self.engine)?.to_string(),
+                None => return Err(KvError::InvalidCommand("stats is only available for the kvs engine".to_owned())),
+            },
+            ("help", []) => HELP.to_owned(),
+            ("exit", []) | ("quit", []) => return Ok(ShellReply::Exit),
+            ("get" | "set" | "rm" | "scan" | "stats" | "help" | "exit" | "quit", _) => {
+                return Err(KvError::InvalidCommand(format!("wrong number of arguments for '{}'", command)))
+            }
+            _ => return Err(KvError::InvalidCommand(format!("unknown command '{}', try 'help'", command))),
+        };
+        Ok(ShellReply::Output(output))
+    }
+
+    ///逐行执行 input 中的命令，遇到第一个错误时停止并返回带行号的错误
+    pub fn run_script(&mut self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        for (number, line) in input.lines().enumerate() {
+            match self.execute(&line?) {
+                Ok(ShellReply::Output(text)) => {
+                    if !text.is_empty() {
+                        writeln!(output, "{}", text)?;
+                    }
+                }
+                Ok(ShellReply::Exit) => break,
+                Err(e) => return Err(KvError::InvalidCommand(format!("line {}: {}", number + 1, e))),
+            }
+        }
+        output.flush()?;
+        Ok(())
+    }
+}
+
+///按空白拆分参数，处理引号和转义
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    if chars.peek() == Some(&'#') {
+        return Ok(words);
+    }
+    while chars.peek().is_some() {
+        let mut word = String::new();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c.is_whitespace() => break,
+                '\\' => word.push(chars.next().ok_or_else(|| syntax("trailing backslash"))?),
+                '\'' => loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(syntax("unterminated single quote")),
+                    }
+                },
+                '"' => loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.push(chars.next().ok_or_else(|| syntax("unterminated double quote"))?),
+                        Some(c) => word.push(c),
+                        None => return Err(syntax("unterminated double quote")),
+                    }
+                },
+                c => word.push(c),
+            }
+        }
+        words.push(word);
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+    Ok(words)
+}
+
+fn syntax(message: &str) -> KvError {
+    KvError::InvalidCommand(message.to_owned())
+}
+
+///输出时给包含空白、引号或反斜杠的字符串加上双引号，使输出可以原样作为命令参数
+fn quote(s: &str) -> String {
+    let plain = !s.is_empty() && !s.chars().any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | '#'));
+    if plain {
+        return s.to_owned();
+    }
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use crate::{KvError, KvsEngine, Result};
+
+///基于 sled 的存储引擎，用于性能对比，也可以作为稳定的备选实现
+#[derive(Debug, Clone)]
+pub struct SledKvsEngine {
+    db: sled::Db,
+}
+
+impl SledKvsEngine {
+    ///使用已经打开的 sled 数据库
+    pub fn new(db: sled::Db) -> SledKvsEngine {
+        SledKvsEngine { db }
+    }
+
+    ///打开（或创建）目录中的 sled 数据库
+    pub fn open(path: impl AsRef<Path>) -> Result<SledKvsEngine> {
+        Ok(SledKvsEngine::new(sled::open(path)?))
+    }
+}
+
+impl KvsEngine for SledKvsEngine {
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        self.db.insert(key, value.into_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        self.db
+            .get(key)?
+            .map(|value| {
+                String::from_utf8(value.to_vec())
+                    .map_err(|_| KvError::Corruption("sled value is not valid UTF-8".to_owned()))
+            })
+            .transpose()
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        self.db.remove(key)?.ok_or(KvError::KeyNotFound)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn scan(&mut self, prefix: &str) -> Result<Vec<(String, String)>> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|pair| {
+                let (key, value) = pair?;
+                match (String::from_utf8(key.to_vec()), String::from_utf8(value.to_vec())) {
+                    (Ok(key), Ok(value)) => Ok((key, value)),
+                    _ => Err(KvError::Corruption("sled entry is not valid UTF-8".to_owned())),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+
+use crate::{CacheStats, FamilyStats, FileSystem, IndexMode, Result};
+
+// 保存累计计数和最近一次 compaction 信息的文件，日志之外的元数据
+const STATS_FILE: &str = "stats.json";
+
+///累计的操作次数，跨越多次打开
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpCounters {
+    pub gets: u64,
+    pub sets: u64,
+    pub removes: u64,
+    #[serde(default)]
+    pub merges: u64,
+    pub scans: u64,
+    pub compactions: u64,
+}
+
+///最近一次 compaction
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionInfo {
+    ///完成时间，UNIX 时间戳（秒）
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    ///compaction 前后日志总大小的差
+    pub reclaimed_bytes: u64,
+}
+
+///一个日志文件的大小
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerationStats {
+    pub gen: u64,
+    pub bytes: u64,
+}
+
+///`KvStore::stats` 的结果
+#[derive(Serialize, Debug, Clone)]
+pub struct StoreStats {
+    pub live_keys: u64,
+    ///所有日志文件的总大小
+    pub total_bytes: u64,
+    ///compaction 可以回收的陈旧记录大小
+    pub stale_bytes: u64,
+    pub generations: Vec<GenerationStats>,
+    ///值日志文件及其大小，gen 是值日志文件的编号
+    pub value_logs: Vec<GenerationStats>,
+    pub avg_key_bytes: f64,
+    pub avg_value_bytes: f64,
+    pub index_mode: IndexMode,
+    ///内存索引大小的估算值
+    pub index_bytes: u64,
+    ///没有启用值缓存时为 None
+    pub cache: Option<CacheStats>,
+    ///每个列族的键数和陈旧记录，包括默认列族
+    pub families: Vec<FamilyStats>,
+    pub last_compaction: Option<CompactionInfo>,
+    pub ops: OpCounters,
+}
+
+impl fmt::Display for StoreStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "live keys:        {}", self.live_keys)?;
+        writeln!(f, "total bytes:      {}", self.total_bytes)?;
+        writeln!(f, "stale bytes:      {}", self.stale_bytes)?;
+        writeln!(f, "avg key bytes:    {:.1}", self.avg_key_bytes)?;
+        writeln!(f, "avg value bytes:  {:.1}", self.avg_value_bytes)?;
+        let mode = match self.index_mode {
+            IndexMode::Full => "full keys",
+            IndexMode::Hashed => "key fingerprints",
+        };
+        writeln!(f, "index:            {}, ~{} bytes", mode, self.index_bytes)?;
+        match self.cache {
+            Some(cache) => writeln!(
+                f,
+                "value cache:      {} hits, {} misses, {} entries, ~{} of {} bytes",
+                cache.hits, cache.misses, cache.entries, cache.bytes, cache.capacity_bytes
+            )?,
+            None => writeln!(f, "value cache:      disabled")?,
+        }
+        writeln!(f, "families:         {}", self.families.len())?;
+        for family in &self.families {
+            writeln!(f, "  {}  {} keys, {} stale bytes", family.name, family.live_keys, family.stale_bytes)?;
+        }
+        writeln!(f, "generations:      {}", self.generations.len())?;
+        for generation in &self.generations {
+            writeln!(f, "  {}.log  {} bytes", generation.gen, generation.bytes)?;
+        }
+        writeln!(f, "value logs:       {}", self.value_logs.len())?;
+        for value_log in &self.value_logs {
+            writeln!(f, "  {}.vlog  {} bytes", value_log.gen, value_log.bytes)?;
+        }
+        match self.last_compaction {
+            Some(compaction) => writeln!(
+                f,
+                "last compaction:  at {} (unix), took {} ms, reclaimed {} bytes",
+                compaction.finished_at, compaction.duration_ms, compaction.reclaimed_bytes
+            )?,
+            None => writeln!(f, "last compaction:  never")?,
+        }
+        let ops = self.ops;
+        write!(
+            f,
+            "operations:       {} gets, {} sets, {} removes, {} merges, {} scans, {} compactions",
+            ops.gets, ops.sets, ops.removes, ops.merges, ops.scans, ops.compactions
+        )
+    }
+}
+
+///持久化到 `stats.json` 的部分，在 compaction 和关闭时写入
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub(crate) struct PersistedStats {
+    pub(crate) ops: OpCounters,
+    pub(crate) last_compaction: Option<CompactionInfo>,
+    ///文件中的计数，没有变化时不需要重写
+    #[serde(skip)]
+    saved: OpCounters,
+}
+
+impl PersistedStats {
+    ///文件不存在或损坏时从零开始计数，统计信息不影响数据
+    pub(crate) fn load(fs: &dyn FileSystem, dir: &Path) -> PersistedStats {
+        let mut stats: PersistedStats = fs
+            .read(&dir.join(STATS_FILE))
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default();
+        stats.saved = stats.ops;
+        stats
+    }
+
+    pub(crate) fn save(&mut self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", STATS_FILE));
+        let mut file = fs.create(&tmp)?;
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+        fs.rename(&tmp, &dir.join(STATS_FILE))?;
+        self.saved = self.ops;
+        Ok(())
+    }
+
+    ///计数变化之后才写入；compaction 信息只在计数变化时更新
+    pub(crate) fn save_if_changed(&mut self, fs: &dyn FileSystem, dir: &Path) -> Result<()> {
+        if self.ops == self.saved {
+            return Ok(());
+        }
+        self.save(fs, dir)
+    }
+}
@@ -0,0 +1,97 @@
+//! 类型化的读写接口
+//!
+//! 值用 serde_json 编码成字符串保存，读取时反序列化；值不是要求的类型时返回 `KvError::TypeMismatch`，
+//! 和日志本身的 `SerdeErr` 区分开。`TypedTree` 把一个键前缀绑定到一种值类型上，键都相对于这个前缀。
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::any::type_name;
+use std::marker::PhantomData;
+
+use crate::{KvError, KvStore, Result};
+
+///编码类型化的值
+pub(crate) fn encode<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    Ok(serde_json::to_string(value)?)
+}
+
+///把键 key 的值解码为 T
+pub(crate) fn decode<T: DeserializeOwned>(key: &str, value: &str) -> Result<T> {
+    serde_json::from_str(value).map_err(|e| KvError::TypeMismatch {
+        key: key.to_owned(),
+        expected: type_name::<T>(),
+        reason: e.to_string(),
+    })
+}
+
+///绑定到一个键前缀的类型化视图，通过 `KvStore::typed_tree` 创建
+///
+///```no_run
+///# use kvs::KvStore;
+///# use serde::{Deserialize, Serialize};
+///#[derive(Serialize, Deserialize)]
+///struct User {
+///    name: String,
+///}
+///
+///let mut store = KvStore::open("data")?;
+///let mut users = store.typed_tree::<User>("user:");
+///users.set("1", &User { name: "ann".to_owned() })?;
+///assert_eq!(users.get("1")?.map(|user| user.name), Some("ann".to_owned()));
+///# Ok::<(), kvs::KvError>(())
+///```
+#[derive(Debug)]
+pub struct TypedTree<'a, T> {
+    store: &'a mut KvStore,
+    family: String,
+    prefix: String,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Serialize + DeserializeOwned> TypedTree<'a, T> {
+    pub(crate) fn new(store: &'a mut KvStore, family: &str, prefix: &str) -> TypedTree<'a, T> {
+        TypedTree {
+            store,
+            family: family.to_owned(),
+            prefix: prefix.to_owned(),
+            value: PhantomData,
+        }
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn get(&mut self, key: &str) -> Result<Option<T>> {
+        self.store.get_typed_cf(&self.family, self.full_key(key))
+    }
+
+    pub fn set(&mut self, key: &str, value: &T) -> Result<()> {
+        self.store.set_typed_cf(&self.family, self.full_key(key), value)
+    }
+
+    pub fn remove(&mut self, key: &str) -> Result<()> {
+        self.store.remove_cf(&self.family, self.full_key(key))
+    }
+
+    ///按键的顺序返回前缀下的所有值，键不带前缀
+    pub fn scan(&mut self) -> Result<Vec<(String, T)>> {
+        self.store
+            .scan_cf(&self.family, &self.prefix)?
+            .into_iter()
+            .map(|(key, value)| {
+                let value = decode(&key, &value)?;
+                Ok((key[self.prefix.len()..].to_owned(), value))
+            })
+            .collect()
+    }
+
+    ///删除前缀下的所有键，返回删除的键数
+    pub fn clear(&mut self) -> Result<u64> {
+        self.store.remove_prefix_cf(&self.family, &self.prefix)
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
@@ -0,0 +1,426 @@
+//! KvStore 访问文件的接口，默认直接使用磁盘
+//!
+//! `MemoryFs` 把文件保存在内存中；`FaultyFs` 在 `MemoryFs` 的基础上注入故障：写入失败、
+//! 写入只完成一部分，以及模拟崩溃时丢弃没有 sync 的数据，用于测试崩溃后数据的一致性。
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+///打开用于读取的文件
+pub trait FsReader: Read + Seek + Send + Debug {}
+
+impl<T: Read + Seek + Send + Debug> FsReader for T {}
+
+///打开用于追加写入的文件
+pub trait FsWriter: Write + Seek + Send + Debug {
+    ///把已经写入的数据持久化，之后崩溃也不会丢失
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+///KvStore 使用的文件操作
+pub trait FileSystem: Send + Sync + Debug {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    ///目录中的所有文件（不包括子目录）
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FsReader>>;
+    ///以追加方式打开文件，文件不存在时创建
+    fn append(&self, path: &Path) -> io::Result<Box<dyn FsWriter>>;
+    ///创建文件，已存在时清空
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FsWriter>>;
+    fn len(&self, path: &Path) -> io::Result<u64>;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.open(path)?.read_to_end(&mut content)?;
+        Ok(content)
+    }
+}
+
+///直接读写磁盘
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiskFs;
+
+impl FsWriter for File {
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+impl FileSystem for DiskFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FsReader>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(OpenOptions::new().create(true).append(true).open(path)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(File::create(path)?))
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemFile {
+    data: Vec<u8>,
+    ///已经 sync 的长度，模拟崩溃时截断到这里
+    synced: usize,
+}
+
+#[derive(Debug, Default)]
+struct MemState {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, MemFile>,
+}
+
+///把文件保存在内存中，克隆出来的实例共享同一份数据
+///
+///目录操作、删除和重命名立即生效，不需要 sync
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    state: Arc<Mutex<MemState>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    ///丢弃所有文件中没有 sync 的数据，相当于机器断电后重启
+    pub fn crash(&self) {
+        for file in self.state.lock().unwrap().files.values_mut() {
+            file.data.truncate(file.synced);
+        }
+    }
+
+    fn writer(&self, path: &Path, truncate: bool, faults: Option<Arc<Mutex<Faults>>>) -> io::Result<MemWriter> {
+        let mut state = self.state.lock().unwrap();
+        check_parent(&state, path)?;
+        let file = state.files.entry(path.to_owned()).or_default();
+        if truncate {
+            file.data.clear();
+            file.synced = 0;
+        }
+        Ok(MemWriter {
+            fs: self.clone(),
+            path: path.to_owned(),
+            pos: file.data.len() as u64,
+            faults,
+        })
+    }
+
+    fn with_file<T>(&self, path: &Path, f: impl FnOnce(&mut MemFile) -> T) -> io::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let file = state.files.get_mut(path).ok_or_else(|| not_found(path))?;
+        Ok(f(file))
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path.display()))
+}
+
+fn check_parent(state: &MemState, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !state.dirs.contains(parent) => Err(not_found(parent)),
+        _ => Ok(()),
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        for dir in path.ancestors().filter(|dir| !dir.as_os_str().is_empty()) {
+            state.dirs.insert(dir.to_owned());
+        }
+        Ok(())
+    }
+
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let state = self.state.lock().unwrap();
+        if !state.dirs.contains(dir) {
+            return Err(not_found(dir));
+        }
+        Ok(state
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FsReader>> {
+        //读取器持有文件内容的副本，读到末尾时重新加载
+        let data = self.with_file(path, |file| file.data.clone())?;
+        Ok(Box::new(MemReader {
+            fs: self.clone(),
+            path: path.to_owned(),
+            inner: Cursor::new(data),
+        }))
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(self.writer(path, false, None)?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(self.writer(path, true, None)?))
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        self.with_file(path, |file| file.data.len() as u64)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        check_parent(&state, to)?;
+        let file = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_owned(), file);
+        Ok(())
+    }
+}
+
+///MemoryFs 的读取器；读到快照末尾时重新加载文件，这样可以读到打开之后追加的数据
+#[derive(Debug)]
+struct MemReader {
+    fs: MemoryFs,
+    path: PathBuf,
+    inner: Cursor<Vec<u8>>,
+}
+
+impl MemReader {
+    fn reload(&mut self) -> io::Result<()> {
+        let pos = self.inner.position();
+        let data = self.fs.with_file(&self.path, |file| file.data.clone())?;
+        self.inner = Cursor::new(data);
+        self.inner.set_position(pos);
+        Ok(())
+    }
+}
+
+impl Read for MemReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.inner.position() >= self.inner.get_ref().len() as u64 {
+            self.reload()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for MemReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if let SeekFrom::End(_) = pos {
+            self.reload()?;
+        }
+        self.inner.seek(pos)
+    }
+}
+
+///MemoryFs 的写入器，总是追加到文件末尾
+#[derive(Debug)]
+struct MemWriter {
+    fs: MemoryFs,
+    path: PathBuf,
+    pos: u64,
+    faults: Option<Arc<Mutex<Faults>>>,
+}
+
+impl Write for MemWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let (len, result) = match &self.faults {
+            Some(faults) => faults.lock().unwrap().on_write(buf.len()),
+            None => (buf.len(), Ok(())),
+        };
+        self.pos = self.fs.with_file(&self.path, |file| {
+            file.data.extend_from_slice(&buf[..len]);
+            file.data.len() as u64
+        })?;
+        result.map(|_| len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemWriter {
+    ///写入总是追加，只支持查询当前位置
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match pos {
+            SeekFrom::Current(0) => Ok(self.pos),
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, "append-only file")),
+        }
+    }
+}
+
+impl FsWriter for MemWriter {
+    fn sync(&mut self) -> io::Result<()> {
+        if let Some(faults) = &self.faults {
+            faults.lock().unwrap().on_sync()?;
+        }
+        self.fs.with_file(&self.path, |file| file.synced = file.data.len())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Faults {
+    ///还能成功的写入次数，None 表示不限
+    writes_left: Option<u64>,
+    ///失败的那次写入仍然写入的字节数
+    torn_len: usize,
+    ///已经注入过故障，之后的写入和 sync 都失败
+    failed: bool,
+}
+
+impl Faults {
+    ///返回这次写入实际写入的字节数和结果
+    fn on_write(&mut self, len: usize) -> (usize, io::Result<()>) {
+        if self.failed {
+            return (0, Err(injected()));
+        }
+        match &mut self.writes_left {
+            Some(0) => {
+                self.failed = true;
+                (self.torn_len.min(len), Err(injected()))
+            }
+            Some(left) => {
+                *left -= 1;
+                (len, Ok(()))
+            }
+            None => (len, Ok(())),
+        }
+    }
+
+    fn on_sync(&mut self) -> io::Result<()> {
+        if self.failed {
+            Err(injected())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn injected() -> io::Error {
+    io::Error::other("injected fault")
+}
+
+///在 MemoryFs 的基础上注入故障，克隆出来的实例共享数据和故障设置
+///
+///```
+///# use kvs::{FaultyFs, KvStore, StoreOptions};
+///let fs = FaultyFs::new();
+///let mut store = KvStore::open_with("data", StoreOptions::new().file_system(fs.clone()))?;
+///fs.fail_writes_after(0);
+///assert!(store.set("key".to_owned(), "value".to_owned()).is_err());
+///# fs.heal();
+///# Ok::<(), kvs::KvError>(())
+///```
+#[derive(Debug, Clone, Default)]
+pub struct FaultyFs {
+    inner: MemoryFs,
+    faults: Arc<Mutex<Faults>>,
+}
+
+impl FaultyFs {
+    pub fn new() -> FaultyFs {
+        FaultyFs::default()
+    }
+
+    ///再成功 n 次写入之后，所有写入和 sync 都失败
+    pub fn fail_writes_after(&self, n: u64) {
+        self.tear_write_after(n, 0);
+    }
+
+    ///再成功 n 次写入之后，下一次写入只写入前 len 字节就失败，之后的写入和 sync 都失败
+    pub fn tear_write_after(&self, n: u64, len: usize) {
+        let mut faults = self.faults.lock().unwrap();
+        *faults = Faults {
+            writes_left: Some(n),
+            torn_len: len,
+            failed: false,
+        };
+    }
+
+    ///清除故障设置，已经写入的数据保持不变，相当于进程崩溃后重启
+    pub fn heal(&self) {
+        *self.faults.lock().unwrap() = Faults::default();
+    }
+
+    ///丢弃没有 sync 的数据并清除故障设置，相当于机器断电后重启
+    pub fn crash(&self) {
+        self.inner.crash();
+        self.heal();
+    }
+}
+
+impl FileSystem for FaultyFs {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(path)
+    }
+
+    fn list_files(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        self.inner.list_files(dir)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn FsReader>> {
+        self.inner.open(path)
+    }
+
+    fn append(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(self.inner.writer(path, false, Some(Arc::clone(&self.faults)))?))
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn FsWriter>> {
+        Ok(Box::new(self.inner.writer(path, true, Some(Arc::clone(&self.faults)))?))
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        self.inner.len(path)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(from, to)
+    }
+}
@@ -0,0 +1,331 @@
+//! 大值的值日志（WiscKey）
+//!
+//! 不小于 `StoreOptions::value_log_threshold` 的值写在单独的 `<id>.vlog` 文件中，主日志中的
+//! `SetBlob` 记录只保存值在值日志中的位置，compaction 复制的是这个位置而不是值本身。
+//! 值日志文件和主日志一样以日志头部开始；加密时值按 64 KiB 分块，每块单独加密，读取时逐块解密。
+//! 值日志有自己的垃圾回收：`KvStore::gc_value_log` 把垃圾超过一半的文件中还在使用的值复制到新的
+//! 值日志文件，通过一次 compaction 更新主日志中的位置，然后删除这些文件。
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    read_frame, BufWriterWithPos, Commend, EncryptionKey, FileSystem, Format, FsReader, KvError, LogHeader,
+    LogWriter, Result, StoreOptions,
+};
+
+///默认的大值阈值
+pub(crate) const DEFAULT_THRESHOLD: u64 = 1024 * 1024;
+// 当前的值日志超过这个大小后，下一个值写到新的文件中
+const FILE_LIMIT: u64 = 64 * 1024 * 1024;
+// 加密时每块明文的长度
+const CHUNK_LEN: usize = 64 * 1024;
+
+///值在值日志中的位置
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlobPos {
+    ///值日志文件的编号
+    pub file: u64,
+    pub pos: u64,
+    ///在文件中占用的字节数
+    pub len: u64,
+    ///值的字节数
+    pub size: u64,
+}
+
+///`KvStore::gc_value_log` 的结果
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    ///删除的值日志文件数
+    pub removed_files: u64,
+    ///复制到新文件的仍在使用的值的字节数
+    pub relocated_bytes: u64,
+    ///回收的磁盘空间
+    pub reclaimed_bytes: u64,
+}
+
+impl fmt::Display for GcReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "removed {} value log files, relocated {} bytes, reclaimed {} bytes",
+            self.removed_files, self.relocated_bytes, self.reclaimed_bytes
+        )
+    }
+}
+
+///正在写入的值日志
+#[derive(Debug)]
+pub(crate) struct ValueLog {
+    ///当前写入的文件编号和写入器，第一次写入大值时才创建
+    active: Option<(u64, LogWriter)>,
+    next_id: u64,
+}
+
+impl ValueLog {
+    pub(crate) fn open(fs: &dyn FileSystem, dir: &Path) -> Result<ValueLog> {
+        Ok(ValueLog {
+            active: None,
+            next_id: sorted_files(fs, dir)?.last().map_or(1, |id| id + 1),
+        })
+    }
+
+    ///把 value 中的所有数据写成一个值
+    pub(crate) fn write(&mut self, dir: &Path, options: &StoreOptions, value: &mut dyn Read) -> Result<BlobPos> {
+        if self.active.as_ref().is_some_and(|(_, writer)| writer.pos >= FILE_LIMIT) {
+            self.close()?;
+        }
+        let (file, writer) = match &mut self.active {
+            Some(active) => active,
+            None => {
+                let id = self.next_id;
+                let mut writer = BufWriterWithPos::new(options.fs().append(&vlog_path(dir, id))?)?;
+                //值日志中是原始的值，不使用记录格式
+                LogHeader {
+                    key_id: options.encryption_key.as_ref().map(EncryptionKey::id),
+                    format: Format::Json,
+                }
+                .write(&mut writer)?;
+                self.next_id += 1;
+                self.active.insert((id, writer))
+            }
+        };
+        let pos = writer.pos;
+        let mut size = 0;
+        let mut chunk = vec![0; CHUNK_LEN];
+        loop {
+            let n = read_full(value, &mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            size += n as u64;
+            match &options.encryption_key {
+                None => writer.write_all(&chunk[..n])?,
+                Some(key) => {
+                    let sealed = key.seal(&chunk[..n], &key.bind(b"vlog", *file, writer.pos))?;
+                    writer.write_all(&(sealed.len() as u32).to_le_bytes())?;
+                    writer.write_all(&sealed)?;
+                }
+            }
+            if n < CHUNK_LEN {
+                break;
+            }
+        }
+        writer.flush()?;
+        Ok(BlobPos {
+            file: *file,
+            pos,
+            len: writer.pos - pos,
+            size,
+        })
+    }
+
+    ///把一个值复制到当前的值日志，返回新的位置
+    pub(crate) fn copy(&mut self, dir: &Path, options: &StoreOptions, blob: BlobPos) -> Result<BlobPos> {
+        let mut reader = open_blob(dir, options, &blob)?;
+        self.write(dir, options, &mut reader)
+    }
+
+    ///把当前的值日志落盘
+    pub(crate) fn sync(&mut self) -> Result<()> {
+        if let Some((_, writer)) = &mut self.active {
+            writer.sync()?;
+        }
+        Ok(())
+    }
+
+    ///结束当前的值日志，之后的值写到新的文件中
+    pub(crate) fn close(&mut self) -> Result<()> {
+        self.sync()?;
+        self.active = None;
+        Ok(())
+    }
+}
+
+///以流的方式读取的值；值日志被截断、读不满值的长度时返回 `UnexpectedEof`
+#[derive(Debug)]
+pub struct ValueReader {
+    size: u64,
+    ///还没有读出的字节数
+    remaining: u64,
+    source: Source,
+}
+
+#[derive(Debug)]
+enum Source {
+    Memory(Cursor<Vec<u8>>),
+    Plain(Take<Box<dyn FsReader>>),
+    Sealed {
+        file: Take<Box<dyn FsReader>>,
+        key: EncryptionKey,
+        chunk: Cursor<Vec<u8>>,
+        ///值日志文件编号和下一块的偏移，每块的认证绑定了它的位置
+        position: (u64, u64),
+    },
+}
+
+impl ValueReader {
+    ///值的字节数
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl From<String> for ValueReader {
+    fn from(value: String) -> ValueReader {
+        ValueReader {
+            size: value.len() as u64,
+            remaining: value.len() as u64,
+            source: Source::Memory(Cursor::new(value.into_bytes())),
+        }
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.remaining == 0 {
+            return Ok(0);
+        }
+        let len = buf.len().min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.source.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "value log is truncated"));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Source::Memory(cursor) => cursor.read(buf),
+            Source::Plain(file) => file.read(buf),
+            Source::Sealed {
+                file,
+                key,
+                chunk,
+                position,
+            } => {
+                if chunk.position() == chunk.get_ref().len() as u64 {
+                    //值还没有读完，读不到下一块就是值日志被截断了
+                    let mut len = [0u8; 4];
+                    file.read_exact(&mut len)?;
+                    let sealed = read_frame(file, u32::from_le_bytes(len))?;
+                    let (id, offset) = position;
+                    let plain = key
+                        .open(&sealed, &key.bind(b"vlog", *id, *offset))
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                    *offset += 4 + sealed.len() as u64;
+                    *chunk = Cursor::new(plain);
+                }
+                chunk.read(buf)
+            }
+        }
+    }
+}
+
+///打开值日志中的一个值
+pub(crate) fn open_blob(dir: &Path, options: &StoreOptions, blob: &BlobPos) -> Result<ValueReader> {
+    let mut file = options.fs().open(&vlog_path(dir, blob.file))?;
+    let header = LogHeader::read(blob.file, &mut file)?;
+    file.seek(SeekFrom::Start(blob.pos))?;
+    let file = file.take(blob.len);
+    let source = match header.key_id {
+        None => Source::Plain(file),
+        Some(key_id) => Source::Sealed {
+            file,
+            key: options.key(key_id).ok_or(KvError::WrongKey { gen: blob.file, key_id })?.clone(),
+            chunk: Cursor::new(Vec::new()),
+            position: (blob.file, blob.pos),
+        },
+    };
+    Ok(ValueReader {
+        size: blob.size,
+        remaining: blob.size,
+        source,
+    })
+}
+
+///读出值日志中的一个值，值日志被截断时返回 `KvError::Corruption`
+pub(crate) fn read_blob(dir: &Path, options: &StoreOptions, blob: &BlobPos) -> Result<Vec<u8>> {
+    //记录中的长度可能已经损坏，不按它预先分配内存
+    let mut value = Vec::new();
+    match open_blob(dir, options, blob)?.read_to_end(&mut value) {
+        Ok(_) => Ok(value),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(KvError::Corruption(format!(
+            "{}.vlog is truncated, the value at {} is shorter than {} bytes",
+            blob.file, blob.pos, blob.size
+        ))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+///读出键 key 在值日志中的值，不是 UTF-8 时返回 `KvError::BinaryValue`
+pub(crate) fn read_value(dir: &Path, options: &StoreOptions, key: &str, blob: &BlobPos) -> Result<String> {
+    String::from_utf8(read_blob(dir, options, blob)?).map_err(|_| KvError::BinaryValue(key.to_owned()))
+}
+
+///把 `SetBlob` 记录换成带有值的 `Set` 记录，其它记录不变
+pub(crate) fn inline(dir: &Path, options: &StoreOptions, cmd: Commend) -> Result<Commend> {
+    match cmd {
+        Commend::SetBlob {
+            key,
+            blob,
+            cf,
+            expires,
+            seq,
+            ts,
+        } => Ok(Commend::Set {
+            value: read_value(dir, options, &key, &blob)?,
+            key,
+            cf,
+            expires,
+            seq,
+            ts,
+        }),
+        cmd => Ok(cmd),
+    }
+}
+
+///值日志文件的编号和大小
+pub(crate) fn file_sizes(fs: &dyn FileSystem, dir: &Path) -> Result<HashMap<u64, u64>> {
+    sorted_files(fs, dir)?
+        .into_iter()
+        .map(|id| Ok((id, fs.len(&vlog_path(dir, id))?)))
+        .collect()
+}
+
+///目录中所有值日志文件的编号，从小到大
+pub(crate) fn sorted_files(fs: &dyn FileSystem, dir: &Path) -> Result<Vec<u64>> {
+    let mut files: Vec<u64> = fs
+        .list_files(dir)?
+        .into_iter()
+        .filter(|path| path.extension() == Some("vlog".as_ref()))
+        .filter_map(|path| path.file_stem().and_then(OsStr::to_str).and_then(|stem| stem.parse().ok()))
+        .collect();
+    files.sort_unstable();
+    Ok(files)
+}
+
+pub(crate) fn vlog_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{}.vlog", id))
+}
+
+///尽量读满 buf，返回读到的字节数，小于 buf 的长度说明已经读到末尾
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
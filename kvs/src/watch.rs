@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+
+use crate::keydir::prefix_end;
+use crate::vlog;
+use crate::{
+    family_of, is_incomplete_record, log_path, sorted_gen_list, BufReaderWithPos, CommandStream, Commend, KvError,
+    FamilyOptions, LogHeader, Result, StoreOptions,
+};
+
+///日志中的位置：日志文件代号 + 文件内偏移
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LogPosition {
+    pub gen: u64,
+    pub offset: u64,
+}
+
+// 格式为 `gen:offset`，CLI 用它来恢复订阅
+impl fmt::Display for LogPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.gen, self.offset)
+    }
+}
+
+impl FromStr for LogPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (gen, offset) = s.split_once(':').ok_or("expected <gen>:<offset>")?;
+        Ok(LogPosition {
+            gen: gen.parse().map_err(|_| "invalid generation")?,
+            offset: offset.parse().map_err(|_| "invalid offset")?,
+        })
+    }
+}
+
+///键的变更
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Change {
+    ///expires 为值过期的时间（UNIX 时间戳，毫秒），None 表示不过期
+    Set {
+        key: String,
+        value: String,
+        expires: Option<u64>,
+    },
+    Remove { key: String },
+    ///合并操作数，消费者需要注册同样的合并运算符才能得到合并后的值
+    Merge { key: String, operator: String, operand: String },
+    ///删除 [start, end) 中的所有键，end 为 None 时没有上界
+    RemoveRange { start: String, end: Option<String> },
+    ///值过期被删除：读取时第一次发现过期，或者 compaction 丢掉了过期的值
+    Expire { key: String },
+    ///列族被删除，其中所有的键都被删除
+    DropFamily,
+    ///用 options 创建了列族
+    CreateFamily { options: FamilyOptions },
+}
+
+impl Change {
+    ///变更的键，范围删除返回范围的起点，创建和删除列族返回空字符串
+    pub fn key(&self) -> &str {
+        match self {
+            Change::Set { key, .. } | Change::Remove { key } | Change::Merge { key, .. } | Change::Expire { key } => key,
+            Change::RemoveRange { start, .. } => start,
+            Change::DropFamily | Change::CreateFamily { .. } => "",
+        }
+    }
+
+    ///变更是否涉及以 prefix 开头的键
+    pub fn matches(&self, prefix: &str) -> bool {
+        match self {
+            Change::RemoveRange { start, end } => {
+                let below_end = end.as_deref().is_none_or(|end| end > prefix);
+                let above_start = prefix_end(prefix).is_none_or(|prefix_end| *start < prefix_end);
+                below_end && above_start
+            }
+            Change::DropFamily | Change::CreateFamily { .. } => true,
+            change => change.key().starts_with(prefix),
+        }
+    }
+}
+
+impl Change {
+    ///写入对应的变更和它所在的列族，其它记录返回 None
+    pub(crate) fn from_command(cmd: Commend) -> Option<(String, Change)> {
+        let (cf, change) = match cmd {
+            Commend::Set { key, value, cf, expires, .. } => (cf, Change::Set { key, value, expires }),
+            Commend::Remove { key, cf, .. } => (cf, Change::Remove { key }),
+            Commend::Merge { key, op, operand, cf, .. } => (
+                cf,
+                Change::Merge {
+                    key,
+                    operator: op,
+                    operand,
+                },
+            ),
+            Commend::RemoveRange { start, end, cf, .. } => (cf, Change::RemoveRange { start, end }),
+            Commend::Expire { key, cf, .. } => (cf, Change::Expire { key }),
+            Commend::DropFamily { cf } => (Some(cf), Change::DropFamily),
+            Commend::CreateFamily { cf, options } => (Some(cf), Change::CreateFamily { options }),
+            _ => return None,
+        };
+        Some((family_of(&cf).to_owned(), change))
+    }
+}
+
+///把一条记录转换成变更和它所在的列族；值日志中的值被读出，事件中的值是字符串，
+///二进制的值中无法解码的字节被替换成 U+FFFD
+pub(crate) fn change_of(dir: &Path, options: &StoreOptions, cmd: Commend) -> Result<Option<(String, Change)>> {
+    match cmd {
+        Commend::SetBlob { key, blob, cf, expires, .. } => {
+            let value = String::from_utf8_lossy(&vlog::read_blob(dir, options, &blob)?).into_owned();
+            Ok(Some((family_of(&cf).to_owned(), Change::Set { key, value, expires })))
+        }
+        cmd => Ok(Change::from_command(cmd)),
+    }
+}
+
+///变更事件
+///
+///`position` 是这条记录之后的位置，消费者保存最后处理的事件的 `position`，
+///重启后传给 `KvStore::watch_from` 即可从下一条记录继续
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchEvent {
+    pub position: LogPosition,
+    ///变更所在的列族
+    pub family: String,
+    pub change: Change,
+}
+
+impl WatchEvent {
+    ///事件是否属于 family（None 表示所有列族）中以 prefix 开头的键
+    fn matches(&self, family: Option<&str>, prefix: &str) -> bool {
+        family.is_none_or(|family| family == self.family) && self.change.matches(prefix)
+    }
+}
+
+///订阅了某个列族（None 表示所有列族）中某个前缀的接收端
+#[derive(Debug)]
+pub(crate) struct Watcher {
+    pub(crate) family: Option<String>,
+    pub(crate) prefix: String,
+    pub(crate) sender: WatchSender,
+}
+
+///逐条接收事件，或者按写入分组接收（原子批次的事件在同一组中）
+#[derive(Debug)]
+pub(crate) enum WatchSender {
+    Event(Sender<WatchEvent>),
+    Group(Sender<Vec<WatchEvent>>),
+}
+
+impl Watcher {
+    ///发送一次写入产生的事件，接收端已经关闭时返回 false
+    pub(crate) fn notify(&self, events: &[WatchEvent]) -> bool {
+        let mut events = events
+            .iter()
+            .filter(|event| event.matches(self.family.as_deref(), &self.prefix))
+            .cloned();
+        match &self.sender {
+            WatchSender::Event(sender) => events.all(|event| sender.send(event).is_ok()),
+            WatchSender::Group(sender) => {
+                let group: Vec<WatchEvent> = events.collect();
+                group.is_empty() || sender.send(group).is_ok()
+            }
+        }
+    }
+}
+
+///直接读取目录中的日志文件，返回 `from` 之后列族 family（None 表示所有列族）中所有以 prefix 开头的键的变更
+///
+///`from` 为 None 时从最早的日志开始。`from` 所在的日志已经被 compaction 删除时
+///返回 `KvError::PositionCompacted`，此时消费者需要通过 `scan` 重新同步。
+///和打开时加载日志一样，原子批次的记录全部读到之后才返回，日志末尾不完整的批次被丢弃
+pub(crate) fn read_changes(
+    path: &Path,
+    options: &StoreOptions,
+    family: Option<&str>,
+    prefix: &str,
+    from: Option<LogPosition>,
+) -> Result<Vec<WatchEvent>> {
+    let groups = read_change_groups(path, options, family, prefix, from)?;
+    Ok(groups.into_iter().flatten().collect())
+}
+
+///和 `read_changes` 相同，但按写入分组返回，一个原子批次的事件在同一组中
+pub(crate) fn read_change_groups(
+    path: &Path,
+    options: &StoreOptions,
+    family: Option<&str>,
+    prefix: &str,
+    from: Option<LogPosition>,
+) -> Result<Vec<Vec<WatchEvent>>> {
+    let gen_list = sorted_gen_list(options.fs(), path)?;
+    let from = match (from, gen_list.first()) {
+        (Some(from), Some(&oldest)) if from.gen < oldest => {
+            return Err(KvError::PositionCompacted { gen: from.gen })
+        }
+        (Some(from), _) => from,
+        (None, _) => LogPosition { gen: 0, offset: 0 },
+    };
+
+    let mut groups = Vec::new();
+    for gen in gen_list.into_iter().filter(|&gen| gen >= from.gen) {
+        let mut file = options.fs().open(&log_path(path, gen))?;
+        let header = LogHeader::read(gen, &mut file)?;
+        let codec = header.codec(gen, options)?;
+        let mut reader = BufReaderWithPos::new(file)?;
+        let start = if gen == from.gen { from.offset } else { 0 };
+        reader.seek(SeekFrom::Start(start.max(header.len())))?;
+        let mut stream = CommandStream::new(&mut reader, codec);
+        //正在读取的批次：还差几条记录，已经读到的事件
+        let mut batch: Option<(usize, Vec<WatchEvent>)> = None;
+        while let Some(cmd) = stream.next() {
+            let cmd = match cmd {
+                Ok(cmd) => cmd,
+                // 其它进程正在追加的记录还没有写完整，或者追加时崩溃；它所在的批次也一起丢弃
+                Err(ref e) if is_incomplete_record(e) => break,
+                Err(e) => return Err(e),
+            };
+            if let Commend::Batch { len } = cmd {
+                batch = Some((len, Vec::with_capacity(len)));
+                continue;
+            }
+            let event = change_of(path, options, cmd)?
+                .map(|(cf, change)| WatchEvent {
+                    position: LogPosition {
+                        gen,
+                        offset: stream.pos(),
+                    },
+                    family: cf,
+                    change,
+                })
+                .filter(|event| event.matches(family, prefix));
+            match &mut batch {
+                Some((remaining, pending)) => {
+                    pending.extend(event);
+                    *remaining -= 1;
+                    if *remaining == 0 {
+                        groups.push(batch.take().unwrap().1);
+                    }
+                }
+                None => groups.extend(event.map(|event| vec![event])),
+            }
+        }
+    }
+    groups.retain(|group| !group.is_empty());
+    Ok(groups)
+}
@@ -0,0 +1,153 @@
+use assert_cmd::prelude::*;
+use kvs::{AsyncKvStore, AsyncKvsServer, KvError, KvsClient, KvsEngine, MemoryStore, Result};
+use std::net::TcpStream;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+#[tokio::test]
+async fn async_get_set_remove() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = AsyncKvStore::open(temp_dir.path()).await?;
+    store.set("key1".to_owned(), "value1".to_owned()).await?;
+    store.set("key2".to_owned(), "value2".to_owned()).await?;
+    assert_eq!(store.get("key1".to_owned()).await?, Some("value1".to_owned()));
+    store.remove("key1".to_owned()).await?;
+    assert_eq!(store.get("key1".to_owned()).await?, None);
+    assert!(matches!(store.remove("key1".to_owned()).await, Err(KvError::KeyNotFound)));
+    assert_eq!(
+        store.scan("key".to_owned()).await?,
+        vec![("key2".to_owned(), "value2".to_owned())]
+    );
+    drop(store);
+
+    let store = AsyncKvStore::open(temp_dir.path()).await?;
+    assert_eq!(store.get("key2".to_owned()).await?, Some("value2".to_owned()));
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn async_concurrent_tasks() -> Result<()> {
+    let store = AsyncKvStore::new(MemoryStore::new()).threads(4);
+    let tasks: Vec<_> = (0..100)
+        .map(|i| {
+            let store = store.clone();
+            tokio::spawn(async move { store.set(format!("key{:03}", i), format!("value{}", i)).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap()?;
+    }
+    assert_eq!(store.scan("key".to_owned()).await?.len(), 100);
+    Ok(())
+}
+
+// 一个操作 panic 只让它自己失败，之后的操作照常执行
+#[tokio::test]
+async fn panicking_operation_fails_alone() -> Result<()> {
+    let store = AsyncKvStore::new(MemoryStore::new());
+    store.set("key1".to_owned(), "value1".to_owned()).await?;
+    let result: Result<()> = store.run(|_| panic!("operation failed")).await;
+    assert!(matches!(result, Err(KvError::TaskFailed)));
+    let result: Result<()> = store.run(|_| panic!("operation failed again")).await;
+    assert!(matches!(result, Err(KvError::TaskFailed)));
+
+    let get = tokio::time::timeout(Duration::from_secs(5), store.get("key1".to_owned()));
+    assert_eq!(get.await.expect("operation after a panic hung")?, Some("value1".to_owned()));
+    store.set("key2".to_owned(), "value2".to_owned()).await?;
+    assert_eq!(store.scan("key".to_owned()).await?.len(), 2);
+    Ok(())
+}
+
+// 调用方在开始之前就放弃的操作不会执行
+#[tokio::test]
+async fn cancelled_operation_is_skipped() -> Result<()> {
+    let store = AsyncKvStore::new(MemoryStore::new());
+    let busy = tokio::spawn({
+        let store = store.clone();
+        async move {
+            store
+                .run(|_| {
+                    thread::sleep(Duration::from_millis(300));
+                    Ok(())
+                })
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let cancelled = tokio::time::timeout(
+        Duration::from_millis(50),
+        store.set("key".to_owned(), "value".to_owned()),
+    )
+    .await;
+    assert!(cancelled.is_err());
+    busy.await.unwrap()?;
+    assert_eq!(store.get("key".to_owned()).await?, None);
+    Ok(())
+}
+
+#[test]
+fn async_server_many_connections() -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_io()
+        .build()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))?;
+    let addr = listener.local_addr()?;
+    let server = AsyncKvsServer::new(AsyncKvStore::new(MemoryStore::new()));
+    runtime.spawn(server.serve(listener));
+
+    // 所有客户端同时保持连接
+    let mut clients = (0..50).map(|_| KvsClient::connect(addr)).collect::<Result<Vec<_>>>()?;
+    for (i, client) in clients.iter_mut().enumerate() {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    for (i, client) in clients.iter_mut().enumerate().rev() {
+        assert_eq!(client.get(format!("key{}", (i + 1) % 50))?, Some(format!("value{}", (i + 1) % 50)));
+    }
+    assert!(matches!(clients[0].remove("missing".to_owned()), Err(KvError::KeyNotFound)));
+    assert_eq!(clients[1].scan("key1")?.len(), 11);
+    Ok(())
+}
+
+// 测试结束时结束服务进程
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn cli_async_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let _server = Server(
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", &addr.to_string(), "--async", "--threads", "1"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap(),
+    );
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "server did not start");
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mut client = KvsClient::connect(addr)?;
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:0", "--async", "--protocol", "http"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+    Ok(())
+}
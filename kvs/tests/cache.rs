@@ -0,0 +1,75 @@
+use kvs::{KvStore, MemoryFs, Result, StoreOptions};
+use tempfile::TempDir;
+
+#[test]
+fn cached_gets_hit_and_track_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().value_cache(1 << 20))?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    let cache = store.stats()?.cache.unwrap();
+    assert_eq!((cache.hits, cache.misses, cache.entries), (1, 1, 1));
+
+    // 写入使缓存的值失效
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    let cache = store.stats()?.cache.unwrap();
+    assert_eq!((cache.hits, cache.misses, cache.entries, cache.bytes), (1, 3, 0, 0));
+    Ok(())
+}
+
+#[test]
+fn cache_evicts_least_recently_used() -> Result<()> {
+    let fs = MemoryFs::new();
+    let value = "v".repeat(1000);
+    let mut store = KvStore::open_with("data", StoreOptions::new().file_system(fs).value_cache(3000))?;
+    for key in ["a", "b", "c"] {
+        store.set(key.to_owned(), value.clone())?;
+        store.get(key.to_owned())?;
+    }
+    // 只放得下两个值，"a" 被淘汰
+    let cache = store.stats()?.cache.unwrap();
+    assert_eq!((cache.entries, cache.misses), (2, 3));
+    assert!(cache.bytes <= cache.capacity_bytes);
+    // 读取 "b" 之后最久没用的是 "c"，写入 "a" 时淘汰它
+    assert_eq!(store.get("b".to_owned())?, Some(value.clone()));
+    store.get("a".to_owned())?;
+    store.get("b".to_owned())?;
+    store.get("c".to_owned())?;
+    let cache = store.stats()?.cache.unwrap();
+    assert_eq!((cache.hits, cache.misses), (2, 5));
+
+    // 比整个缓存还大的值不缓存
+    store.set("big".to_owned(), "v".repeat(4000))?;
+    store.get("big".to_owned())?;
+    assert_eq!(store.stats()?.cache.unwrap().entries, 2);
+    Ok(())
+}
+
+#[test]
+fn cache_survives_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().value_cache(1 << 20))?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+        store.set(format!("key{}", i), format!("new{}", i))?;
+        store.get(format!("key{}", i))?;
+    }
+    store.compaction()?;
+    for i in 0..100 {
+        assert_eq!(store.get(format!("key{}", i))?, Some(format!("new{}", i)));
+    }
+    store.set("key7".to_owned(), "after".to_owned())?;
+    assert_eq!(store.get("key7".to_owned())?, Some("after".to_owned()));
+    drop(store);
+
+    // 没有设置缓存大小时统计信息显示缓存未启用
+    let mut store = KvStore::open(temp_dir.path())?;
+    let stats = store.stats()?;
+    assert!(stats.cache.is_none());
+    assert!(stats.to_string().contains("value cache:      disabled"));
+    Ok(())
+}
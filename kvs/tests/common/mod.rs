@@ -0,0 +1,41 @@
+// 多个集成测试共用的辅助函数，不是每个测试文件都用到全部
+#![allow(dead_code)]
+
+use assert_cmd::prelude::*;
+use std::net::{SocketAddr, TcpListener};
+use std::process::{Child, Command, Output};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// 复制、选举都是异步的，轮询直到 check 成立
+pub fn wait_for(mut check: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !check() {
+        assert!(Instant::now() < deadline, "nodes did not converge in time");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn free_addr() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+}
+
+// 测试结束时结束服务进程
+pub struct Server(pub Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// 用 kvs-client 向 addr 上的服务发送一条命令
+pub fn client(addr: SocketAddr, args: &[&str]) -> Output {
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(args)
+        .args(["--addr", &addr.to_string()])
+        .output()
+        .unwrap()
+}
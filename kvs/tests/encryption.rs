@@ -0,0 +1,137 @@
+use assert_cmd::prelude::*;
+use kvs::{EncryptionKey, KvError, KvStore, Result, StoreOptions};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::process::Command;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+// 加密的存储能读回写入的值，磁盘上没有明文
+#[test]
+fn encrypted_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let key = EncryptionKey::new([7; 32]);
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(key.clone()))?;
+    store.set("key1".to_owned(), "secret-value".to_owned())?;
+    drop(store);
+
+    for entry in WalkDir::new(temp_dir.path()).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let content = std::fs::read(entry.path())?;
+            assert!(!String::from_utf8_lossy(&content).contains("secret-value"));
+        }
+    }
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(key))?;
+    assert_eq!(store.get("key1".to_owned())?, Some("secret-value".to_owned()));
+    Ok(())
+}
+
+// 用错误的密钥或不提供密钥打开加密的存储时明确地失败
+#[test]
+fn encrypted_store_wrong_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let key = EncryptionKey::new([7; 32]);
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(key))?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let wrong = EncryptionKey::new([8; 32]);
+    let err = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(wrong)).unwrap_err();
+    assert!(matches!(err, KvError::WrongKey { .. }));
+    assert!(matches!(KvStore::open(temp_dir.path()).unwrap_err(), KvError::WrongKey { .. }));
+    Ok(())
+}
+
+// compaction 用当前的密钥重写日志，之后不再需要旧密钥
+#[test]
+fn encryption_key_rotation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let old_key = EncryptionKey::new([1; 32]);
+    let new_key = EncryptionKey::new([2; 32]);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("plain".to_owned(), "value0".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(old_key.clone()))?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+
+    let options = StoreOptions::new().encryption_key(new_key.clone()).previous_key(old_key);
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    store.compaction()?;
+    drop(store);
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().encryption_key(new_key))?;
+    assert_eq!(store.get("plain".to_owned())?, Some("value0".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    Ok(())
+}
+
+// `kvs --key-file` 读写加密的存储
+#[test]
+fn cli_key_file() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let key_file = temp_dir.path().join("store.key");
+    std::fs::write(&key_file, "ab".repeat(32)).unwrap();
+    let key_arg = key_file.to_str().unwrap();
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["set", "key1", "value1", "--key-file", key_arg])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "key1", "--key-file", key_arg])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// 加密记录绑定了所在的日志文件和偏移，复制到别的日志或别的位置时认证失败
+#[test]
+fn replayed_encrypted_record_is_rejected() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().encryption_key(EncryptionKey::new([7; 32]));
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    store.set("key1".to_owned(), "old".to_owned())?;
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    store.set("key1".to_owned(), "new".to_owned())?;
+    store.set("key2".to_owned(), "new".to_owned())?;
+    drop(store);
+    let first = std::fs::read(temp_dir.path().join("1.log"))?;
+    let second = std::fs::read(temp_dir.path().join("2.log"))?;
+    assert_eq!(first.len() * 2 - 17, second.len());
+
+    // 把第一个日志中的旧值放到第二个日志的同一个偏移，回滚 key1
+    let mut replayed = second.clone();
+    replayed[..first.len()].copy_from_slice(&first);
+    std::fs::write(temp_dir.path().join("2.log"), &replayed)?;
+    let err = KvStore::open_with(temp_dir.path(), options.clone()).unwrap_err();
+    assert!(matches!(err, KvError::DecryptionFailed));
+
+    // 交换同一个日志中的两条记录
+    let record = first.len() - 17;
+    let mut swapped = second.clone();
+    swapped[17..17 + record].copy_from_slice(&second[17 + record..]);
+    swapped[17 + record..].copy_from_slice(&second[17..17 + record]);
+    std::fs::write(temp_dir.path().join("2.log"), &swapped)?;
+    let err = KvStore::open_with(temp_dir.path(), options.clone()).unwrap_err();
+    assert!(matches!(err, KvError::DecryptionFailed));
+
+    std::fs::write(temp_dir.path().join("2.log"), &second)?;
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("new".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use assert_cmd::prelude::*;
+use kvs::{FamilyOptions, KvError, KvStore, Result, WriteBatch, DEFAULT_FAMILY};
+use predicates::str::contains;
+use std::fs::{self, OpenOptions};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+#[test]
+fn families_are_separate_key_spaces() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("users", FamilyOptions::new())?;
+    assert!(matches!(store.create_family("users", FamilyOptions::new()), Err(KvError::FamilyExists(_))));
+    assert!(matches!(
+        store.set_cf("missing", "key".to_owned(), "value".to_owned()),
+        Err(KvError::FamilyNotFound(_))
+    ));
+
+    store.set("key1".to_owned(), "default".to_owned())?;
+    store.set_cf("users", "key1".to_owned(), "user".to_owned())?;
+    store.set_cf("users", "key2".to_owned(), "user2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("default".to_owned()));
+    assert_eq!(store.get_cf("users", "key1".to_owned())?, Some("user".to_owned()));
+    assert_eq!(store.scan("key")?.len(), 1);
+    assert_eq!(store.scan_cf("users", "key")?.len(), 2);
+    store.remove_cf("users", "key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("default".to_owned()));
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    let names: Vec<String> = store.families().into_iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec![DEFAULT_FAMILY, "users"]);
+    assert_eq!(store.get_cf("users", "key1".to_owned())?, None);
+    assert_eq!(store.get_cf("users", "key2".to_owned())?, Some("user2".to_owned()));
+    store.compaction()?;
+    assert_eq!(store.get_cf("users", "key2".to_owned())?, Some("user2".to_owned()));
+    let stats = store.stats()?;
+    assert_eq!(stats.live_keys, 2);
+    assert_eq!(stats.families[1].name, "users");
+    assert_eq!(stats.families[1].live_keys, 1);
+    Ok(())
+}
+
+#[test]
+fn drop_family_removes_all_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("tmp", FamilyOptions::new())?;
+    for i in 0..10 {
+        store.set_cf("tmp", format!("key{}", i), "value".to_owned())?;
+    }
+    store.set("key0".to_owned(), "kept".to_owned())?;
+    assert!(matches!(store.drop_family(DEFAULT_FAMILY), Err(KvError::DropDefaultFamily)));
+    store.drop_family("tmp")?;
+    assert!(matches!(store.get_cf("tmp", "key0".to_owned()), Err(KvError::FamilyNotFound(_))));
+    drop(store);
+
+    // 重新打开之后删除的键仍然不在，同名的新列族中也没有
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.families().len(), 1);
+    store.create_family("tmp", FamilyOptions::new())?;
+    assert!(store.scan_cf("tmp", "")?.is_empty());
+    assert_eq!(store.get("key0".to_owned())?, Some("kept".to_owned()));
+    assert!(store.stats()?.stale_bytes > 0);
+    Ok(())
+}
+
+#[test]
+fn batch_spans_families() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("users", FamilyOptions::new())?;
+    store.set("old".to_owned(), "value".to_owned())?;
+    store.write(
+        WriteBatch::new()
+            .set("users", "u1", "alice")
+            .set(DEFAULT_FAMILY, "count", "1")
+            .remove(DEFAULT_FAMILY, "old")
+            .remove("users", "missing"),
+    )?;
+    assert_eq!(store.get_cf("users", "u1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(store.get("count".to_owned())?, Some("1".to_owned()));
+    assert_eq!(store.get("old".to_owned())?, None);
+    assert!(matches!(
+        store.write(WriteBatch::new().set("count", "a", "b")),
+        Err(KvError::FamilyNotFound(_))
+    ));
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_cf("users", "u1".to_owned())?, Some("alice".to_owned()));
+    assert_eq!(store.get("old".to_owned())?, None);
+    Ok(())
+}
+
+// 最后几条记录没有写入日志的批次完全不应用
+#[test]
+fn incomplete_batch_is_discarded() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("users", FamilyOptions::new())?;
+    store.set("before".to_owned(), "value".to_owned())?;
+    store.write(WriteBatch::new().set(DEFAULT_FAMILY, "a", "1").remove(DEFAULT_FAMILY, "before"))?;
+    let end = store.position();
+    drop(store);
+
+    // 在记录边界截掉批次的最后一条记录
+    let log = temp_dir.path().join(format!("{}.log", end.gen));
+    let contents = String::from_utf8(fs::read(&log)?).unwrap();
+    let last = contents.rfind(r#"{"Remove":{"key":"before""#).unwrap();
+    let file = OpenOptions::new().write(true).open(&log)?;
+    file.set_len(last as u64)?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("a".to_owned())?, None);
+    assert_eq!(store.get("before".to_owned())?, Some("value".to_owned()));
+    drop(store);
+
+    // 在记录中间截断的批次由 repair 丢弃
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.write(WriteBatch::new().set("users", "u1", "alice").set(DEFAULT_FAMILY, "b", "2"))?;
+    let end = store.position();
+    drop(store);
+    let log = temp_dir.path().join(format!("{}.log", end.gen));
+    OpenOptions::new().write(true).open(&log)?.set_len(end.offset - 3)?;
+    let report = KvStore::repair(temp_dir.path(), &Default::default())?;
+    assert_eq!(report.live_keys, 1);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_cf("users", "u1".to_owned())?, None);
+    assert_eq!(store.get("before".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn family_ttl_and_compaction_threshold() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("sessions", FamilyOptions::new().ttl(Duration::from_millis(200)))?;
+    store.create_family("small", FamilyOptions::new().compaction_threshold(200))?;
+    store.set_cf("sessions", "s1".to_owned(), "token".to_owned())?;
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, Some("token".to_owned()));
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    assert!(store.scan_cf("sessions", "")?.is_empty());
+    assert!(matches!(store.remove_cf("sessions", "s1".to_owned()), Err(KvError::KeyNotFound)));
+
+    // 反复覆盖 small 中的键很快超过阈值触发 compaction，过期的会话也被丢掉
+    for i in 0..20 {
+        store.set_cf("small", "key".to_owned(), format!("value{}", i))?;
+    }
+    let stats = store.stats()?;
+    assert!(stats.ops.compactions > 0);
+    assert_eq!(stats.families.iter().find(|family| family.name == "sessions").unwrap().live_keys, 0);
+    assert_eq!(store.get_cf("small", "key".to_owned())?, Some("value19".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn expired_keys_are_logged_once() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("sessions", FamilyOptions::new().ttl(Duration::from_millis(200)))?;
+    store.set_cf("sessions", "s1".to_owned(), "token".to_owned())?;
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    let log = temp_dir.path().join(format!("{}.log", store.position().gen));
+    drop(store);
+
+    // 第一次读到过期的值时写入过期记录，之后键已经不在索引中
+    let contents = String::from_utf8(fs::read(&log)?).unwrap();
+    assert_eq!(contents.matches(r#"{"Expire":{"key":"s1","cf":"sessions""#).count(), 1);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn cli_column_families() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let kvs = |args: &[&str]| {
+        let mut command = Command::cargo_bin("kvs").unwrap();
+        command.args(args).current_dir(&temp_dir);
+        command
+    };
+    kvs(&["cf", "create", "users", "--ttl", "3600"]).assert().success();
+    kvs(&["--cf", "users", "set", "key1", "alice"]).assert().success();
+    kvs(&["get", "key1"]).assert().success().stdout("Key not found\n");
+    kvs(&["get", "key1", "--cf", "users"]).assert().success().stdout("alice\n");
+    kvs(&["cf", "list"])
+        .assert()
+        .success()
+        .stdout("default 0 keys\nusers 1 keys, ttl 3600s\n");
+    kvs(&["watch", "", "--once", "--cf", "users"])
+        .assert()
+        .success()
+        .stdout(contains("set key1 alice"));
+    kvs(&["--cf", "nope", "get", "key1"])
+        .assert()
+        .failure()
+        .stderr(contains("Column family nope does not exist"));
+    kvs(&["stats", "--cf", "users"]).assert().failure();
+    kvs(&["cf", "drop", "default"])
+        .assert()
+        .failure()
+        .stderr(contains("cannot be dropped"));
+    kvs(&["cf", "drop", "users"]).assert().success();
+    kvs(&["--cf", "users", "get", "key1"]).assert().failure();
+}
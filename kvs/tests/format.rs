@@ -0,0 +1,137 @@
+use assert_cmd::prelude::*;
+use kvs::{EncryptionKey, FamilyOptions, Format, KvStore, Result, StoreOptions};
+use predicates::str::contains;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn log_files(dir: &Path) -> Vec<Vec<u8>> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension() == Some("log".as_ref()))
+        .map(|path| fs::read(path).unwrap())
+        .collect()
+}
+
+#[test]
+fn reads_generations_written_in_different_formats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("json".to_owned(), "1".to_owned())?;
+    store.set("shared".to_owned(), "json".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().record_format(Format::Bincode))?;
+    store.set("bincode".to_owned(), "2".to_owned())?;
+    store.create_family("sessions", FamilyOptions::new().ttl(Duration::from_secs(3600)))?;
+    store.set_cf("sessions", "expiring".to_owned(), "x".to_owned())?;
+    store.remove("json".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().record_format(Format::MessagePack))?;
+    store.set("msgpack".to_owned(), "3".to_owned())?;
+    store.incr("count".to_owned(), 4)?;
+    store.set("shared".to_owned(), "msgpack".to_owned())?;
+    drop(store);
+
+    // 没有指定格式时沿用最新日志的格式
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.incr("count".to_owned(), 1)?;
+    let expected = [
+        ("bincode", "2"),
+        ("count", "5"),
+        ("msgpack", "3"),
+        ("shared", "msgpack"),
+    ]
+    .map(|(key, value)| (key.to_owned(), value.to_owned()));
+    assert_eq!(store.scan("")?, expected);
+    assert_eq!(store.history("shared")?.len(), 2);
+    assert_eq!(store.get_cf("sessions", "expiring".to_owned())?, Some("x".to_owned()));
+    store.compaction()?;
+    drop(store);
+    // compaction 之后全部是 MessagePack
+    assert!(log_files(temp_dir.path()).iter().all(|log| !log.starts_with(b"{")));
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan("")?, expected);
+    assert_eq!(store.get_cf("sessions", "expiring".to_owned())?, Some("x".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn encrypted_bincode_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let key = EncryptionKey::new([3; 32]);
+    let options = StoreOptions::new().encryption_key(key).record_format(Format::Bincode);
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    for i in 0..10 {
+        store.set("key".to_owned(), format!("secret{}", i))?;
+    }
+    store.remove_range("a".."b")?;
+    let changes = KvStore::read_changes(temp_dir.path(), &options, "key", None)?;
+    assert_eq!(changes.len(), 10);
+    assert_eq!(store.history("key")?.len(), 10);
+    store.compaction()?;
+    drop(store);
+    assert!(log_files(temp_dir.path()).iter().all(|log| !log.windows(6).any(|bytes| bytes == b"secret")));
+
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    assert_eq!(store.get("key".to_owned())?, Some("secret9".to_owned()));
+    assert!(KvStore::verify(temp_dir.path(), &options)?.is_clean());
+    Ok(())
+}
+
+#[test]
+fn cli_migrate() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for args in [&["set", "a", "1"][..], &["set", "b", "2"], &["rm", "a"]] {
+        Command::cargo_bin("kvs").unwrap().args(args).current_dir(&temp_dir).assert().success();
+    }
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["migrate", "--format", "msgpack"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Migrated store to msgpack"));
+    assert!(log_files(temp_dir.path()).iter().all(|log| log.starts_with(b"KVSLOG")));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "b"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("2\n");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["migrate", "--format", "yaml"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("unknown format"));
+}
+
+// 日志末尾损坏的长度前缀当作不完整的记录，不会按它分配内存
+#[test]
+fn corrupt_length_prefix_is_incomplete() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().record_format(Format::Bincode);
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let log = temp_dir.path().join(format!("{}.log", store.position().gen));
+    drop(store);
+
+    let mut content = fs::read(&log)?;
+    content.extend_from_slice(&u32::MAX.to_le_bytes());
+    content.extend_from_slice(b"torn");
+    fs::write(&log, content)?;
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use assert_cmd::prelude::*;
+use kvs::{At, FamilyOptions, KvStore, Result, StoreOptions, WriteBatch};
+use predicates::prelude::*;
+use predicates::str::contains;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use tempfile::TempDir;
+
+#[test]
+fn versions_are_numbered_and_readable() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "v1".to_owned())?;
+    store.set("other".to_owned(), "x".to_owned())?;
+    store.set("key".to_owned(), "v2".to_owned())?;
+    store.remove("key".to_owned())?;
+    store.write(WriteBatch::new().set("default", "key", "v3"))?;
+
+    let history = store.history("key")?;
+    let seqs: Vec<u64> = history.iter().map(|version| version.seq).collect();
+    assert_eq!(seqs, [1, 3, 4, 5]);
+    let values: Vec<Option<&str>> = history.iter().map(|version| version.value.as_deref()).collect();
+    assert_eq!(values, [Some("v1"), Some("v2"), None, Some("v3")]);
+    assert!(history.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    assert_eq!(store.get_at("key", At::Seq(0))?, None);
+    assert_eq!(store.get_at("key", At::Seq(2))?, Some("v1".to_owned()));
+    assert_eq!(store.get_at("key", At::Seq(3))?, Some("v2".to_owned()));
+    assert_eq!(store.get_at("key", At::Seq(4))?, None);
+    assert_eq!(store.get_at("key", At::Time(SystemTime::now()))?, Some("v3".to_owned()));
+
+    // 重新打开之后序号继续递增
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key".to_owned(), "v4".to_owned())?;
+    assert_eq!(store.history("key")?.last().unwrap().seq, 6);
+    Ok(())
+}
+
+#[test]
+fn expiry_is_a_version() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("sessions", FamilyOptions::new().ttl(Duration::from_millis(200)))?;
+    store.set_cf("sessions", "s1".to_owned(), "token".to_owned())?;
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+
+    // 过期记录和删除一样是一个值为 None 的版本
+    let history = store.history_cf("sessions", "s1")?;
+    let values: Vec<Option<&str>> = history.iter().map(|version| version.value.as_deref()).collect();
+    assert_eq!(values, [Some("token"), None]);
+    Ok(())
+}
+
+#[test]
+fn sequence_numbers_are_not_reused_after_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("kept".to_owned(), "a".to_owned())?;
+    store.set("gone".to_owned(), "b".to_owned())?;
+    store.set("gone".to_owned(), "c".to_owned())?;
+    store.remove("gone".to_owned())?;
+    // compaction 丢掉了序号 2 到 4 的记录
+    store.compaction()?;
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("new".to_owned(), "d".to_owned())?;
+    assert_eq!(store.history("new")?[0].seq, 5);
+    assert_eq!(store.get_at("new", At::Seq(4))?, None);
+    store.remove("new".to_owned())?;
+    drop(store);
+
+    // 修复同样不会丢掉用过的序号
+    KvStore::repair(temp_dir.path(), &StoreOptions::default())?;
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("later".to_owned(), "e".to_owned())?;
+    assert_eq!(store.history("later")?[0].seq, 7);
+    Ok(())
+}
+
+#[test]
+fn time_travel_reads() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_family("users", FamilyOptions::new())?;
+    store.set_cf("users", "u1".to_owned(), "alice".to_owned())?;
+    thread::sleep(Duration::from_millis(20));
+    let between = SystemTime::now();
+    thread::sleep(Duration::from_millis(20));
+    store.set_cf("users", "u1".to_owned(), "bob".to_owned())?;
+
+    assert_eq!(store.get_at_cf("users", "u1", At::Time(between))?, Some("alice".to_owned()));
+    assert_eq!(store.get_at_cf("users", "u1", At::Time(SystemTime::now()))?, Some("bob".to_owned()));
+    assert_eq!(store.get_at("u1", At::Time(SystemTime::now()))?, None);
+
+    // 删除列族时它的历史也一起删除
+    store.drop_family("users")?;
+    store.create_family("users", FamilyOptions::new())?;
+    assert!(store.history_cf("users", "u1")?.is_empty());
+    Ok(())
+}
+
+#[test]
+fn compaction_keeps_versions_within_retention() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().history_retention(Duration::from_secs(3600));
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    store.set("key".to_owned(), "v1".to_owned())?;
+    store.set("key".to_owned(), "v2".to_owned())?;
+    store.set("gone".to_owned(), "x".to_owned())?;
+    store.remove("gone".to_owned())?;
+    store.compaction()?;
+    store.set("key".to_owned(), "v3".to_owned())?;
+    store.compaction()?;
+    assert_eq!(store.history("key")?.len(), 3);
+    assert_eq!(store.get_at("key", At::Seq(1))?, Some("v1".to_owned()));
+    assert_eq!(store.history("gone")?.len(), 2);
+
+    // 重新加载 compaction 之后的日志时以当前的值为准
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key".to_owned())?, Some("v3".to_owned()));
+    assert_eq!(store.get("gone".to_owned())?, None);
+    store.compaction()?;
+    assert_eq!(store.get_at("key", At::Seq(2))?, Some("v2".to_owned()));
+
+    // 没有保留时间时 compaction 只保留当前的值
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.compaction()?;
+    assert_eq!(store.history("key")?.len(), 1);
+    assert!(store.history("gone")?.is_empty());
+    assert_eq!(store.get_at("key", At::Seq(2))?, None);
+    Ok(())
+}
+
+#[test]
+fn cli_history() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for args in [&["set", "key", "v1"][..], &["set", "key", "v2"], &["rm", "key"]] {
+        Command::cargo_bin("kvs").unwrap().args(args).current_dir(&temp_dir).assert().success();
+    }
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["history", "key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(" v1\n").and(contains(" v2\n")).and(contains("3 ")).and(contains("(removed)")));
+}
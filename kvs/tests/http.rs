@@ -0,0 +1,161 @@
+use assert_cmd::prelude::*;
+use kvs::{Follower, HttpServer, KvStore, Result};
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// 发送一个 HTTP 请求，返回状态码和 JSON 内容
+fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        body.len(),
+        body
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn start_server(dir: &TempDir) -> Result<SocketAddr> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = HttpServer::new(KvStore::open(dir.path())?).threads(2);
+    thread::spawn(move || server.serve(listener));
+    Ok(addr)
+}
+
+#[test]
+fn http_get_put_delete() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = start_server(&dir)?;
+
+    assert_eq!(
+        request(addr, "PUT", "/keys/key1", "value1"),
+        (200, json!({ "key": "key1", "value": "value1" }))
+    );
+    assert_eq!(
+        request(addr, "GET", "/keys/key1", ""),
+        (200, json!({ "key": "key1", "value": "value1" }))
+    );
+    assert_eq!(request(addr, "DELETE", "/keys/key1", ""), (200, json!({ "key": "key1" })));
+
+    let (status, body) = request(addr, "GET", "/keys/key1", "");
+    assert_eq!(status, 404);
+    assert_eq!(body["error"], "Key not found");
+    assert_eq!(request(addr, "DELETE", "/keys/key1", "").0, 404);
+
+    // 键经过百分号解码
+    request(addr, "PUT", "/keys/hello%20world", "spaced");
+    assert_eq!(
+        request(addr, "GET", "/keys/hello%20world", ""),
+        (200, json!({ "key": "hello world", "value": "spaced" }))
+    );
+    Ok(())
+}
+
+#[test]
+fn http_prefix_listing() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = start_server(&dir)?;
+    for (key, value) in [("user:2", "b"), ("user:1", "a"), ("order:1", "c")] {
+        request(addr, "PUT", &format!("/keys/{}", key), value);
+    }
+
+    assert_eq!(
+        request(addr, "GET", "/keys?prefix=user%3A", ""),
+        (
+            200,
+            json!([{ "key": "user:1", "value": "a" }, { "key": "user:2", "value": "b" }])
+        )
+    );
+    assert_eq!(request(addr, "GET", "/keys", "").1.as_array().unwrap().len(), 3);
+    Ok(())
+}
+
+#[test]
+fn http_bad_requests() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = start_server(&dir)?;
+    assert_eq!(request(addr, "GET", "/other", "").0, 404);
+    assert_eq!(request(addr, "POST", "/keys/key1", "value").0, 405);
+    assert_eq!(request(addr, "DELETE", "/keys", "").0, 405);
+    assert_eq!(request(addr, "GET", "/keys/bad%zz", "").0, 400);
+    Ok(())
+}
+
+// 只读的从节点拒绝写入时返回 503
+#[test]
+fn http_read_only_replica() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let leader = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = HttpServer::new(Follower::start(dir.path(), leader)?);
+    thread::spawn(move || server.serve(listener));
+    assert_eq!(request(addr, "PUT", "/keys/key1", "value1").0, 503);
+    assert_eq!(request(addr, "GET", "/keys/key1", "").0, 404);
+    Ok(())
+}
+
+// 不发送请求的连接在超时后被关闭，不会一直占用唯一的工作线程
+#[test]
+fn http_idle_connection_times_out() -> Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let server = HttpServer::new(KvStore::open(dir.path())?)
+        .threads(1)
+        .read_timeout(Duration::from_millis(200));
+    thread::spawn(move || server.serve(listener));
+
+    let mut idle = TcpStream::connect(addr)?;
+    write!(idle, "GET /keys/key1 HTTP/1.1\r\n")?;
+    assert_eq!(request(addr, "PUT", "/keys/key1", "value1").0, 200);
+    let mut response = String::new();
+    idle.read_to_string(&mut response)?;
+    assert!(response.starts_with("HTTP/1.1 408 "));
+    Ok(())
+}
+
+// 测试结束时结束服务进程
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn cli_http_server() {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let _server = Server(
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", &addr.to_string(), "--protocol", "http"])
+            .current_dir(&dir)
+            .spawn()
+            .unwrap(),
+    );
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "server did not start");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(request(addr, "PUT", "/keys/key1", "value1").0, 200);
+    assert_eq!(request(addr, "GET", "/keys/key1", "").1["value"], "value1");
+}
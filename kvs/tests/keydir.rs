@@ -0,0 +1,107 @@
+use assert_cmd::prelude::*;
+use kvs::{EncryptionKey, IndexMode, KvError, KvStore, Result, StoreOptions};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn hashed() -> StoreOptions {
+    StoreOptions::new().index_memory_budget(0)
+}
+
+#[test]
+fn hashed_index_operations() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), hashed())?;
+    for i in 0..100 {
+        store.set(format!("key{:02}", i), format!("value{}", i))?;
+    }
+    store.set("key07".to_owned(), "changed".to_owned())?;
+    store.remove("key08".to_owned())?;
+    assert!(matches!(store.remove("key08".to_owned()), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get("key07".to_owned())?, Some("changed".to_owned()));
+    assert_eq!(store.get("key08".to_owned())?, None);
+    assert_eq!(store.get("missing".to_owned())?, None);
+    // 扫描结果仍然按键排序
+    let pairs = store.scan("key0")?;
+    assert_eq!(pairs.len(), 9);
+    assert_eq!(pairs[0], ("key00".to_owned(), "value0".to_owned()));
+    assert_eq!(pairs[7], ("key07".to_owned(), "changed".to_owned()));
+    assert_eq!(pairs[8].0, "key09");
+    drop(store);
+
+    // 覆盖和删除重新加载到指纹索引中的结果正确
+    let mut store = KvStore::open_with(temp_dir.path(), hashed())?;
+    assert_eq!(store.scan("")?.len(), 99);
+    assert_eq!(store.get("key07".to_owned())?, Some("changed".to_owned()));
+    assert_eq!(store.get("key08".to_owned())?, None);
+    store.compaction()?;
+    assert_eq!(store.get("key99".to_owned())?, Some("value99".to_owned()));
+    assert_eq!(store.stats()?.stale_bytes, 0);
+    Ok(())
+}
+
+#[test]
+fn index_switches_to_fingerprints_over_budget() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let long_key = |i: usize| format!("{}{}", "k".repeat(200), i);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 0..50 {
+        store.set(long_key(i), "value".to_owned())?;
+    }
+    let full = store.stats()?;
+    assert_eq!(full.index_mode, IndexMode::Full);
+    drop(store);
+
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().index_memory_budget(full.index_bytes / 2))?;
+    let stats = store.stats()?;
+    assert_eq!(stats.index_mode, IndexMode::Hashed);
+    assert!(stats.index_bytes < full.index_bytes / 2);
+    assert_eq!(stats.live_keys, 50);
+    assert_eq!(stats.avg_key_bytes, full.avg_key_bytes);
+    assert_eq!(store.get(long_key(42))?, Some("value".to_owned()));
+
+    // 索引没有超过预算时保留完整的键
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().index_memory_budget(full.index_bytes))?;
+    assert_eq!(store.stats()?.index_mode, IndexMode::Full);
+    Ok(())
+}
+
+#[test]
+fn hashed_index_with_encryption() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = hashed().encryption_key(EncryptionKey::new([7; 32]));
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn cli_index_memory_budget() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["stats", "--index-memory-budget", "0"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("index:            key fingerprints"));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["stats", "--json"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("\"index_mode\": \"full\""));
+}
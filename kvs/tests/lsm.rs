@@ -0,0 +1,162 @@
+use assert_cmd::prelude::*;
+use kvs::{KvError, LsmOptions, LsmStore, Result};
+use predicates::ord::eq;
+use predicates::str::PredicateStrExt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+use tempfile::TempDir;
+
+// 表很小，测试中会多次刷盘并产生多层 compaction
+fn small_options() -> LsmOptions {
+    LsmOptions::new()
+        .memtable_size(4 * 1024)
+        .block_size(256)
+        .table_size(8 * 1024)
+        .level0_limit(2)
+        .level_base_size(16 * 1024)
+}
+
+#[test]
+fn lsm_get_set_remove() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    assert!(matches!(store.remove("key2".to_owned()), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // 没有刷盘的写入从预写日志恢复
+    drop(store);
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// 追加时崩溃留下的半条记录在打开时被截掉，之后的写入照常恢复
+#[test]
+fn lsm_torn_wal_record_is_truncated() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    drop(store);
+    OpenOptions::new()
+        .append(true)
+        .open(temp_dir.path().join("lsm.wal"))?
+        .write_all(br#"{"Set":{"key":"key2","va"#)?;
+
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+// 覆盖和删除在刷盘和分层 compaction 之后仍然有效
+#[test]
+fn lsm_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open_with(temp_dir.path(), small_options())?;
+
+    for iter in 0..10 {
+        for key_id in 0..2000 {
+            store.set(format!("key{}", key_id), format!("{}-{}", key_id, iter))?;
+        }
+    }
+    for key_id in (0..2000).step_by(3) {
+        store.remove(format!("key{}", key_id))?;
+    }
+    store.flush()?;
+
+    let check = |store: &mut LsmStore| -> Result<()> {
+        for key_id in 0..2000 {
+            let expected = (key_id % 3 != 0).then(|| format!("{}-9", key_id));
+            assert_eq!(store.get(format!("key{}", key_id))?, expected);
+        }
+        Ok(())
+    };
+    check(&mut store)?;
+    drop(store);
+    let mut store = LsmStore::open_with(temp_dir.path(), small_options())?;
+    check(&mut store)?;
+
+    let tables = std::fs::read_dir(temp_dir.path())?
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("sst".as_ref()))
+        .count();
+    assert!(tables > 1, "expected data to be spread over several tables");
+    Ok(())
+}
+
+// SSTable 中损坏的长度报告为损坏，不会按它分配内存
+#[test]
+fn lsm_corrupt_table_length() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.flush()?;
+    drop(store);
+    let table = std::fs::read_dir(temp_dir.path())?
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension() == Some("sst".as_ref()))
+        .unwrap();
+    let mut content = std::fs::read(&table)?;
+    content[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+    std::fs::write(&table, content)?;
+
+    let mut store = LsmStore::open(temp_dir.path())?;
+    assert!(matches!(store.get("key1".to_owned()), Err(KvError::Corruption(_))));
+    assert!(matches!(store.scan(""), Err(KvError::Corruption(_))));
+    Ok(())
+}
+
+// 扫描合并内存表和每一层，以最新的值为准
+#[test]
+fn lsm_scan() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = LsmStore::open_with(temp_dir.path(), small_options())?;
+    for key_id in 0..1000 {
+        store.set(format!("key{:04}", key_id), "old".to_owned())?;
+    }
+    store.flush()?;
+    store.set("key0001".to_owned(), "new".to_owned())?;
+    store.remove("key0002".to_owned())?;
+
+    let pairs = store.scan("key000")?;
+    assert_eq!(
+        pairs,
+        (0..10)
+            .filter(|id| *id != 2)
+            .map(|id| (format!("key{:04}", id), if id == 1 { "new" } else { "old" }.to_owned()))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(store.scan("")?.len(), 999);
+    Ok(())
+}
+
+// `kvs --engine lsm` 使用 LSM 引擎保存数据
+#[test]
+fn cli_lsm_engine() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["--engine", "lsm", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["--engine", "lsm", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+    assert!(temp_dir.path().join("lsm.wal").exists());
+}
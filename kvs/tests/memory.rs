@@ -0,0 +1,68 @@
+use kvs::{KvError, KvStore, KvsEngine, MemoryStore, Result};
+use tempfile::TempDir;
+
+// scan 按键的升序返回前缀下的键值对
+#[test]
+fn scan_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for key in ["user:2", "user:10", "order:1", "user:1", "users"] {
+        store.set(key.to_owned(), format!("{}-value", key))?;
+    }
+    store.remove("user:10".to_owned())?;
+
+    let keys: Vec<String> = store.scan("user:")?.into_iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec!["user:1", "user:2"]);
+    assert_eq!(store.scan("")?.len(), 4);
+    Ok(())
+}
+
+// 内存引擎对同样的操作和 KvStore 表现一致
+#[test]
+fn memory_store_semantics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut disk = KvStore::open(temp_dir.path())?;
+    let mut memory = MemoryStore::new();
+    let engines: [&mut dyn KvsEngine; 2] = [&mut disk, &mut memory];
+
+    let mut results = Vec::new();
+    for store in engines {
+        store.set("b".to_owned(), "1".to_owned())?;
+        store.set("a".to_owned(), "2".to_owned())?;
+        store.set("b".to_owned(), "3".to_owned())?;
+        store.set("c".to_owned(), "4".to_owned())?;
+        store.remove("c".to_owned())?;
+        assert!(matches!(store.remove("c".to_owned()), Err(KvError::KeyNotFound)));
+        assert_eq!(store.get("c".to_owned())?, None);
+        results.push(store.scan("")?);
+    }
+    assert_eq!(results[0], results[1]);
+    Ok(())
+}
+
+// 内存快照是普通的日志，KvStore 可以打开它，反过来也可以
+#[test]
+fn memory_store_snapshot() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut memory = MemoryStore::new();
+    memory.set("key1".to_owned(), "value1".to_owned())?;
+    memory.set("key2".to_owned(), "value2".to_owned())?;
+    memory.snapshot_to(temp_dir.path())?;
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.remove("key1".to_owned())?;
+    drop(store);
+
+    let mut memory = MemoryStore::load_from(temp_dir.path())?;
+    assert_eq!(memory.get("key1".to_owned())?, None);
+    assert_eq!(
+        memory.scan("")?,
+        vec![
+            ("key2".to_owned(), "value2".to_owned()),
+            ("key3".to_owned(), "value3".to_owned())
+        ]
+    );
+    Ok(())
+}
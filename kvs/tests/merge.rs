@@ -0,0 +1,127 @@
+use assert_cmd::prelude::*;
+use kvs::{Change, KvError, KvStore, MemoryFs, Result, StoreOptions};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+fn counters_fold_during_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().value_cache(1 << 20))?;
+    store.incr("hits".to_owned(), 1)?;
+    store.incr("hits".to_owned(), 5)?;
+    assert_eq!(store.get("hits".to_owned())?, Some("6".to_owned()));
+    // 合并使缓存的值失效
+    store.decr("hits".to_owned(), 2)?;
+    assert_eq!(store.get("hits".to_owned())?, Some("4".to_owned()));
+    store.set("base".to_owned(), "100".to_owned())?;
+    store.incr("base".to_owned(), 1)?;
+    assert_eq!(store.scan("")?, [("base".to_owned(), "101".to_owned()), ("hits".to_owned(), "4".to_owned())]);
+    assert_eq!(store.stats()?.ops.merges, 4);
+
+    store.compaction()?;
+    // 操作数被合并成了一个值
+    assert_eq!(store.history("hits")?.len(), 1);
+    store.incr("hits".to_owned(), 10)?;
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("hits".to_owned())?, Some("14".to_owned()));
+    assert_eq!(store.get("base".to_owned())?, Some("101".to_owned()));
+    // set 覆盖还没合并的操作数，remove 清除计数
+    store.set("hits".to_owned(), "0".to_owned())?;
+    assert_eq!(store.get("hits".to_owned())?, Some("0".to_owned()));
+    store.remove("base".to_owned())?;
+    store.incr("base".to_owned(), 1)?;
+    assert_eq!(store.get("base".to_owned())?, Some("1".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn custom_and_builtin_operators() -> Result<()> {
+    let fs = MemoryFs::new();
+    let upper = |_key: &str, existing: Option<&str>, operand: &str| {
+        Ok::<_, KvError>(format!("{}{}", existing.unwrap_or(""), operand.to_uppercase()))
+    };
+    let options = StoreOptions::new().file_system(fs.clone()).merge_operator("upper", upper);
+    let mut store = KvStore::open_with("data", options.clone())?;
+    store.merge("name".to_owned(), "upper", "ab".to_owned())?;
+    store.merge("name".to_owned(), "upper", "c".to_owned())?;
+    assert_eq!(store.get("name".to_owned())?, Some("ABC".to_owned()));
+    store.merge("max".to_owned(), "max", "3".to_owned())?;
+    store.merge("max".to_owned(), "max", "1".to_owned())?;
+    assert_eq!(store.get("max".to_owned())?, Some("3".to_owned()));
+    store.merge("list".to_owned(), "append", "1".to_owned())?;
+    store.merge("list".to_owned(), "append", r#""two""#.to_owned())?;
+    assert_eq!(store.get("list".to_owned())?, Some(r#"[1,"two"]"#.to_owned()));
+    assert!(matches!(
+        store.merge("name".to_owned(), "missing", "x".to_owned()),
+        Err(KvError::UnknownMergeOperator(_))
+    ));
+    drop(store);
+
+    // 日志中记录的是运算符的名字，重新打开时需要再次注册
+    let mut store = KvStore::open_with("data", StoreOptions::new().file_system(fs))?;
+    assert!(matches!(store.get("name".to_owned()), Err(KvError::UnknownMergeOperator(_))));
+    drop(store);
+    let mut store = KvStore::open_with("data", options)?;
+    assert_eq!(store.get("name".to_owned())?, Some("ABC".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn failed_merges_surface_on_read() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("");
+    store.set("key".to_owned(), "text".to_owned())?;
+    // incr 在写入之前检查现有的值
+    assert!(matches!(store.incr("key".to_owned(), 1), Err(KvError::MergeFailed(_))));
+    assert_eq!(store.get("key".to_owned())?, Some("text".to_owned()));
+    store.merge("key".to_owned(), "add", "1".to_owned())?;
+    assert!(matches!(store.get("key".to_owned()), Err(KvError::MergeFailed(_))));
+    // compaction 保留无法合并的操作数
+    store.compaction()?;
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert!(matches!(store.get("key".to_owned()), Err(KvError::MergeFailed(_))));
+    store.set("key".to_owned(), "1".to_owned())?;
+    assert_eq!(store.get("key".to_owned())?, Some("1".to_owned()));
+
+    let changes: Vec<Change> = events.iter().map(|event| event.change).collect();
+    assert_eq!(
+        changes[1],
+        Change::Merge {
+            key: "key".to_owned(),
+            operator: "add".to_owned(),
+            operand: "1".to_owned()
+        }
+    );
+    Ok(())
+}
+
+#[test]
+fn cli_incr() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["incr", "count"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["incr", "count", "-5"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-4\n");
+    Command::cargo_bin("kvs").unwrap().args(["set", "name", "x"]).current_dir(&temp_dir).assert().success();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["incr", "name"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("not an integer"));
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{client, free_addr, wait_for, Server};
+use kvs::{
+    FamilyOptions, KvError, KvStore, KvsClient, KvsEngine, KvsServer, RaftConfig, RaftNode, RaftRole, Result,
+};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// size 个成员的集群的数据目录、配置和客户端地址
+fn cluster(size: u64, snapshot_threshold: u64) -> (Vec<TempDir>, Vec<RaftConfig>, Vec<SocketAddr>) {
+    let addrs: Vec<_> = (0..size).map(|_| (free_addr(), free_addr())).collect();
+    let configs = (1..=size)
+        .map(|id| {
+            addrs
+                .iter()
+                .zip(1..)
+                .fold(RaftConfig::new(id), |config, (&(raft_addr, client_addr), peer)| {
+                    config.peer(peer, raft_addr, client_addr)
+                })
+                .election_timeout(Duration::from_millis(200))
+                .snapshot_threshold(snapshot_threshold)
+        })
+        .collect();
+    let dirs = (0..size)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    (dirs, configs, addrs.iter().map(|&(_, client_addr)| client_addr).collect())
+}
+
+// 当前主节点的下标
+fn leader(nodes: &[Option<RaftNode>]) -> usize {
+    let mut found = None;
+    wait_for(|| {
+        found = nodes.iter().position(|node| {
+            node.as_ref()
+                .is_some_and(|node| node.status().unwrap().role == RaftRole::Leader)
+        });
+        found.is_some()
+    });
+    found.unwrap()
+}
+
+#[test]
+fn raft_cluster_replicates_and_fails_over() -> Result<()> {
+    let (dirs, configs, clients) = cluster(3, 1000);
+    let mut nodes: Vec<Option<RaftNode>> = dirs
+        .iter()
+        .zip(&configs)
+        .map(|(dir, config)| RaftNode::start(dir.path(), config.clone()).map(Some))
+        .collect::<Result<_>>()?;
+
+    let first = leader(&nodes);
+    let node = nodes[first].as_ref().unwrap();
+    node.set("key1".to_owned(), "value1".to_owned())?;
+    node.set("key2".to_owned(), "value2".to_owned())?;
+    node.remove("key2".to_owned())?;
+    assert!(matches!(node.remove("key2".to_owned()), Err(KvError::KeyNotFound)));
+    assert_eq!(node.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(node.scan("key")?, vec![("key1".to_owned(), "value1".to_owned())]);
+
+    // 从节点把客户端重定向到主节点的客户端地址
+    let follower = (first + 1) % 3;
+    let redirect = nodes[follower].as_ref().unwrap().get("key1".to_owned());
+    assert!(
+        matches!(redirect, Err(KvError::NotLeader { leader: Some(ref addr) }) if *addr == clients[first].to_string())
+    );
+    assert!(matches!(
+        nodes[follower].as_ref().unwrap().set("key3".to_owned(), "value3".to_owned()),
+        Err(KvError::NotLeader { .. })
+    ));
+
+    // 主节点下线后选出新的主节点，已经提交的写入都还在
+    nodes[first] = None;
+    let second = leader(&nodes);
+    let node = nodes[second].as_ref().unwrap();
+    assert_eq!(node.get("key1".to_owned())?, Some("value1".to_owned()));
+    node.set("key3".to_owned(), "value3".to_owned())?;
+
+    // 旧的主节点作为从节点重新加入并追上进度
+    nodes[first] = Some(RaftNode::start(dirs[first].path(), configs[first].clone())?);
+    let commit = nodes[second].as_ref().unwrap().status()?.commit_index;
+    wait_for(|| nodes[first].as_ref().unwrap().status().unwrap().last_applied >= commit);
+    drop(nodes);
+
+    let mut store = KvStore::open(dirs[first].path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// 缺少的条目已经压缩进快照时，从节点收到快照
+#[test]
+fn raft_follower_catches_up_from_snapshot() -> Result<()> {
+    let (dirs, configs, _) = cluster(3, 5);
+    let mut nodes: Vec<Option<RaftNode>> = dirs
+        .iter()
+        .zip(&configs)
+        .map(|(dir, config)| RaftNode::start(dir.path(), config.clone()).map(Some))
+        .collect::<Result<_>>()?;
+
+    let first = leader(&nodes);
+    let lagging = (first + 1) % 3;
+    nodes[lagging] = None;
+
+    let node = nodes[first].as_ref().unwrap();
+    for i in 0..20 {
+        node.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    node.remove("key0".to_owned())?;
+    let commit = node.status()?.commit_index;
+
+    nodes[lagging] = Some(RaftNode::start(dirs[lagging].path(), configs[lagging].clone())?);
+    wait_for(|| nodes[lagging].as_ref().unwrap().status().unwrap().last_applied >= commit);
+    drop(nodes);
+
+    let mut store = KvStore::open(dirs[lagging].path())?;
+    assert_eq!(store.get("key0".to_owned())?, None);
+    assert_eq!(store.get("key19".to_owned())?, Some("value19".to_owned()));
+    assert_eq!(store.scan("key")?.len(), 19);
+    Ok(())
+}
+
+// 快照包括所有列族，较大的快照分成多块发送
+#[test]
+fn raft_snapshot_includes_all_families() -> Result<()> {
+    let (dirs, configs, _) = cluster(3, 5);
+    for dir in &dirs {
+        let mut store = KvStore::open(dir.path())?;
+        store.create_family("users", FamilyOptions::new())?;
+        for i in 0..2500 {
+            store.set_cf("users", format!("u{:04}", i), format!("user{}", i))?;
+        }
+        store.create_family("empty", FamilyOptions::new())?;
+    }
+    let mut nodes: Vec<Option<RaftNode>> = dirs
+        .iter()
+        .zip(&configs)
+        .map(|(dir, config)| RaftNode::start(dir.path(), config.clone()).map(Some))
+        .collect::<Result<_>>()?;
+
+    let first = leader(&nodes);
+    let node = nodes[first].as_ref().unwrap();
+    for i in 0..10 {
+        node.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    let commit = node.status()?.commit_index;
+
+    // 丢失了所有数据的节点只能从快照恢复
+    let lagging = (first + 1) % 3;
+    nodes[lagging] = None;
+    fs::remove_dir_all(dirs[lagging].path())?;
+    fs::create_dir(dirs[lagging].path())?;
+    nodes[lagging] = Some(RaftNode::start(dirs[lagging].path(), configs[lagging].clone())?);
+    wait_for(|| nodes[lagging].as_ref().unwrap().status().unwrap().last_applied >= commit);
+    drop(nodes);
+
+    let mut store = KvStore::open(dirs[lagging].path())?;
+    assert_eq!(store.scan("key")?.len(), 10);
+    assert_eq!(store.scan_cf("users", "")?.len(), 2500);
+    assert_eq!(store.get_cf("users", "u2499".to_owned())?, Some("user2499".to_owned()));
+    assert!(store.families().iter().any(|(name, _)| name == "empty"));
+    Ok(())
+}
+
+// 等待提交的写入不持有 kvs-server 的锁，无法提交的写入同时等待各自的超时，而不是排队
+#[test]
+fn raft_pending_commits_do_not_block_other_clients() -> Result<()> {
+    let (dirs, configs, _) = cluster(3, 1000);
+    let mut nodes: Vec<Option<RaftNode>> = dirs
+        .iter()
+        .zip(&configs)
+        .map(|(dir, config)| RaftNode::start(dir.path(), config.clone()).map(Some))
+        .collect::<Result<_>>()?;
+    let first = leader(&nodes);
+    let node = nodes[first].take().unwrap();
+    node.set("key1".to_owned(), "value1".to_owned())?;
+    // 两个从节点下线，主节点的写入无法提交
+    drop(nodes);
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    thread::spawn(move || KvsServer::new(node).serve(listener));
+    let started = Instant::now();
+    let writers: Vec<_> = (0..3)
+        .map(|i| thread::spawn(move || KvsClient::connect(addr)?.set(format!("key{}", i), "value".to_owned())))
+        .collect();
+    for writer in writers {
+        assert!(writer.join().unwrap().is_err());
+    }
+    assert!(started.elapsed() < Duration::from_secs(10));
+    Ok(())
+}
+
+// 追加条目时崩溃留下的不完整条目在重启时被截掉
+#[test]
+fn raft_torn_log_entry_is_truncated() -> Result<()> {
+    let (dirs, configs, _) = cluster(1, 1000);
+    let node = RaftNode::start(dirs[0].path(), configs[0].clone())?;
+    wait_for(|| node.status().unwrap().role == RaftRole::Leader);
+    node.set("key1".to_owned(), "value1".to_owned())?;
+    drop(node);
+    let log = dirs[0].path().join("raft").join("log.json");
+    OpenOptions::new().append(true).open(&log)?.write_all(br#"{"index":3,"term":1,"comm"#)?;
+
+    let node = RaftNode::start(dirs[0].path(), configs[0].clone())?;
+    wait_for(|| node.status().unwrap().role == RaftRole::Leader);
+    assert_eq!(node.get("key1".to_owned())?, Some("value1".to_owned()));
+    node.set("key2".to_owned(), "value2".to_owned())?;
+    drop(node);
+    let node = RaftNode::start(dirs[0].path(), configs[0].clone())?;
+    wait_for(|| node.status().unwrap().role == RaftRole::Leader);
+    assert_eq!(node.get("key2".to_owned())?, Some("value2".to_owned()));
+    Ok(())
+}
+
+// 本机上的三个 kvs-server 进程，客户端可以连接其中任意一个
+#[test]
+fn cli_raft_cluster_processes() {
+    let addrs: Vec<_> = (0..3).map(|_| (free_addr(), free_addr())).collect();
+    let peers: Vec<String> = addrs
+        .iter()
+        .zip(1..)
+        .map(|((raft_addr, client_addr), id)| format!("{}={},{}", id, raft_addr, client_addr))
+        .collect();
+    let dirs: Vec<_> = (0..3)
+        .map(|_| TempDir::new().expect("unable to create temporary working directory"))
+        .collect();
+    let _servers: Vec<_> = dirs
+        .iter()
+        .zip(&addrs)
+        .zip(1..)
+        .map(|((dir, (_, client_addr)), id)| {
+            let mut command = Command::cargo_bin("kvs-server").unwrap();
+            command
+                .args(["--addr", &client_addr.to_string()])
+                .args(["--raft-id", &id.to_string()]);
+            for peer in &peers {
+                command.args(["--peer", peer]);
+            }
+            Server(command.current_dir(dir).spawn().unwrap())
+        })
+        .collect();
+
+    wait_for(|| client(addrs[0].1, &["set", "key1", "value1"]).status.success());
+    for (_, client_addr) in &addrs {
+        wait_for(|| client(*client_addr, &["get", "key1"]).stdout == b"value1\n");
+    }
+    assert!(client(addrs[2].1, &["rm", "key1"]).status.success());
+    assert_eq!(client(addrs[1].1, &["get", "key1"]).stdout, b"Key not found\n");
+}
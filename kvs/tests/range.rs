@@ -0,0 +1,103 @@
+use assert_cmd::prelude::*;
+use kvs::{Change, IndexMode, KvStore, Result, StoreOptions};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+fn range_tombstone_survives_reopen_and_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("user:");
+    for key in ["user:1", "user:2", "user:3", "userx", "zebra"] {
+        store.set(key.to_owned(), "v".to_owned())?;
+    }
+    assert_eq!(store.remove_range("user:1".."user:3")?, 2);
+    assert_eq!(store.remove_range("b".."a")?, 0);
+    assert_eq!(store.get("user:1".to_owned())?, None);
+    assert_eq!(store.get("user:3".to_owned())?, Some("v".to_owned()));
+    let changes: Vec<Change> = events.try_iter().map(|event| event.change).collect();
+    assert_eq!(
+        changes[3],
+        Change::RemoveRange {
+            start: "user:1".to_owned(),
+            end: Some("user:3".to_owned())
+        }
+    );
+    // 范围删除之后写入的键不受影响
+    store.set("user:2".to_owned(), "back".to_owned())?;
+
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("user:1".to_owned())?, None);
+    assert_eq!(store.get("user:2".to_owned())?, Some("back".to_owned()));
+    assert_eq!(store.remove_prefix("user:")?, 2);
+    store.compaction()?;
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.scan("")?, [("userx".to_owned(), "v".to_owned()), ("zebra".to_owned(), "v".to_owned())]);
+    assert_eq!(store.history("user:3")?.len(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn range_tombstone_in_hashed_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().index_memory_budget(0);
+    let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+    for i in 0..10 {
+        store.set(format!("key{}", i), i.to_string())?;
+    }
+    assert_eq!(store.stats()?.index_mode, IndexMode::Hashed);
+    assert_eq!(store.remove_range("key3".."key7")?, 4);
+    assert_eq!(store.history("key5")?.last().unwrap().value, None);
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key5".to_owned())?, None);
+    assert_eq!(store.get("key7".to_owned())?, Some("7".to_owned()));
+    assert_eq!(store.scan("key")?.len(), 6);
+    Ok(())
+}
+
+#[test]
+fn multi_get_and_multi_set() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), StoreOptions::new().value_cache(1 << 20))?;
+    store.multi_set(vec![
+        ("a".to_owned(), "1".to_owned()),
+        ("b".to_owned(), "2".to_owned()),
+        ("a".to_owned(), "3".to_owned()),
+    ])?;
+    store.incr("c".to_owned(), 5)?;
+    let keys = ["b", "missing", "a", "c", "b"].map(str::to_owned);
+    let expected = [Some("2"), None, Some("3"), Some("5"), Some("2")].map(|value| value.map(str::to_owned));
+    assert_eq!(store.multi_get(&keys)?, expected);
+    // 第二次读取来自值缓存
+    assert_eq!(store.multi_get(&keys)?, expected);
+    assert_eq!(store.stats()?.ops.gets, 10);
+    Ok(())
+}
+
+#[test]
+fn cli_rm_prefix_and_mget() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    for args in [["set", "tmp:1", "a"], ["set", "tmp:2", "b"], ["set", "keep", "c"]] {
+        Command::cargo_bin("kvs").unwrap().args(args).current_dir(&temp_dir).assert().success();
+    }
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["rm", "--prefix", "tmp:"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("Removed 2 keys"));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["mget", "tmp:1", "keep"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\nc\n");
+    Command::cargo_bin("kvs").unwrap().args(["rm"]).current_dir(&temp_dir).assert().failure();
+}
@@ -0,0 +1,255 @@
+use assert_cmd::prelude::*;
+use kvs::{EncryptionKey, Format, KvError, KvStore, Result, StoreOptions, WriteBatch, DEFAULT_FAMILY};
+use predicates::str::contains;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+// 目录中唯一非空的日志文件
+fn data_log(dir: &Path) -> PathBuf {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| {
+            path.extension() == Some("log".as_ref()) && fs::metadata(path).unwrap().len() > 0
+        })
+        .unwrap()
+}
+
+fn write_keys(dir: &Path, options: StoreOptions) -> Result<()> {
+    let mut store = KvStore::open_with(dir, options)?;
+    for i in 1..=3 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    Ok(())
+}
+
+#[test]
+fn verify_and_repair_malformed_record() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path(), StoreOptions::default())?;
+    assert!(KvStore::verify(temp_dir.path(), &StoreOptions::default())?.is_clean());
+
+    // 损坏 key2 的记录
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    let start = content.windows(6).position(|w| w == b"\"key2\"").unwrap();
+    content[start + 2] = b'\\';
+    fs::write(&log, &content)?;
+    assert!(KvStore::open(temp_dir.path()).is_err());
+
+    let report = KvStore::verify(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!(report.records, 2);
+    assert_eq!(report.damaged.len(), 1);
+    assert!(report.damaged[0].offset > 0 && report.damaged[0].offset <= start as u64);
+
+    let report = KvStore::repair(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!(
+        (report.records, report.live_keys, report.lost.len()),
+        (2, 2, 1)
+    );
+    assert!(temp_dir
+        .path()
+        .join("corrupt")
+        .join(log.file_name().unwrap())
+        .exists());
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    drop(store);
+    assert!(KvStore::verify(temp_dir.path(), &StoreOptions::default())?.is_clean());
+    Ok(())
+}
+
+// 崩溃中断的写入在日志末尾留下不完整的记录
+#[test]
+fn repair_truncated_tail() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path(), StoreOptions::default())?;
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    content.extend_from_slice(br#"{"Set":{"key":"key4","val"#);
+    fs::write(&log, &content)?;
+
+    let report = KvStore::verify(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!(report.damaged.len(), 1);
+    assert_eq!(report.damaged[0].reason, "truncated record");
+    assert_eq!(report.damaged[0].len, 25);
+
+    let report = KvStore::repair(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!((report.live_keys, report.lost_bytes()), (3, 25));
+    Ok(())
+}
+
+// 日志在批次的记录边界上结束：没有无法解码的字节，但批次中已经读到的写入也丢失了
+#[test]
+fn repair_reports_incomplete_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.write(WriteBatch::new().set(DEFAULT_FAMILY, "key2", "value2").set(DEFAULT_FAMILY, "key3", "value3"))?;
+    drop(store);
+
+    let log = data_log(temp_dir.path());
+    let content = fs::read(&log)?;
+    let last = content.windows(6).rposition(|w| w == br#"{"Set""#).unwrap();
+    fs::write(&log, &content[..last])?;
+
+    let report = KvStore::repair(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!(report.live_keys, 1);
+    assert_eq!(report.lost.len(), 1);
+    assert_eq!(report.lost[0].reason, "incomplete batch, 1 of 2 records missing");
+    assert!(report.lost_bytes() > 0);
+    Ok(())
+}
+
+// 未加密的 JSON 日志没有校验和，报告中说明只检查了格式；其它日志的每条记录都有校验和
+#[test]
+fn verify_lists_unchecked_generations() -> Result<()> {
+    let plain_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(plain_dir.path(), StoreOptions::default())?;
+    let report = KvStore::verify(plain_dir.path(), &StoreOptions::default())?;
+    assert_eq!(report.unchecked.len() as u64, report.generations);
+    assert!(report.to_string().contains("have no checksum"));
+
+    let encrypted_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().encryption_key(EncryptionKey::new([3; 32]));
+    write_keys(encrypted_dir.path(), options.clone())?;
+    let report = KvStore::verify(encrypted_dir.path(), &options)?;
+    assert!(report.unchecked.is_empty());
+
+    let bincode_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(bincode_dir.path(), StoreOptions::new().record_format(Format::Bincode))?;
+    let report = KvStore::verify(bincode_dir.path(), &StoreOptions::default())?;
+    assert!(report.unchecked.is_empty());
+    Ok(())
+}
+
+// 改动后仍然能解码的记录也能通过校验和发现
+#[test]
+fn verify_detects_changed_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().record_format(Format::Bincode);
+    write_keys(temp_dir.path(), options.clone())?;
+
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    let start = content.windows(6).position(|w| w == b"value2").unwrap();
+    content[start + 5] = b'9';
+    fs::write(&log, &content)?;
+
+    let report = KvStore::verify(temp_dir.path(), &options)?;
+    assert_eq!(report.records, 2);
+    assert_eq!(report.damaged.len(), 1);
+    assert_eq!(report.damaged[0].reason, "checksum mismatch");
+    assert!(matches!(KvStore::open_with(temp_dir.path(), options.clone()), Err(KvError::Corruption(_))));
+
+    KvStore::repair(temp_dir.path(), &options)?;
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn repair_keeps_records_after_damage() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    for i in 1..=3 {
+        store.set(format!("user:{}", i), "v".to_owned())?;
+    }
+    store.remove_range("user:1".."user:9")?;
+    store.incr("count".to_owned(), 2)?;
+    store.incr("count".to_owned(), 3)?;
+    drop(store);
+
+    // 损坏范围删除之前的一条记录，之后的范围删除和合并记录都要保留
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    let start = content.windows(8).position(|w| w == b"\"user:3\"").unwrap();
+    content[start + 2] = b'\\';
+    fs::write(&log, &content)?;
+
+    let report = KvStore::repair(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!((report.records, report.lost.len()), (5, 1));
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("user:1".to_owned())?, None);
+    assert_eq!(store.get("user:2".to_owned())?, None);
+    assert_eq!(store.get("count".to_owned())?, Some("5".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn repair_encrypted_store() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let options = StoreOptions::new().encryption_key(EncryptionKey::new([3; 32]));
+    write_keys(temp_dir.path(), options.clone())?;
+
+    // 改动日志末尾附近的一个密文字节，最后一条记录的校验和不一致
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    let last = content.len() - 5;
+    content[last] ^= 0xff;
+    fs::write(&log, &content)?;
+
+    let report = KvStore::verify(temp_dir.path(), &options)?;
+    assert_eq!(report.records, 2);
+    assert_eq!(report.damaged[0].reason, "checksum mismatch");
+    // 没有密钥什么都读不出来
+    let report = KvStore::verify(temp_dir.path(), &StoreOptions::default())?;
+    assert_eq!(report.records, 0);
+
+    KvStore::repair(temp_dir.path(), &options)?;
+    let mut store = KvStore::open_with(temp_dir.path(), options)?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, None);
+    Ok(())
+}
+
+#[test]
+fn cli_verify_repair() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    write_keys(temp_dir.path(), StoreOptions::default())?;
+    let log = data_log(temp_dir.path());
+    let mut content = fs::read(&log)?;
+    content.insert(0, b'#');
+    fs::write(&log, &content)?;
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["verify"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stdout(contains(format!(
+            "{} at offset 0: 1 bytes unreadable",
+            log.file_name().unwrap().to_str().unwrap()
+        )));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["repair"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(
+            "recovered 3 records into 3 keys, lost 1 bytes in 1 regions",
+        ));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["verify", "--json"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("\"damaged\": []"));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+    Ok(())
+}
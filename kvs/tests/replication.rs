@@ -0,0 +1,259 @@
+mod common;
+
+use assert_cmd::prelude::*;
+use common::{client, free_addr, wait_for, Server};
+use kvs::{
+    FamilyOptions, Follower, KvError, KvStore, KvsEngine, ReplicationLeader, Result, WriteBatch, DEFAULT_FAMILY,
+};
+use std::fs;
+use std::net::TcpStream;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn start_leader(dir: &TempDir) -> Result<(Arc<Mutex<KvStore>>, ReplicationLeader)> {
+    let store = Arc::new(Mutex::new(KvStore::open(dir.path())?));
+    let leader = ReplicationLeader::start(Arc::clone(&store), "127.0.0.1:0")?;
+    Ok((store, leader))
+}
+
+#[test]
+fn follower_applies_leader_writes() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    store.lock().unwrap().set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("key1".to_owned()).unwrap() == Some("value1".to_owned()));
+
+    store.lock().unwrap().set("key2".to_owned(), "value2".to_owned())?;
+    store.lock().unwrap().remove("key1".to_owned())?;
+    wait_for(|| follower.get("key1".to_owned()).unwrap().is_none());
+    assert_eq!(follower.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    // 从节点只读，并报告落后主节点多少
+    assert!(matches!(follower.set("key3".to_owned(), "value3".to_owned()), Err(KvError::ReadOnly)));
+    assert!(matches!(follower.remove("key2".to_owned()), Err(KvError::ReadOnly)));
+    wait_for(|| follower.status().lag_bytes() == Some(0));
+    assert!(follower.status().connected);
+    Ok(())
+}
+
+// 从节点重启后从已经应用的位置继续，这个位置被主节点 compaction 删除时改用检查点
+#[test]
+fn follower_catches_up_after_restart() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    store.lock().unwrap().set("key1".to_owned(), "value1".to_owned())?;
+    store.lock().unwrap().set("key2".to_owned(), "value2".to_owned())?;
+
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("key2".to_owned()).unwrap().is_some());
+    drop(follower);
+
+    // 从节点停止期间的写入从日志补发
+    store.lock().unwrap().set("key3".to_owned(), "value3".to_owned())?;
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("key3".to_owned()).unwrap().is_some());
+    drop(follower);
+
+    // 错过的写入已经被 compaction 删除，从节点收到检查点
+    {
+        let mut store = store.lock().unwrap();
+        store.remove("key1".to_owned())?;
+        store.set("key2".to_owned(), "changed".to_owned())?;
+        store.compaction()?;
+    }
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("key2".to_owned()).unwrap() == Some("changed".to_owned()));
+    assert_eq!(follower.get("key1".to_owned())?, None);
+    assert_eq!(follower.get("key3".to_owned())?, Some("value3".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn replayed_merges_are_not_applied_twice() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    store.lock().unwrap().set("other".to_owned(), "x".to_owned())?;
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("other".to_owned()).unwrap().is_some());
+    let before = follower.status().applied.unwrap();
+    for _ in 0..3 {
+        store.lock().unwrap().incr("count".to_owned(), 1)?;
+    }
+    wait_for(|| follower.get("count".to_owned()).unwrap() == Some("3".to_owned()));
+    drop(follower);
+
+    // 模拟应用之后、保存位置之前崩溃：保存的位置落后，重新连接时补发三条合并记录
+    fs::write(follower_dir.path().join("replication.pos"), serde_json::to_vec(&before)?)?;
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.status().lag_bytes() == Some(0));
+    assert_eq!(follower.get("count".to_owned())?, Some("3".to_owned()));
+    Ok(())
+}
+
+// 从节点整体应用原子批次，读取时看不到半个批次
+#[test]
+fn follower_applies_batches_atomically() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    store.lock().unwrap().set("other".to_owned(), "x".to_owned())?;
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("other".to_owned()).unwrap().is_some());
+
+    let writer = thread::spawn(move || -> Result<()> {
+        for round in 0..20 {
+            let batch = (0..100).fold(WriteBatch::new(), |batch, i| {
+                batch.set(DEFAULT_FAMILY, format!("key{:03}", i), round.to_string())
+            });
+            store.lock().unwrap().write(batch)?;
+        }
+        Ok(())
+    });
+    loop {
+        let pairs = follower.scan("key")?;
+        assert!(pairs.is_empty() || pairs.len() == 100);
+        assert!(pairs.iter().all(|(_, value)| *value == pairs[0].1), "saw part of a batch");
+        if pairs.first().is_some_and(|(_, value)| value == "19") {
+            break;
+        }
+    }
+    writer.join().unwrap()
+}
+
+// 键的过期时间和值一起复制，从节点上的值按主节点的过期时间过期
+#[test]
+fn follower_expires_keys_with_ttl() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    {
+        let mut store = store.lock().unwrap();
+        store.create_family("sessions", FamilyOptions::new().ttl(Duration::from_millis(1000)))?;
+        store.set_with_ttl("token".to_owned(), "abc".to_owned(), Duration::from_millis(1000))?;
+        store.set_cf("sessions", "s1".to_owned(), "u1".to_owned())?;
+        store.set("other".to_owned(), "x".to_owned())?;
+    }
+
+    let mut follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get("other".to_owned()).unwrap().is_some());
+    assert_eq!(follower.get("token".to_owned())?, Some("abc".to_owned()));
+    assert_eq!(follower.get_cf("sessions", "s1".to_owned())?, Some("u1".to_owned()));
+
+    // 连接之后的写入通过变更记录复制
+    store.lock().unwrap().set_with_ttl("later".to_owned(), "def".to_owned(), Duration::from_millis(1000))?;
+    wait_for(|| follower.get("later".to_owned()).unwrap().is_some());
+
+    // 主节点没有再读这些键，不会写过期记录
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(follower.get("token".to_owned())?, None);
+    assert_eq!(follower.get("later".to_owned())?, None);
+    assert_eq!(follower.get_cf("sessions", "s1".to_owned())?, None);
+    assert_eq!(follower.get("other".to_owned())?, Some("x".to_owned()));
+    Ok(())
+}
+
+// 非默认列族中的写入和删除列族也被复制，检查点包括所有列族
+#[test]
+fn follower_replicates_column_families() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    {
+        let mut store = store.lock().unwrap();
+        store.create_family("users", FamilyOptions::new())?;
+        store.set_cf("users", "u1".to_owned(), "alice".to_owned())?;
+    }
+
+    let follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    let get = |family: &str, key: &str| follower.get_cf(family, key.to_owned()).ok().flatten();
+    wait_for(|| get("users", "u1") == Some("alice".to_owned()));
+
+    // 连接之后创建的列族带着配置复制到从节点
+    let sessions = FamilyOptions::new().compaction_threshold(4096).ttl(Duration::from_secs(3600));
+    {
+        let mut store = store.lock().unwrap();
+        store.create_family("sessions", sessions)?;
+        store.create_family("empty", sessions)?;
+        store.set_cf("sessions", "s1".to_owned(), "u1".to_owned())?;
+        store.set_cf("users", "u2".to_owned(), "bob".to_owned())?;
+        store.remove_cf("users", "u1".to_owned())?;
+    }
+    wait_for(|| get("users", "u2") == Some("bob".to_owned()));
+    assert_eq!(get("users", "u1"), None);
+    assert_eq!(get("sessions", "s1"), Some("u1".to_owned()));
+    assert_eq!(get("default", "u2"), None);
+
+    store.lock().unwrap().drop_family("sessions")?;
+    wait_for(|| matches!(follower.get_cf("sessions", "s1".to_owned()), Err(KvError::FamilyNotFound(_))));
+    drop(follower);
+    let families = KvStore::open(follower_dir.path())?.families();
+    assert!(families.contains(&("empty".to_owned(), sessions)));
+
+    // 从节点停止期间主节点 compaction，重启后从检查点恢复所有列族
+    {
+        let mut store = store.lock().unwrap();
+        store.set_cf("users", "u3".to_owned(), "carol".to_owned())?;
+        store.compaction()?;
+    }
+    let follower = Follower::start(follower_dir.path(), leader.local_addr())?;
+    wait_for(|| follower.get_cf("users", "u3".to_owned()).ok().flatten() == Some("carol".to_owned()));
+    assert_eq!(follower.get_cf("users", "u2".to_owned())?, Some("bob".to_owned()));
+    Ok(())
+}
+
+// 丢弃主节点后不再接受连接，已经连接的从节点断开
+#[test]
+fn leader_stops_when_dropped() -> Result<()> {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (store, leader) = start_leader(&leader_dir)?;
+    store.lock().unwrap().set("key1".to_owned(), "value1".to_owned())?;
+    let addr = leader.local_addr();
+    let mut follower = Follower::start(follower_dir.path(), addr)?;
+    wait_for(|| follower.get("key1".to_owned()).unwrap().is_some());
+    assert!(follower.status().connected);
+
+    drop(leader);
+    assert!(TcpStream::connect(addr).is_err());
+    wait_for(|| !follower.status().connected);
+    Ok(())
+}
+
+// 主从节点是本机上两个独立的 kvs-server 进程
+#[test]
+fn cli_leader_and_follower_processes() {
+    let leader_dir = TempDir::new().expect("unable to create temporary working directory");
+    let follower_dir = TempDir::new().expect("unable to create temporary working directory");
+    let (leader_addr, replication_addr, follower_addr) = (free_addr(), free_addr(), free_addr());
+
+    let _leader = Server(
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", &leader_addr.to_string()])
+            .args(["--replication-addr", &replication_addr.to_string()])
+            .current_dir(&leader_dir)
+            .spawn()
+            .unwrap(),
+    );
+    let _follower = Server(
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", &follower_addr.to_string()])
+            .args(["--follow", &replication_addr.to_string()])
+            .current_dir(&follower_dir)
+            .spawn()
+            .unwrap(),
+    );
+
+    wait_for(|| client(leader_addr, &["set", "key1", "value1"]).status.success());
+    wait_for(|| client(follower_addr, &["get", "key1"]).stdout == b"value1\n");
+    assert!(!client(follower_addr, &["set", "key2", "value2"]).status.success());
+}
@@ -0,0 +1,193 @@
+use assert_cmd::prelude::*;
+use kvs::{KvStore, MemoryStore, RespServer};
+use redis::{Commands, Connection, RedisResult};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+// 在空闲端口上用 RESP 提供内存存储，直到测试进程结束
+fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || RespServer::new(MemoryStore::new()).serve(listener));
+    addr
+}
+
+fn connect(addr: SocketAddr) -> Connection {
+    redis::Client::open(format!("redis://{}/", addr))
+        .unwrap()
+        .get_connection()
+        .unwrap()
+}
+
+#[test]
+fn resp_get_set_del_exists() -> RedisResult<()> {
+    let mut con = connect(start_server());
+    let pong: String = redis::cmd("PING").query(&mut con)?;
+    assert_eq!(pong, "PONG");
+
+    con.set::<_, _, ()>("key1", "value1")?;
+    con.set::<_, _, ()>("key2", "value2")?;
+    assert_eq!(con.get::<_, Option<String>>("key1")?, Some("value1".to_owned()));
+    assert_eq!(con.get::<_, Option<String>>("missing")?, None);
+    assert_eq!(con.exists::<_, i64>(&["key1", "key2", "missing"])?, 2);
+    assert_eq!(con.del::<_, i64>(&["key1", "missing"])?, 1);
+    assert_eq!(con.exists::<_, i64>("key1")?, 0);
+
+    let info: String = redis::cmd("INFO").query(&mut con)?;
+    assert!(info.contains("db0:keys=1"));
+    let unknown: RedisResult<String> = redis::cmd("FLUSHALL").query(&mut con);
+    assert!(unknown.is_err());
+    Ok(())
+}
+
+#[test]
+fn resp_set_options() -> RedisResult<()> {
+    let mut con = connect(start_server());
+    let set = |con: &mut Connection, args: &[&str]| -> RedisResult<Option<String>> {
+        redis::cmd("SET").arg(args).query(con)
+    };
+
+    assert_eq!(set(&mut con, &["key", "first", "NX"])?, Some("OK".to_owned()));
+    assert_eq!(set(&mut con, &["key", "second", "NX"])?, None);
+    assert_eq!(set(&mut con, &["other", "value", "XX"])?, None);
+    assert_eq!(set(&mut con, &["key", "third", "XX"])?, Some("OK".to_owned()));
+    assert_eq!(con.get::<_, String>("key")?, "third");
+    assert!(set(&mut con, &["key", "value", "EX", "0"]).is_err());
+    assert!(set(&mut con, &["key", "value", "NX", "XX"]).is_err());
+
+    // 过期的键消失，不带 TTL 的 set 清除过期时间
+    set(&mut con, &["short", "value", "PX", "100"])?;
+    set(&mut con, &["long", "value", "EX", "100"])?;
+    set(&mut con, &["cleared", "value", "PX", "100"])?;
+    set(&mut con, &["cleared", "value"])?;
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(con.get::<_, Option<String>>("short")?, None);
+    assert_eq!(con.get::<_, Option<String>>("long")?, Some("value".to_owned()));
+    assert_eq!(con.get::<_, Option<String>>("cleared")?, Some("value".to_owned()));
+    assert_eq!(set(&mut con, &["short", "again", "NX"])?, Some("OK".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn resp_keys_and_scan() -> RedisResult<()> {
+    let mut con = connect(start_server());
+    for i in 0..25 {
+        con.set::<_, _, ()>(format!("user:{:02}", i), i)?;
+    }
+    con.set::<_, _, ()>("order:1", "x")?;
+
+    let mut keys: Vec<String> = con.keys("user:1?")?;
+    keys.sort();
+    assert_eq!(keys.len(), 10);
+    assert_eq!(keys[0], "user:10");
+    assert_eq!(con.keys::<_, Vec<String>>("*")?.len(), 26);
+    assert_eq!(con.keys::<_, Vec<String>>("user:[01]5")?, vec!["user:05", "user:15"]);
+
+    let scanned: Vec<String> = con.scan_match("user:*")?.collect();
+    assert_eq!(scanned.len(), 25);
+    let all: Vec<String> = con.scan()?.collect();
+    assert_eq!(all.len(), 26);
+
+    // 多个 `*` 的模式在长键上也很快返回
+    con.set::<_, _, ()>("a".repeat(200), "x")?;
+    let started = Instant::now();
+    assert!(con.keys::<_, Vec<String>>("*a*a*a*a*a*a*a*a*b")?.is_empty());
+    assert_eq!(con.keys::<_, Vec<String>>("*a*a*a*a*a*a*a*a")?.len(), 1);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    Ok(())
+}
+
+// telnet 中输入的内联命令，以及格式错误的输入
+#[test]
+fn resp_inline_and_protocol_errors() {
+    let addr = start_server();
+    let stream = TcpStream::connect(addr).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut line = String::new();
+
+    writer.write_all(b"SET greeting hello\r\nGET greeting\r\n").unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "+OK\r\n");
+    line.clear();
+    reader.read_line(&mut line).unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert_eq!(line, "$5\r\nhello\r\n");
+
+    line.clear();
+    writer.write_all(b"*1\r\n+PING\r\n").unwrap();
+    reader.read_line(&mut line).unwrap();
+    assert!(line.starts_with("-ERR Protocol error"));
+}
+
+// 测试结束时结束服务进程
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// 启动 kvs-server 进程，返回进程和连接
+fn start_process(dir: &TempDir) -> (Server, Connection) {
+    let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let server = Server(
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", &addr.to_string(), "--protocol", "resp"])
+            .current_dir(dir)
+            .spawn()
+            .unwrap(),
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let con = loop {
+        match redis::Client::open(format!("redis://{}/", addr)).unwrap().get_connection() {
+            Ok(con) => break con,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            Err(e) => panic!("server did not start: {}", e),
+        }
+    };
+    (server, con)
+}
+
+#[test]
+fn cli_resp_server() -> kvs::Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let (server, mut con) = start_process(&dir);
+    let _: () = con.set("key1", "value1").unwrap();
+    let _: () = con.set("key2", "value2").unwrap();
+    let _: i64 = con.del("key2").unwrap();
+    drop(con);
+    drop(server);
+
+    // 数据保存在普通的 kvs 存储中
+    let mut store = KvStore::open(dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
+
+// 过期时间写在日志中，服务重启后键仍然会过期
+#[test]
+fn cli_resp_ttl_survives_restart() -> kvs::Result<()> {
+    let dir = TempDir::new().expect("unable to create temporary working directory");
+    let (server, mut con) = start_process(&dir);
+    let _: () = redis::cmd("SET").arg(&["temp", "value", "PX", "500"]).query(&mut con).unwrap();
+    let _: () = con.set("kept", "value").unwrap();
+    drop(con);
+    drop(server);
+
+    let (_server, mut con) = start_process(&dir);
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(con.get::<_, Option<String>>("temp").unwrap(), None);
+    assert_eq!(con.get::<_, Option<String>>("kept").unwrap(), Some("value".to_owned()));
+    assert_eq!(con.keys::<_, Vec<String>>("*").unwrap(), vec!["kept"]);
+    Ok(())
+}
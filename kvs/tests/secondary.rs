@@ -0,0 +1,86 @@
+use assert_cmd::prelude::*;
+use kvs::{IndexDef, KvError, KvStore, Result};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn user(email: &str, city: &str, age: u32) -> String {
+    format!(r#"{{"email":"{}","address":{{"city":"{}"}},"age":{}}}"#, email, city, age)
+}
+
+#[test]
+fn find_by_indexed_field() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user:1".to_owned(), user("a@example.com", "Paris", 30))?;
+    store.set("user:2".to_owned(), user("b@example.com", "Paris", 41))?;
+    store.set("note".to_owned(), "not json".to_owned())?;
+
+    // 创建索引时为已有的值建立索引
+    store.create_index("email", "email")?;
+    store.create_index("city", "address.city")?;
+    store.create_index("age", "age")?;
+    assert_eq!(store.find_by("email", "b@example.com")?, vec!["user:2"]);
+    assert_eq!(store.find_by("city", "Paris")?, vec!["user:1", "user:2"]);
+    assert_eq!(store.find_by("age", "30")?, vec!["user:1"]);
+
+    // 之后的写入同步更新索引
+    store.set("user:2".to_owned(), user("b@example.com", "Oslo", 41))?;
+    store.set("user:3".to_owned(), user("c@example.com", "Oslo", 30))?;
+    store.remove("user:1".to_owned())?;
+    assert_eq!(store.find_by("city", "Paris")?, Vec::<String>::new());
+    assert_eq!(store.find_by("city", "Oslo")?, vec!["user:2", "user:3"]);
+    assert_eq!(store.find_by("age", "30")?, vec!["user:3"]);
+    store.set("user:3".to_owned(), "plain text".to_owned())?;
+    assert_eq!(store.find_by("email", "c@example.com")?, Vec::<String>::new());
+    drop(store);
+
+    // 索引定义被保存，打开时重建索引项
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.indexes().len(), 3);
+    assert_eq!(store.find_by("city", "Oslo")?, vec!["user:2"]);
+    store.compaction()?;
+    assert_eq!(store.find_by("email", "b@example.com")?, vec!["user:2"]);
+    Ok(())
+}
+
+#[test]
+fn index_management_errors() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.create_index("email", "email")?;
+    assert!(matches!(store.create_index("email", "mail"), Err(KvError::IndexExists(_))));
+    assert!(matches!(store.create_index("bad", "a..b"), Err(KvError::InvalidIndexPath(_))));
+    assert!(matches!(store.find_by("missing", "x"), Err(KvError::IndexNotFound(_))));
+    assert_eq!(store.indexes(), vec![IndexDef::new("email", "email")?]);
+
+    store.drop_index("email")?;
+    assert!(matches!(store.drop_index("email"), Err(KvError::IndexNotFound(_))));
+    drop(store);
+    assert!(KvStore::open(temp_dir.path())?.indexes().is_empty());
+    Ok(())
+}
+
+#[test]
+fn cli_index() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let kvs = |args: &[&str]| {
+        let mut command = Command::cargo_bin("kvs").unwrap();
+        command.args(args).current_dir(&temp_dir);
+        command
+    };
+    kvs(&["set", "user:1", &user("a@example.com", "Paris", 30)]).assert().success();
+    kvs(&["index", "create", "city", "address.city"]).assert().success();
+    kvs(&["set", "user:2", &user("b@example.com", "Paris", 41)]).assert().success();
+    kvs(&["index", "list"]).assert().success().stdout("city address.city\n");
+    kvs(&["index", "find", "city", "Paris"]).assert().success().stdout("user:1\nuser:2\n");
+    kvs(&["index", "create", "city", "email"])
+        .assert()
+        .failure()
+        .stderr(contains("index city already exists"));
+    kvs(&["index", "drop", "city"]).assert().success();
+    kvs(&["index", "find", "city", "Paris"])
+        .assert()
+        .failure()
+        .stderr(contains("index city does not exist"));
+}
@@ -0,0 +1,121 @@
+use assert_cmd::prelude::*;
+use kvs::{HashRing, KvsServer, MemoryStore, Result, ShardedClient};
+use predicates::str::contains;
+use std::net::{SocketAddr, TcpListener};
+use std::process::Command;
+use std::thread;
+
+// 在空闲端口上提供内存存储，直到测试进程结束
+fn start_shard() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || KvsServer::new(MemoryStore::new()).serve(listener));
+    addr
+}
+
+#[test]
+fn hash_ring_is_stable_and_balanced() {
+    let shards: Vec<SocketAddr> = (0..4).map(|i| format!("10.0.0.{}:4000", i).parse().unwrap()).collect();
+    let mut ring = HashRing::new(160);
+    for &shard in &shards {
+        ring.add(shard);
+    }
+    let mut counts = vec![0; shards.len()];
+    for i in 0..4000 {
+        let shard = ring.shard_for(&format!("key{}", i)).unwrap();
+        counts[shards.iter().position(|&s| s == shard).unwrap()] += 1;
+    }
+    assert!(counts.iter().all(|&count| count > 500), "unbalanced ring: {:?}", counts);
+
+    // 移除分片只移动它负责的键
+    let mut smaller = ring.clone();
+    smaller.remove(shards[0]);
+    for i in 0..1000 {
+        let key = format!("key{}", i);
+        let before = ring.shard_for(&key).unwrap();
+        if before != shards[0] {
+            assert_eq!(smaller.shard_for(&key), Some(before));
+        }
+    }
+}
+
+#[test]
+fn sharded_client_get_set_remove() -> Result<()> {
+    let shards = vec![start_shard(), start_shard(), start_shard()];
+    let mut client = ShardedClient::connect(shards.clone())?;
+    for i in 0..100 {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    assert_eq!(client.get("key42".to_owned())?, Some("value42".to_owned()));
+    client.remove("key42".to_owned())?;
+    assert_eq!(client.get("key42".to_owned())?, None);
+    assert_eq!(client.scan("key")?.len(), 99);
+
+    // 键分布在每个分片上，每个键只在哈希环指定的分片中
+    for &shard in &shards {
+        let mut single = ShardedClient::connect(vec![shard])?;
+        let pairs = single.scan("")?;
+        assert!(!pairs.is_empty());
+        assert!(pairs.iter().all(|(key, _)| client.shard_for(key) == Some(shard)));
+    }
+    Ok(())
+}
+
+#[test]
+fn sharded_client_rebalances() -> Result<()> {
+    let shards = vec![start_shard(), start_shard()];
+    let mut client = ShardedClient::connect(shards.clone())?;
+    for i in 0..200 {
+        client.set(format!("key{}", i), format!("value{}", i))?;
+    }
+
+    let added = start_shard();
+    let moved = client.add_shard(added)?;
+    assert!(moved > 0 && moved < 200);
+    assert_eq!(ShardedClient::connect(vec![added])?.scan("")?.len(), moved);
+    assert_eq!(client.scan("")?.len(), 200);
+
+    // 使用新分片列表的客户端能找到所有的键
+    let mut fresh = ShardedClient::connect(vec![shards[0], shards[1], added])?;
+    for i in 0..200 {
+        assert_eq!(fresh.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+
+    client.remove_shard(shards[0])?;
+    assert!(ShardedClient::connect(vec![shards[0]])?.scan("")?.is_empty());
+    let mut fresh = ShardedClient::connect(vec![shards[1], added])?;
+    for i in 0..200 {
+        assert_eq!(fresh.get(format!("key{}", i))?, Some(format!("value{}", i)));
+    }
+    Ok(())
+}
+
+#[test]
+fn cli_sharded_client() {
+    let (first, second) = (start_shard(), start_shard());
+    let shards = format!("{},{}", first, second);
+    for i in 0..20 {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["set", &format!("key{}", i), "value", "--shards", &first.to_string()])
+            .assert()
+            .success();
+    }
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["add-shard", &second.to_string(), "--shards", &first.to_string()])
+        .assert()
+        .success()
+        .stdout(contains("Moved"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["scan", "key", "--shards", &shards])
+        .assert()
+        .success()
+        .stdout(contains("key19 value"));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["add-shard", &second.to_string()])
+        .assert()
+        .failure();
+}
@@ -0,0 +1,104 @@
+use assert_cmd::prelude::*;
+use kvs::{KvStore, MemoryStore, Result, Shell, ShellReply};
+use predicates::str::contains;
+use std::process::Command;
+use tempfile::TempDir;
+
+fn output(text: &str) -> ShellReply {
+    ShellReply::Output(text.to_owned())
+}
+
+#[test]
+fn shell_commands() -> Result<()> {
+    let mut shell = Shell::new(MemoryStore::new());
+    assert_eq!(shell.execute("set key1 value1")?, output(""));
+    assert_eq!(shell.execute("  get   key1  ")?, output("value1"));
+    assert_eq!(shell.execute("get key2")?, output("Key not found"));
+    assert_eq!(shell.execute("")?, output(""));
+    assert_eq!(shell.execute("# comment")?, output(""));
+
+    shell.execute("set key2 value2")?;
+    shell.execute("set other value3")?;
+    assert_eq!(shell.execute("scan key")?, output("key1 value1\nkey2 value2"));
+    assert_eq!(shell.execute("scan")?, output("key1 value1\nkey2 value2\nother value3"));
+
+    assert_eq!(shell.execute("rm key1")?, output(""));
+    assert_eq!(shell.execute("rm key1").unwrap_err().to_string(), "Key not found");
+    assert_eq!(shell.execute("exit")?, ShellReply::Exit);
+    Ok(())
+}
+
+#[test]
+fn shell_quoting() -> Result<()> {
+    let mut shell = Shell::new(MemoryStore::new());
+    shell.execute(r#"set "hello world" 'it is "quoted"'"#)?;
+    assert_eq!(shell.execute("get 'hello world'")?, output(r#"it is "quoted""#));
+    shell.execute(r#"set back\ slash "a\\b""#)?;
+    assert_eq!(shell.execute(r"get back\ slash")?, output(r"a\b"));
+
+    // scan 的输出可以直接作为参数粘贴回去
+    assert_eq!(
+        shell.execute("scan")?,
+        output(r#""back slash" "a\\b"
+"hello world" "it is \"quoted\"""#)
+    );
+    Ok(())
+}
+
+#[test]
+fn shell_errors() {
+    let mut shell = Shell::new(MemoryStore::new());
+    let error = |shell: &mut Shell<MemoryStore>, line| shell.execute(line).unwrap_err().to_string();
+    assert_eq!(error(&mut shell, "fetch key"), "unknown command 'fetch', try 'help'");
+    assert_eq!(error(&mut shell, "set key"), "wrong number of arguments for 'set'");
+    assert_eq!(error(&mut shell, "get 'key"), "unterminated single quote");
+    assert_eq!(error(&mut shell, "stats"), "stats is only available for the kvs engine");
+}
+
+#[test]
+fn shell_script() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut shell = Shell::new(KvStore::open(temp_dir.path())?).stats(KvStore::stats);
+    let mut out = Vec::new();
+    shell.run_script("set a 1\nset b 2\nget a\nstats\nexit\nget b\n".as_bytes(), &mut out)?;
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("1\nlive keys:        2\n"));
+    assert!(!out.ends_with("2\n"));
+
+    let error = shell.run_script("get a\nbogus\nset c 3\n".as_bytes(), Vec::new()).unwrap_err();
+    assert_eq!(error.to_string(), "line 2: unknown command 'bogus', try 'help'");
+    assert_eq!(shell.execute("get c")?, ShellReply::Output("Key not found".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn cli_shell_script() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["shell", "--script"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("set key1 value1\nset 'key 2' \"value 2\"\nget key1\nscan\n")
+        .assert()
+        .success()
+        .stdout("value1\n\"key 2\" \"value 2\"\nkey1 value1\n");
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["get", "key 2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value 2\n");
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["shell", "--script"])
+        .current_dir(&temp_dir)
+        .with_stdin()
+        .buffer("rm key1\nrm key1\n")
+        .assert()
+        .failure()
+        .stderr(contains("line 2: Key not found"));
+}
@@ -0,0 +1,96 @@
+use assert_cmd::prelude::*;
+use kvs::{FaultyFs, FileSystem, KvStore, Result, StoreOptions};
+use predicates::prelude::*;
+use predicates::str::contains;
+use std::path::Path;
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+fn store_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "longer value".to_owned())?;
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.get("key1".to_owned())?;
+
+    let stats = store.stats()?;
+    assert_eq!(stats.live_keys, 1);
+    assert_eq!(stats.avg_key_bytes, 4.0);
+    assert_eq!(stats.avg_value_bytes, 6.0);
+    assert!(stats.stale_bytes > 0);
+    assert_eq!(stats.total_bytes, stats.generations.iter().map(|g| g.bytes).sum::<u64>());
+    assert_eq!((stats.ops.sets, stats.ops.removes, stats.ops.gets), (3, 1, 1));
+    assert!(stats.last_compaction.is_none());
+
+    store.compaction()?;
+    let stats = store.stats()?;
+    assert_eq!(stats.stale_bytes, 0);
+    assert_eq!(stats.ops.compactions, 1);
+    assert!(stats.last_compaction.unwrap().reclaimed_bytes > 0);
+    drop(store);
+
+    // 重新打开之后计数和 compaction 信息仍然保留
+    let mut store = KvStore::open(temp_dir.path())?;
+    let stats = store.stats()?;
+    assert_eq!((stats.ops.sets, stats.ops.compactions), (3, 1));
+    assert!(stats.last_compaction.is_some());
+    Ok(())
+}
+
+#[test]
+fn cli_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["stats"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("live keys:        2").and(contains("last compaction:  never")));
+
+    let output = Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["stats", "--json"])
+        .current_dir(&temp_dir)
+        .output()
+        .unwrap();
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["live_keys"], 2);
+    assert_eq!(stats["ops"]["sets"], 2);
+    assert!(!stats["generations"].as_array().unwrap().is_empty());
+    Ok(())
+}
+
+#[test]
+fn close_saves_changed_stats() -> Result<()> {
+    let fs = FaultyFs::new();
+    let open = || KvStore::open_with("data", StoreOptions::new().file_system(fs.clone()));
+    let stats_file = Path::new("data/stats.json");
+    let mut store = open()?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.close()?;
+    assert!(fs.list_files(Path::new("data"))?.iter().any(|path| path == stats_file));
+
+    // close 返回保存失败的错误
+    let mut store = open()?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    fs.fail_writes_after(0);
+    assert!(store.close().is_err());
+    fs.heal();
+
+    // 计数没有变化时关闭不重写统计信息
+    let mut store = open()?;
+    assert_eq!((store.stats()?.ops.sets, store.stats()?.ops.gets), (1, 0));
+    fs.remove_file(stats_file)?;
+    drop(store);
+    assert!(!fs.list_files(Path::new("data"))?.iter().any(|path| path == stats_file));
+    Ok(())
+}
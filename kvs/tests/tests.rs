@@ -1,7 +1,10 @@
 use assert_cmd::prelude::*;
-use kvs::{KvStore, Result};
+#[cfg(feature = "sled")]
+use kvs::SledKvsEngine;
+use kvs::{KvError, KvStore, KvsEngine, Result};
 use predicates::ord::eq;
 use predicates::str::{contains, is_empty, PredicateStrExt};
+use std::path::Path;
 use std::process::Command;
 use tempfile::TempDir;
 use walkdir::WalkDir;
@@ -14,6 +17,7 @@ fn cli_no_args() {
 
 // `kvs -V` should print the version
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_version() {
     Command::cargo_bin("kvs")
         .unwrap()
@@ -24,6 +28,7 @@ fn cli_version() {
 
 // `kvs get <KEY>` should print "Key not found" for a non-existent key and exit with zero.
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_get_non_existent_key() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs")
@@ -37,6 +42,7 @@ fn cli_get_non_existent_key() {
 
 // `kvs rm <KEY>` should print "Key not found" for an empty database and exit with non-zero code.
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_rm_non_existent_key() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     Command::cargo_bin("kvs")
@@ -50,6 +56,7 @@ fn cli_rm_non_existent_key() {
 
 // `kvs set <KEY> <VALUE>` should print nothing and exit with zero.
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_set() {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
     Command::cargo_bin("kvs")
@@ -62,6 +69,7 @@ fn cli_set() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_get_stored() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
@@ -91,6 +99,7 @@ fn cli_get_stored() -> Result<()> {
 
 // `kvs rm <KEY>` should print nothing and exit with zero.
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_rm_stored() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
@@ -118,6 +127,7 @@ fn cli_rm_stored() -> Result<()> {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_invalid_get() {
     Command::cargo_bin("kvs")
         .unwrap()
@@ -133,6 +143,7 @@ fn cli_invalid_get() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_invalid_set() {
     Command::cargo_bin("kvs")
         .unwrap()
@@ -154,6 +165,7 @@ fn cli_invalid_set() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_invalid_rm() {
     Command::cargo_bin("kvs")
         .unwrap()
@@ -169,6 +181,7 @@ fn cli_invalid_rm() {
 }
 
 #[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
 fn cli_invalid_subcommand() {
     Command::cargo_bin("kvs")
         .unwrap()
@@ -177,11 +190,27 @@ fn cli_invalid_subcommand() {
         .failure();
 }
 
+// 下面的引擎测试对每个引擎都运行：总是运行 KvStore，启用 sled 特性时还运行 SledKvsEngine
+type Open<E> = fn(&Path) -> Result<E>;
+
+fn open_kvs(path: &Path) -> Result<KvStore> {
+    KvStore::open(path)
+}
+
+#[cfg(feature = "sled")]
+fn open_sled(path: &Path) -> Result<SledKvsEngine> {
+    SledKvsEngine::open(path)
+}
+
 // Should get previously stored value.
 #[test]
 fn get_stored_value() -> Result<()> {
+    get_stored_value_on(open_kvs)
+}
+
+fn get_stored_value_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
@@ -191,7 +220,7 @@ fn get_stored_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
 
@@ -201,8 +230,12 @@ fn get_stored_value() -> Result<()> {
 // Should overwrite existent value.
 #[test]
 fn overwrite_value() -> Result<()> {
+    overwrite_value_on(open_kvs)
+}
+
+fn overwrite_value_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
@@ -211,7 +244,7 @@ fn overwrite_value() -> Result<()> {
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
     store.set("key1".to_owned(), "value3".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
@@ -222,15 +255,19 @@ fn overwrite_value() -> Result<()> {
 // Should get `None` when getting a non-existent key.
 #[test]
 fn get_non_existent_value() -> Result<()> {
+    get_non_existent_value_on(open_kvs)
+}
+
+fn get_non_existent_value_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     // Open from disk again and check persistent data.
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     Ok(())
@@ -238,16 +275,24 @@ fn get_non_existent_value() -> Result<()> {
 
 #[test]
 fn remove_non_existent_key() -> Result<()> {
+    remove_non_existent_key_on(open_kvs)
+}
+
+fn remove_non_existent_key_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
-    assert!(store.remove("key1".to_owned()).is_err());
+    let mut store = open(temp_dir.path())?;
+    assert!(matches!(store.remove("key1".to_owned()), Err(KvError::KeyNotFound)));
     Ok(())
 }
 
 #[test]
 fn remove_key() -> Result<()> {
+    remove_key_on(open_kvs)
+}
+
+fn remove_key_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert!(store.remove("key1".to_owned()).is_ok());
     assert_eq!(store.get("key1".to_owned())?, None);
@@ -258,8 +303,12 @@ fn remove_key() -> Result<()> {
 // Test data correctness after compaction.
 #[test]
 fn compaction() -> Result<()> {
+    compaction_on(open_kvs)
+}
+
+fn compaction_on<E: KvsEngine>(open: Open<E>) -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let mut store = open(temp_dir.path())?;
 
     let dir_size = || {
         let entries = WalkDir::new(temp_dir.path()).into_iter();
@@ -289,7 +338,7 @@ fn compaction() -> Result<()> {
 
         drop(store);
         // reopen and check content.
-        let mut store = KvStore::open(temp_dir.path())?;
+        let mut store = open(temp_dir.path())?;
         for key_id in 0..1000 {
             let key = format!("key{}", key_id);
             assert_eq!(store.get(key)?, Some(format!("{}", iter)));
@@ -299,3 +348,60 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_get_stored_value() -> Result<()> {
+    get_stored_value_on(open_sled)
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_overwrite_value() -> Result<()> {
+    overwrite_value_on(open_sled)
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_get_non_existent_value() -> Result<()> {
+    get_non_existent_value_on(open_sled)
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_remove_non_existent_key() -> Result<()> {
+    remove_non_existent_key_on(open_sled)
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_remove_key() -> Result<()> {
+    remove_key_on(open_sled)
+}
+
+#[cfg(feature = "sled")]
+#[test]
+fn sled_compaction() -> Result<()> {
+    compaction_on(open_sled)
+}
+
+// `kvs --engine sled` 使用 sled 保存数据
+#[cfg(feature = "sled")]
+#[test]
+#[allow(clippy::needless_borrows_for_generic_args)]
+fn cli_sled_engine() {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(&["--engine", "sled", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(&["--engine", "sled", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(eq("value1").trim());
+}
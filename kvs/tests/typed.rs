@@ -0,0 +1,79 @@
+use kvs::{FamilyOptions, KvError, KvStore, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tempfile::TempDir;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct User {
+    name: String,
+    age: u32,
+    tags: Vec<String>,
+}
+
+fn user(name: &str, age: u32) -> User {
+    User {
+        name: name.to_owned(),
+        age,
+        tags: vec!["admin".to_owned()],
+    }
+}
+
+#[test]
+fn typed_values_round_trip() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set_typed("ann".to_owned(), &user("ann", 30))?;
+    let scores: BTreeMap<String, f64> = [("math".to_owned(), 9.5)].into_iter().collect();
+    store.set_typed("scores".to_owned(), &scores)?;
+    store.set_typed("unit".to_owned(), "plain string")?;
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_typed::<User>("ann".to_owned())?, Some(user("ann", 30)));
+    assert_eq!(store.get_typed::<BTreeMap<String, f64>>("scores".to_owned())?, Some(scores));
+    assert_eq!(store.get_typed::<String>("unit".to_owned())?, Some("plain string".to_owned()));
+    assert_eq!(store.get_typed::<User>("missing".to_owned())?, None);
+    // 类型化的值保存为 JSON，也可以作为字符串读取
+    assert_eq!(store.get("unit".to_owned())?, Some("\"plain string\"".to_owned()));
+
+    store.set("raw".to_owned(), "not json".to_owned())?;
+    match store.get_typed::<u32>("raw".to_owned()) {
+        Err(KvError::TypeMismatch { key, expected, .. }) => {
+            assert_eq!(key, "raw");
+            assert_eq!(expected, "u32");
+        }
+        other => panic!("expected a type mismatch, got {:?}", other),
+    }
+    assert!(matches!(store.get_typed::<u32>("ann".to_owned()), Err(KvError::TypeMismatch { .. })));
+    Ok(())
+}
+
+#[test]
+fn typed_tree_is_bound_to_a_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user;".to_owned(), "outside".to_owned())?;
+    let mut users = store.typed_tree::<User>("user:");
+    users.set("1", &user("ann", 30))?;
+    users.set("2", &user("bob", 41))?;
+    users.set("3", &user("cid", 25))?;
+    users.remove("3")?;
+    assert_eq!(users.prefix(), "user:");
+    assert_eq!(users.get("2")?, Some(user("bob", 41)));
+    assert_eq!(users.scan()?, [("1".to_owned(), user("ann", 30)), ("2".to_owned(), user("bob", 41))]);
+    assert!(matches!(users.remove("3"), Err(KvError::KeyNotFound)));
+    assert_eq!(store.get_typed::<User>("user:1".to_owned())?, Some(user("ann", 30)));
+
+    store.set("user:bad".to_owned(), "{}".to_owned())?;
+    let mut users = store.typed_tree::<User>("user:");
+    assert!(matches!(users.scan(), Err(KvError::TypeMismatch { key, .. }) if key == "user:bad"));
+    assert_eq!(users.clear()?, 3);
+    assert_eq!(store.scan("")?, [("user;".to_owned(), "outside".to_owned())]);
+
+    store.create_family("counts", FamilyOptions::new())?;
+    let mut counts = store.typed_tree_cf::<u64>("counts", "page:");
+    counts.set("home", &7)?;
+    assert_eq!(counts.get("home")?, Some(7));
+    assert_eq!(store.get_typed::<u64>("page:home".to_owned())?, None);
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use kvs::{
+    EncryptionKey, FaultyFs, FileSystem, KvStore, MemoryFs, MemoryStore, Result, StoreOptions, WriteBatch,
+    DEFAULT_FAMILY,
+};
+use std::path::Path;
+
+fn open(fs: &FaultyFs) -> Result<KvStore> {
+    KvStore::open_with("data", StoreOptions::new().file_system(fs.clone()))
+}
+
+fn get(store: &mut KvStore, key: &str) -> Option<String> {
+    store.get(key.to_owned()).unwrap()
+}
+
+#[test]
+fn store_on_memory_fs() -> Result<()> {
+    let fs = MemoryFs::new();
+    let options = StoreOptions::new().file_system(fs.clone());
+    let mut store = KvStore::open_with("memory/data", options.clone())?;
+    for i in 0..1000 {
+        store.set("key".to_owned(), format!("value{}", i))?;
+    }
+    store.set("other".to_owned(), "value".to_owned())?;
+    store.remove("other".to_owned())?;
+    store.compaction()?;
+    assert_eq!(store.stats()?.live_keys, 1);
+    drop(store);
+
+    // 没有写入磁盘
+    assert!(!Path::new("memory").exists());
+    assert!(fs.list_files(Path::new("memory/data"))?.len() >= 2);
+    let mut store = KvStore::open_with("memory/data", options)?;
+    assert_eq!(get(&mut store, "key"), Some("value999".to_owned()));
+    assert_eq!(get(&mut store, "other"), None);
+    Ok(())
+}
+
+#[test]
+fn memory_store_snapshot_on_memory_fs() -> Result<()> {
+    let fs = MemoryFs::new();
+    let options = StoreOptions::new().file_system(fs.clone());
+    let mut memory = MemoryStore::new();
+    memory.set("key".to_owned(), "value".to_owned())?;
+    memory.snapshot_to_with("snapshot", &options)?;
+    memory.snapshot_to_with("snapshot", &options)?;
+
+    assert!(!Path::new("snapshot").exists());
+    assert_eq!(fs.list_files(Path::new("snapshot"))?.len(), 1);
+    let mut loaded = MemoryStore::load_from_with("snapshot", options)?;
+    assert_eq!(loaded.get("key".to_owned())?, Some("value".to_owned()));
+    Ok(())
+}
+
+// 写入失败不影响之前写入的记录
+#[test]
+fn set_write_failure() -> Result<()> {
+    let fs = FaultyFs::new();
+    let mut store = open(&fs)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    fs.fail_writes_after(0);
+    assert!(store.set("key2".to_owned(), "value2".to_owned()).is_err());
+    drop(store);
+
+    fs.heal();
+    let mut store = open(&fs)?;
+    assert_eq!(get(&mut store, "key1"), Some("value1".to_owned()));
+    assert_eq!(get(&mut store, "key2"), None);
+    Ok(())
+}
+
+// 进程在写入中途退出，下次打开时忽略不完整的记录
+#[test]
+fn torn_write_is_ignored_on_open() -> Result<()> {
+    let fs = FaultyFs::new();
+    let mut store = open(&fs)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    fs.tear_write_after(0, 10);
+    assert!(store.remove("key1".to_owned()).is_err());
+    drop(store);
+    fs.heal();
+
+    let mut store = open(&fs)?;
+    assert_eq!(get(&mut store, "key1"), Some("value1".to_owned()));
+    assert_eq!(get(&mut store, "key2"), Some("value2".to_owned()));
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+    let mut store = open(&fs)?;
+    assert_eq!(get(&mut store, "key3"), Some("value3".to_owned()));
+
+    // repair 仍然丢弃不完整的记录
+    let options = StoreOptions::new().file_system(fs.clone());
+    let report = KvStore::repair("data", &options)?;
+    assert_eq!((report.live_keys, report.lost_bytes()), (3, 10));
+    Ok(())
+}
+
+// 加密日志中不完整的批次整个被丢弃
+#[test]
+fn torn_encrypted_batch_is_discarded() -> Result<()> {
+    let fs = FaultyFs::new();
+    let options = StoreOptions::new()
+        .file_system(fs.clone())
+        .encryption_key(EncryptionKey::new([9; 32]));
+    let mut store = KvStore::open_with("data", options.clone())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    fs.tear_write_after(0, 100);
+    let batch = WriteBatch::new().set(DEFAULT_FAMILY, "key2", "value2").remove(DEFAULT_FAMILY, "key1");
+    assert!(store.write(batch).is_err());
+    drop(store);
+    fs.heal();
+
+    let mut store = KvStore::open_with("data", options)?;
+    assert_eq!(get(&mut store, "key1"), Some("value1".to_owned()));
+    assert_eq!(get(&mut store, "key2"), None);
+    Ok(())
+}
+
+// 写入只 flush 不 sync，断电丢失上次 compaction 之后的所有写入，但总能恢复到那次 compaction 的状态
+#[test]
+fn crash_after_set_and_remove() -> Result<()> {
+    let fs = FaultyFs::new();
+    let mut store = open(&fs)?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.compaction()?;
+    store.set("key1".to_owned(), "changed".to_owned())?;
+    store.remove("key2".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    fs.crash();
+    drop(store);
+
+    let mut store = open(&fs)?;
+    assert_eq!(get(&mut store, "key1"), Some("value1".to_owned()));
+    assert_eq!(get(&mut store, "key2"), Some("value2".to_owned()));
+    assert_eq!(get(&mut store, "key3"), None);
+    Ok(())
+}
+
+// 新日志落盘之后 compaction 才删除旧日志
+#[test]
+fn crash_during_compaction() -> Result<()> {
+    let fs = FaultyFs::new();
+    let mut store = open(&fs)?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), format!("value{}", i))?;
+    }
+    store.compaction()?;
+    for i in 0..100 {
+        store.set(format!("key{}", i), "changed".to_owned())?;
+    }
+    fs.fail_writes_after(0);
+    assert!(store.compaction().is_err());
+    fs.crash();
+    drop(store);
+
+    let mut store = open(&fs)?;
+    for i in 0..100 {
+        assert_eq!(get(&mut store, &format!("key{}", i)), Some(format!("value{}", i)));
+    }
+
+    // 完成的 compaction 在崩溃后仍然有效
+    for i in 0..100 {
+        store.set(format!("key{}", i), "changed".to_owned())?;
+    }
+    store.compaction()?;
+    fs.crash();
+    drop(store);
+    let mut store = open(&fs)?;
+    assert_eq!(store.scan("key")?.len(), 100);
+    assert!(store.scan("key")?.iter().all(|(_, value)| value == "changed"));
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use kvs::{EncryptionKey, KvError, KvStore, MemoryFs, Result, StoreOptions};
+use std::fs::{self, OpenOptions};
+use std::io::Read;
+use tempfile::TempDir;
+
+fn small_threshold() -> StoreOptions {
+    StoreOptions::new().value_log_threshold(1024)
+}
+
+#[test]
+fn large_values_live_in_the_value_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open_with(temp_dir.path(), small_threshold())?;
+    let big = "x".repeat(100_000);
+    store.set("big".to_owned(), big.clone())?;
+    store.set("small".to_owned(), "value".to_owned())?;
+    for i in 0..20 {
+        store.set("small".to_owned(), format!("value{}", i))?;
+    }
+    store.compaction()?;
+
+    // compaction 复制的是位置，不是值
+    let stats = store.stats()?;
+    assert!(stats.total_bytes < 10_000);
+    assert_eq!(stats.value_logs.len(), 1);
+    assert!(stats.value_logs[0].bytes >= 100_000);
+    assert_eq!(store.get("big".to_owned())?, Some(big.clone()));
+
+    drop(store);
+    let mut store = KvStore::open_with(temp_dir.path(), small_threshold())?;
+    let mut reader = store.get_reader("big".to_owned())?.unwrap();
+    assert_eq!(reader.size(), 100_000);
+    let mut streamed = String::new();
+    reader.read_to_string(&mut streamed)?;
+    assert_eq!(streamed, big);
+    assert_eq!(store.history("big")?.last().unwrap().value, Some(big));
+    let mut small = String::new();
+    store.get_reader("small".to_owned())?.unwrap().read_to_string(&mut small)?;
+    assert_eq!(small, "value19");
+    assert!(store.get_reader("missing".to_owned())?.is_none());
+    Ok(())
+}
+
+// 截断的值日志中的值报告为损坏，不会返回不完整的值
+#[test]
+fn truncated_value_log_is_reported() -> Result<()> {
+    // 加密时截断在第一块的末尾，正好落在下一块的长度之前
+    let encrypted = small_threshold().encryption_key(EncryptionKey::new([5; 32]));
+    for (options, len) in [(small_threshold(), 50_000), (encrypted, 17 + 4 + 12 + 64 * 1024 + 16)] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut store = KvStore::open_with(temp_dir.path(), options.clone())?;
+        store.set("big".to_owned(), "x".repeat(100_000))?;
+        drop(store);
+
+        let vlog = fs::read_dir(temp_dir.path())?
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension() == Some("vlog".as_ref()))
+            .unwrap();
+        OpenOptions::new().write(true).open(&vlog)?.set_len(len)?;
+
+        let mut store = KvStore::open_with(temp_dir.path(), options)?;
+        assert!(matches!(store.get("big".to_owned()), Err(KvError::Corruption(_))));
+        let mut streamed = Vec::new();
+        let mut reader = store.get_reader("big".to_owned())?.unwrap();
+        assert!(reader.read_to_end(&mut streamed).is_err());
+    }
+    Ok(())
+}
+
+#[test]
+fn streams_binary_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let blob: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    store.set_reader("blob".to_owned(), blob.as_slice())?;
+    // 短的文本值留在主日志中
+    store.set_reader("text".to_owned(), "hello".as_bytes())?;
+    assert_eq!(store.stats()?.value_logs.len(), 1);
+
+    let mut read = Vec::new();
+    store.get_reader("blob".to_owned())?.unwrap().read_to_end(&mut read)?;
+    assert_eq!(read, blob);
+    assert!(matches!(store.get("blob".to_owned()), Err(KvError::BinaryValue(_))));
+    assert_eq!(store.get("text".to_owned())?, Some("hello".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn garbage_collection_reclaims_overwritten_values() -> Result<()> {
+    let fs = MemoryFs::new();
+    let key = EncryptionKey::new([7; 32]);
+    let options = small_threshold().file_system(fs.clone()).encryption_key(key);
+    let mut store = KvStore::open_with("data", options.clone())?;
+    for i in 0..10 {
+        store.set("big".to_owned(), i.to_string().repeat(200_000))?;
+    }
+    store.set("kept".to_owned(), "k".repeat(150_000))?;
+    store.remove("big".to_owned())?;
+    store.set("big".to_owned(), "z".repeat(200_000))?;
+    let before: u64 = store.stats()?.value_logs.iter().map(|value_log| value_log.bytes).sum();
+
+    let report = store.gc_value_log()?;
+    assert_eq!(report.removed_files, 1);
+    assert!(report.relocated_bytes >= 350_000);
+    let after: u64 = store.stats()?.value_logs.iter().map(|value_log| value_log.bytes).sum();
+    assert!(after < before / 4);
+    // 没有可以回收的了
+    assert_eq!(store.gc_value_log()?.removed_files, 0);
+
+    drop(store);
+    let mut store = KvStore::open_with("data", options)?;
+    assert_eq!(store.get("big".to_owned())?, Some("z".repeat(200_000)));
+    assert_eq!(store.get("kept".to_owned())?, Some("k".repeat(150_000)));
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use assert_cmd::prelude::*;
+use kvs::{Change, FamilyOptions, KvError, KvStore, Result, StoreOptions, WriteBatch, DEFAULT_FAMILY};
+use predicates::prelude::*;
+use predicates::str::contains;
+use std::fs::OpenOptions;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+// 订阅者收到前缀下每一次成功的写入
+#[test]
+fn watch_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("user:");
+
+    store.set("user:1".to_owned(), "alice".to_owned())?;
+    store.set("order:1".to_owned(), "book".to_owned())?;
+    store.remove("user:1".to_owned())?;
+    assert!(store.remove("user:1".to_owned()).is_err());
+    drop(store);
+
+    let changes: Vec<Change> = events.iter().map(|event| event.change).collect();
+    assert_eq!(
+        changes,
+        vec![
+            Change::Set {
+                key: "user:1".to_owned(),
+                value: "alice".to_owned(),
+                expires: None
+            },
+            Change::Remove {
+                key: "user:1".to_owned()
+            },
+        ]
+    );
+    Ok(())
+}
+
+// 重启的消费者从最后处理的事件的位置继续
+#[test]
+fn watch_resume_from_position() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch("");
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    let last_seen = events.recv().unwrap().position;
+    drop(events);
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    let events = store.watch_from("", last_seen)?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+    let keys: Vec<String> = events.iter().map(|event| event.change.key().to_owned()).collect();
+    assert_eq!(keys, vec!["key2", "key3"]);
+
+    // 被 compaction 删除的日志中的位置不能再恢复订阅
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.compaction()?;
+    assert!(matches!(
+        store.watch_from("", last_seen),
+        Err(KvError::PositionCompacted { .. })
+    ));
+    Ok(())
+}
+
+// `kvs watch --once` 打印已有的变更，`--from` 跳过已经看过的
+#[test]
+fn cli_watch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("user:1".to_owned(), "alice".to_owned())?;
+    store.set("order:1".to_owned(), "book".to_owned())?;
+    store.remove("user:1".to_owned())?;
+    drop(store);
+
+    let output = Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["watch", "user:", "--once"])
+        .current_dir(&temp_dir)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with(" set user:1 alice"));
+    assert!(lines[1].ends_with(" rm user:1"));
+
+    let first_position = lines[0].split(' ').next().unwrap();
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["watch", "user:", "--once", "--from", first_position])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("rm user:1").and(contains("set").not()));
+    Ok(())
+}
+
+#[test]
+fn expired_keys_are_recorded_as_changes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    let options = FamilyOptions::new().ttl(Duration::from_millis(200));
+    store.create_family("sessions", options)?;
+    store.set_cf("sessions", "s1".to_owned(), "token".to_owned())?;
+    store.set_cf("sessions", "s2".to_owned(), "token".to_owned())?;
+    thread::sleep(Duration::from_millis(300));
+    let changes = || -> Result<Vec<Change>> {
+        let events = KvStore::read_family_changes(temp_dir.path(), &StoreOptions::default(), "sessions", "", None)?;
+        Ok(events.into_iter().map(|event| event.change).collect())
+    };
+    assert_eq!(changes()?[0], Change::CreateFamily { options });
+
+    // 读取时第一次发现过期才写入过期记录
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    assert_eq!(store.get_cf("sessions", "s1".to_owned())?, None);
+    let expired = |key: &str| Change::Expire { key: key.to_owned() };
+    assert_eq!(changes()?.last(), Some(&expired("s1")));
+    assert_eq!(changes()?.iter().filter(|change| matches!(change, Change::Expire { .. })).count(), 1);
+
+    // compaction 丢掉的过期值也写入过期记录
+    store.compaction()?;
+    assert_eq!(changes()?, [expired("s2")]);
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_cf("sessions", "s2".to_owned())?, None);
+    assert_eq!(changes()?, [expired("s2")]);
+
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["--cf", "sessions", "watch", "", "--once"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("expire s2"));
+    Command::cargo_bin("kvs")
+        .unwrap()
+        .args(["--engine", "lsm", "watch", "", "--once"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("only supports the kvs engine"));
+    Ok(())
+}
+
+// 日志末尾不完整的批次和打开时一样被丢弃，订阅者看不到批次的前半部分
+#[test]
+fn torn_batch_is_not_replayed() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.write(WriteBatch::new().set(DEFAULT_FAMILY, "key2", "value2").set(DEFAULT_FAMILY, "key3", "value3"))?;
+    let end = store.position();
+    drop(store);
+    let keys = || -> Result<Vec<String>> {
+        let events = KvStore::read_changes(temp_dir.path(), &StoreOptions::default(), "", None)?;
+        Ok(events.into_iter().map(|event| event.change.key().to_owned()).collect())
+    };
+    assert_eq!(keys()?, ["key1", "key2", "key3"]);
+
+    // 在批次最后一条记录中间截断
+    let log = temp_dir.path().join(format!("{}.log", end.gen));
+    OpenOptions::new().write(true).open(&log)?.set_len(end.offset - 3)?;
+    assert_eq!(keys()?, ["key1"]);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, None);
+    Ok(())
+}
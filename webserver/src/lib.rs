@@ -1,4 +1,4 @@
-use std::{usize, thread, sync::{mpsc, Arc, Mutex}};
+use std::{thread, sync::{mpsc, Arc, Mutex}};
 
 
 //首先 Worker 结构体需要从线程池 TreadPool 的队列中获取待执行的代码
@@ -6,13 +6,14 @@ use std::{usize, thread, sync::{mpsc, Arc, Mutex}};
 
 pub struct ThreadPool{
     thread : Vec<Worker>,
-    sender: mpsc::Sender<Job>,
+    //Drop 时先关闭发送端，Worker 收不到任务后退出循环
+    sender: Option<mpsc::Sender<Job>>,
 }
 
 type Job = Box<dyn FnOnce()+Send+'static>;
 
 impl ThreadPool {
-    
+
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
@@ -20,7 +21,7 @@ impl ThreadPool {
         //将传过来func 封装成Job类型
         let job = Box::new(f);
         //作为消息队列生产者，发送消息
-        self.sender.send(job).unwrap();
+        self.sender.as_ref().unwrap().send(job).unwrap();
     }
     //ThreadPool constructed func
     pub fn new(size: usize) -> ThreadPool {
@@ -33,7 +34,21 @@ impl ThreadPool {
         for i in 0..size{
             threads.push(Worker::new(i,Arc::clone(&receiver)));
         }
-        ThreadPool { thread: threads ,sender:sender}
+        ThreadPool { thread: threads ,sender: Some(sender)}
+    }
+}
+
+///等待正在执行的任务完成后再退出
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in &mut self.thread {
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    eprintln!("worker {} panicked", worker.id);
+                }
+            }
+        }
     }
 }
 
@@ -42,16 +57,20 @@ impl ThreadPool {
 //接收端是Worker，它的内部线程将接收任务，然后进行处理。
 struct Worker{
     id : usize,
-    thread: thread::JoinHandle<Arc<Mutex<mpsc::Receiver<Job>>>>
+    thread: Option<thread::JoinHandle<()>>
 }
 
 impl Worker {
     //Worker constructed func
     fn new(id: usize,receiver:Arc<Mutex<mpsc::Receiver<Job>>>)->Worker{
         let thread = thread::spawn(move || loop {
-            let job = receiver.lock().unwrap().recv().unwrap();
-            job();
+            let job = receiver.lock().unwrap().recv();
+            match job {
+                Ok(job) => job(),
+                //线程池已经关闭
+                Err(_) => break,
+            }
         });
-        Worker { id: id, thread: thread }
+        Worker { id, thread: Some(thread) }
     }
-}
\ No newline at end of file
+}